@@ -1,3 +1,5 @@
+use sha2::{Digest, Sha256};
+
 fn main() {
     let version = std::process::Command::new("git")
         .args(["describe", "--tags", "--always"])
@@ -9,7 +11,15 @@ fn main() {
         .filter(|s| !s.is_empty())
         .unwrap_or_else(|| std::env::var("CARGO_PKG_VERSION").unwrap_or_else(|_| "unknown".to_string()));
 
+    // The undo script left by `vellum self uninstall` checks the bootstrap it
+    // downloads against the one this build was made from.
+    let bootstrap = std::fs::read("bootstrap.sh").expect("failed to read bootstrap.sh");
+    let bootstrap_sha256: String =
+        Sha256::digest(&bootstrap).iter().map(|b| format!("{b:02x}")).collect();
+
     println!("cargo:rustc-env=VELLUM_VERSION={}", version);
+    println!("cargo:rustc-env=VELLUM_BOOTSTRAP_SHA256={}", bootstrap_sha256);
+    println!("cargo:rerun-if-changed=bootstrap.sh");
     println!("cargo:rerun-if-changed=.git/HEAD");
     println!("cargo:rerun-if-changed=.git/refs/tags");
 }
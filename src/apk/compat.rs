@@ -1,23 +1,43 @@
 use std::collections::HashMap;
 
 use super::index::Package;
+use super::version::{compare_versions, version_lt};
 
 #[derive(Debug, Default)]
 pub struct CompatResult {
     pub compatible: Vec<String>,
     pub incompatible: Vec<String>,
+    /// The newest release supporting the target OS for each compatible
+    /// package, which may be older than the newest in the repo. For a package
+    /// only a renamed provider still supports, this is the provider's version.
+    pub compatible_versions: HashMap<String, String>,
+    /// Upgrade plan: the entries of `compatible_versions` that can be pinned
+    /// as `name=version`, leaving out provider-resolved packages.
+    pub plan: HashMap<String, String>,
+    /// Lowest OS version above the target that some release of each
+    /// incompatible package supports, if any.
+    pub incompatible_nearest: HashMap<String, Option<String>>,
 }
 
 pub fn check_os_compatibility(
     target_os: &str,
     installed_pkgs: &[String],
     index: &[Package],
+    target_arch: Option<&str>,
 ) -> CompatResult {
     let mut result = CompatResult::default();
 
+    // A package renamed and re-published as a provider of its old name still
+    // counts as a candidate for that name.
     let mut pkg_versions: HashMap<&str, Vec<&Package>> = HashMap::new();
     for pkg in index {
         pkg_versions.entry(&pkg.name).or_default().push(pkg);
+        for provided in &pkg.provides {
+            let name = provided.split('=').next().unwrap_or(provided);
+            if name != pkg.name {
+                pkg_versions.entry(name).or_default().push(pkg);
+            }
+        }
     }
 
     for installed in installed_pkgs {
@@ -26,27 +46,67 @@ pub fn check_os_compatibility(
             None => continue,
         };
 
-        let has_os = versions.iter().any(|v| {
-            let (min, max) = v.get_os_constraints();
-            min.is_some() || max.is_some()
-        });
+        // A package whose builds are all for another architecture can't be
+        // reinstalled on this device whatever its OS constraints say.
+        let versions: Vec<&Package> = versions
+            .iter()
+            .copied()
+            .filter(|v| builds_for_arch(v, target_arch))
+            .collect();
+        if versions.is_empty() {
+            result.incompatible.push(installed.clone());
+            result.incompatible_nearest.insert(installed.clone(), None);
+            continue;
+        }
 
-        if !has_os {
+        if !versions.iter().any(|v| v.has_os_constraint()) {
             continue;
         }
 
-        let has_compatible = versions.iter().any(|v| v.is_compatible_with_os(target_os));
+        let best = versions
+            .iter()
+            .filter(|v| v.is_compatible_with_os(target_os))
+            .max_by(|a, b| a.version.cmp(&b.version));
 
-        if has_compatible {
+        if let Some(best) = best {
             result.compatible.push(installed.clone());
+            result.compatible_versions.insert(installed.clone(), best.version.to_string());
+            // A provider can't be pinned as `installed=version`; apk resolves
+            // the replacement itself.
+            if best.name == *installed {
+                result.plan.insert(installed.clone(), best.version.to_string());
+            }
         } else {
             result.incompatible.push(installed.clone());
+            result
+                .incompatible_nearest
+                .insert(installed.clone(), nearest_compatible_os(target_os, &versions));
         }
     }
 
+    result.compatible.sort();
+    result.incompatible.sort();
     result
 }
 
+// Index entries without an architecture are assumed to build for any.
+fn builds_for_arch(pkg: &Package, target_arch: Option<&str>) -> bool {
+    match (target_arch, pkg.arch.as_deref()) {
+        (Some(target), Some(arch)) => arch == target || arch == "noarch",
+        _ => true,
+    }
+}
+
+fn nearest_compatible_os(target_os: &str, versions: &[&Package]) -> Option<String> {
+    versions
+        .iter()
+        .filter_map(|v| {
+            let (min, _) = v.get_os_constraints();
+            min.filter(|m| version_lt(target_os, m) && v.is_compatible_with_os(m))
+        })
+        .min_by(|a, b| compare_versions(a, b))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,11 +114,28 @@ mod tests {
     fn make_package(name: &str, version: &str, depends: Vec<&str>) -> Package {
         Package {
             name: name.to_string(),
-            version: version.to_string(),
+            version: version.into(),
             depends: depends.into_iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
         }
     }
 
+    #[test]
+    fn results_sorted_by_name() {
+        let index = vec![
+            make_package("zeta", "1.0", vec!["remarkable-os>=4.0.0.0"]),
+            make_package("mu", "1.0", vec!["remarkable-os>=3.0.0.0"]),
+            make_package("kappa", "1.0", vec!["remarkable-os>=4.0.0.0"]),
+            make_package("alpha", "1.0", vec!["remarkable-os>=3.0.0.0"]),
+        ];
+        let installed: Vec<String> = index.iter().map(|p| p.name.clone()).collect();
+
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
+
+        assert_eq!(result.compatible, vec!["alpha", "mu"]);
+        assert_eq!(result.incompatible, vec!["kappa", "zeta"]);
+    }
+
     #[test]
     fn all_packages_compatible() {
         let index = vec![
@@ -67,7 +144,7 @@ mod tests {
         ];
         let installed = vec!["pkg1".to_string(), "pkg2".to_string()];
 
-        let result = check_os_compatibility("3.10.0.0", &installed, &index);
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
 
         assert_eq!(result.compatible, vec!["pkg1", "pkg2"]);
         assert!(result.incompatible.is_empty());
@@ -81,7 +158,7 @@ mod tests {
         ];
         let installed = vec!["pkg1".to_string(), "pkg2".to_string()];
 
-        let result = check_os_compatibility("3.10.0.0", &installed, &index);
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
 
         assert_eq!(result.compatible, vec!["pkg1"]);
         assert_eq!(result.incompatible, vec!["pkg2"]);
@@ -94,7 +171,7 @@ mod tests {
         ];
         let installed = vec!["pkg1".to_string(), "unknown-pkg".to_string()];
 
-        let result = check_os_compatibility("3.10.0.0", &installed, &index);
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
 
         assert_eq!(result.compatible, vec!["pkg1"]);
         assert!(result.incompatible.is_empty());
@@ -108,7 +185,7 @@ mod tests {
         ];
         let installed = vec!["pkg1".to_string(), "pkg2".to_string()];
 
-        let result = check_os_compatibility("3.10.0.0", &installed, &index);
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
 
         assert_eq!(result.compatible, vec!["pkg2"]);
         assert!(result.incompatible.is_empty());
@@ -122,7 +199,7 @@ mod tests {
         ];
         let installed = vec!["pkg1".to_string()];
 
-        let result = check_os_compatibility("3.10.0.0", &installed, &index);
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
 
         assert_eq!(result.compatible, vec!["pkg1"]);
         assert!(result.incompatible.is_empty());
@@ -136,7 +213,7 @@ mod tests {
         ];
         let installed = vec!["pkg1".to_string()];
 
-        let result = check_os_compatibility("4.0.0.0", &installed, &index);
+        let result = check_os_compatibility("4.0.0.0", &installed, &index, None);
 
         assert!(result.compatible.is_empty());
         assert_eq!(result.incompatible, vec!["pkg1"]);
@@ -149,7 +226,7 @@ mod tests {
         ];
         let installed: Vec<String> = vec![];
 
-        let result = check_os_compatibility("3.10.0.0", &installed, &index);
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
 
         assert!(result.compatible.is_empty());
         assert!(result.incompatible.is_empty());
@@ -160,9 +237,146 @@ mod tests {
         let index: Vec<Package> = vec![];
         let installed = vec!["pkg1".to_string()];
 
-        let result = check_os_compatibility("3.10.0.0", &installed, &index);
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
+
+        assert!(result.compatible.is_empty());
+        assert!(result.incompatible.is_empty());
+    }
+
+    #[test]
+    fn plan_picks_newest_compatible() {
+        let index = vec![
+            make_package("pkg1", "1.0", vec!["remarkable-os>=3.0.0.0"]),
+            make_package("pkg1", "1.5", vec!["remarkable-os>=3.0.0.0"]),
+            make_package("pkg1", "2.0", vec!["remarkable-os>=4.0.0.0"]),
+        ];
+        let installed = vec!["pkg1".to_string()];
+
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
+
+        assert_eq!(result.compatible_versions.get("pkg1"), Some(&"1.5".to_string()));
+        assert_eq!(result.plan, result.compatible_versions);
+        assert!(result.incompatible_nearest.is_empty());
+    }
+
+    #[test]
+    fn plan_covers_each_package_with_multiple_compatible_versions() {
+        let index = vec![
+            make_package("pkg1", "1.0-r0", vec!["remarkable-os>=3.0.0.0"]),
+            make_package("pkg1", "1.1-r0", vec!["remarkable-os>=3.0.0.0", "remarkable-os<4.0.0.0"]),
+            make_package("pkg1", "1.2-r0", vec!["remarkable-os>=4.0.0.0"]),
+            make_package("pkg2", "0.9-r1", vec!["remarkable-os>=3.0.0.0"]),
+            make_package("pkg2", "0.10-r0", vec!["remarkable-os>=3.5.0.0"]),
+        ];
+        let installed = vec!["pkg1".to_string(), "pkg2".to_string()];
+
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
+
+        assert_eq!(result.plan.len(), 2);
+        assert_eq!(result.plan.get("pkg1"), Some(&"1.1-r0".to_string()));
+        assert_eq!(result.plan.get("pkg2"), Some(&"0.10-r0".to_string()));
+    }
+
+    #[test]
+    fn provider_constraints_count_for_provided_name() {
+        let mut renamed = make_package("new-pkg", "2.0", vec!["remarkable-os>=3.10.0.0"]);
+        renamed.provides = vec!["old-pkg=2.0".to_string()];
+        let index = vec![
+            make_package("old-pkg", "1.0", vec!["remarkable-os<3.10.0.0"]),
+            renamed,
+        ];
+        let installed = vec!["old-pkg".to_string()];
+
+        let result = check_os_compatibility("3.11.0.0", &installed, &index, None);
+
+        assert_eq!(result.compatible, vec!["old-pkg"]);
+        assert!(result.incompatible.is_empty());
+        assert_eq!(result.compatible_versions.get("old-pkg"), Some(&"2.0".to_string()));
+        assert!(result.plan.is_empty());
+    }
+
+    #[test]
+    fn plan_excludes_incompatible_packages() {
+        let index = vec![
+            make_package("pkg1", "1.0", vec!["remarkable-os>=3.0.0.0"]),
+            make_package("pkg2", "1.0", vec!["remarkable-os>=4.0.0.0"]),
+        ];
+        let installed = vec!["pkg1".to_string(), "pkg2".to_string()];
+
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
+
+        assert!(result.plan.contains_key("pkg1"));
+        assert!(!result.plan.contains_key("pkg2"));
+        assert!(!result.compatible_versions.contains_key("pkg2"));
+    }
+
+    #[test]
+    fn incompatible_nearest_is_lowest_future_os() {
+        let index = vec![
+            make_package("pkg1", "1.0", vec!["remarkable-os>=3.0.0.0", "remarkable-os<3.5.0.0"]),
+            make_package("pkg1", "2.0", vec!["remarkable-os>=4.2.0.0"]),
+            make_package("pkg1", "3.0", vec!["remarkable-os>=4.0.0.0"]),
+        ];
+        let installed = vec!["pkg1".to_string()];
+
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
+
+        assert_eq!(result.incompatible, vec!["pkg1"]);
+        assert_eq!(
+            result.incompatible_nearest.get("pkg1"),
+            Some(&Some("4.0.0.0".to_string()))
+        );
+        assert!(result.plan.is_empty());
+    }
+
+    #[test]
+    fn incompatible_nearest_none_when_only_older_os_supported() {
+        let index = vec![
+            make_package("pkg1", "1.0", vec!["remarkable-os>=3.0.0.0", "remarkable-os<3.5.0.0"]),
+        ];
+        let installed = vec!["pkg1".to_string()];
+
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
+
+        assert_eq!(result.incompatible_nearest.get("pkg1"), Some(&None));
+    }
+
+    fn with_arch(mut pkg: Package, arch: &str) -> Package {
+        pkg.arch = Some(arch.to_string());
+        pkg
+    }
+
+    #[test]
+    fn arch_filter_ignores_other_architectures() {
+        let index = vec![
+            with_arch(make_package("pkg1", "1.0", vec!["remarkable-os>=3.0.0.0"]), "aarch64"),
+            with_arch(make_package("pkg1", "2.0", vec!["remarkable-os>=3.0.0.0"]), "armv7"),
+            with_arch(make_package("pkg2", "1.0", vec!["remarkable-os>=3.0.0.0"]), "noarch"),
+        ];
+        let installed = vec!["pkg1".to_string(), "pkg2".to_string()];
 
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, Some("aarch64"));
+
+        assert_eq!(result.compatible, vec!["pkg1", "pkg2"]);
+        assert_eq!(result.plan.get("pkg1").map(String::as_str), Some("1.0"));
+        assert_eq!(result.plan.get("pkg2").map(String::as_str), Some("1.0"));
+    }
+
+    #[test]
+    fn arch_filter_marks_other_architecture_only_incompatible() {
+        let index = vec![
+            with_arch(make_package("pkg1", "1.0", vec!["remarkable-os>=3.0.0.0"]), "armv7"),
+            with_arch(make_package("pkg2", "1.0", vec!["other-dep"]), "armv7"),
+        ];
+        let installed = vec!["pkg1".to_string(), "pkg2".to_string()];
+
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, Some("aarch64"));
         assert!(result.compatible.is_empty());
+        assert_eq!(result.incompatible, vec!["pkg1", "pkg2"]);
+        assert_eq!(result.incompatible_nearest.get("pkg1"), Some(&None));
+
+        let result = check_os_compatibility("3.10.0.0", &installed, &index, None);
+        assert_eq!(result.compatible, vec!["pkg1"]);
         assert!(result.incompatible.is_empty());
     }
 }
@@ -0,0 +1,148 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyOp {
+    Gte,
+    Gt,
+    Lt,
+    Lte,
+    Eq,
+    /// `~`: the same version up to the length given, e.g. `foo~1.2` matches `1.2.5`.
+    Fuzzy,
+}
+
+/// One entry of a package's dependencies, such as `remarkable-os>=3.10.0.0`
+/// or `foo@testing`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    /// `!name`: the package conflicts with `name` rather than needing it.
+    pub conflict: bool,
+    pub operator: Option<DependencyOp>,
+    pub version: Option<String>,
+    pub tag: Option<String>,
+}
+
+impl Dependency {
+    /// The version this dependency puts a bound on when it is `name op version`.
+    pub fn constraint(&self) -> Option<(DependencyOp, &str)> {
+        Some((self.operator?, self.version.as_deref()?))
+    }
+}
+
+// apk writes the repository tag after the name (`foo@testing>=1.0`), while
+// vellum's world entries put it after the version (`foo=1.0@testing`); both
+// are accepted.
+impl FromStr for Dependency {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (conflict, rest) = match s.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (name, constraint) = match rest.find(['<', '>', '=', '~']) {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        let (name, mut tag) = split_tag(name);
+        if name.is_empty() {
+            return Err(anyhow!("invalid dependency '{s}': missing package name"));
+        }
+
+        let (operator, version) = if constraint.is_empty() {
+            (None, None)
+        } else {
+            let (op, rest) = [
+                (">=", DependencyOp::Gte),
+                ("<=", DependencyOp::Lte),
+                ("~=", DependencyOp::Fuzzy),
+                (">", DependencyOp::Gt),
+                ("<", DependencyOp::Lt),
+                ("=", DependencyOp::Eq),
+                ("~", DependencyOp::Fuzzy),
+            ]
+            .iter()
+            .find_map(|(prefix, op)| constraint.strip_prefix(prefix).map(|rest| (*op, rest)))
+            .ok_or_else(|| anyhow!("invalid dependency '{s}': unsupported operator"))?;
+
+            let (version, version_tag) = split_tag(rest);
+            if version.is_empty() {
+                return Err(anyhow!("invalid dependency '{s}': missing version"));
+            }
+            tag = tag.or(version_tag);
+            (Some(op), Some(version.to_string()))
+        };
+
+        Ok(Dependency { name: name.to_string(), conflict, operator, version, tag })
+    }
+}
+
+fn split_tag(s: &str) -> (&str, Option<String>) {
+    match s.split_once('@') {
+        Some((before, tag)) => (before, Some(tag.to_string())),
+        None => (s, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_operators() {
+        let dep: Dependency = "remarkable-os>=3.10.0.0".parse().unwrap();
+        assert_eq!(dep.name, "remarkable-os");
+        assert_eq!(dep.constraint(), Some((DependencyOp::Gte, "3.10.0.0")));
+        assert_eq!(dep.tag, None);
+
+        let cases = [
+            ("a<1", DependencyOp::Lt),
+            ("a<=1", DependencyOp::Lte),
+            ("a>1", DependencyOp::Gt),
+            ("a=1", DependencyOp::Eq),
+            ("a~1", DependencyOp::Fuzzy),
+            ("a~=1", DependencyOp::Fuzzy),
+        ];
+        for (s, op) in cases {
+            assert_eq!(s.parse::<Dependency>().unwrap().constraint(), Some((op, "1")));
+        }
+    }
+
+    #[test]
+    fn parses_bare_names_and_tags() {
+        let dep: Dependency = "so:libc.musl-aarch64.so.1".parse().unwrap();
+        assert_eq!(dep.name, "so:libc.musl-aarch64.so.1");
+        assert_eq!(dep.operator, None);
+        assert_eq!(dep.version, None);
+
+        let dep: Dependency = "foo@testing>=1.0".parse().unwrap();
+        assert_eq!(dep.name, "foo");
+        assert_eq!(dep.tag.as_deref(), Some("testing"));
+        assert_eq!(dep.version.as_deref(), Some("1.0"));
+
+        let dep: Dependency = "foo=1.0-r0@testing".parse().unwrap();
+        assert_eq!(dep.tag.as_deref(), Some("testing"));
+        assert_eq!(dep.version.as_deref(), Some("1.0-r0"));
+    }
+
+    #[test]
+    fn parses_conflicts() {
+        let dep: Dependency = "!foo<2.0".parse().unwrap();
+        assert!(dep.conflict);
+        assert_eq!(dep.name, "foo");
+        assert_eq!(dep.constraint(), Some((DependencyOp::Lt, "2.0")));
+        assert!(!"foo".parse::<Dependency>().unwrap().conflict);
+    }
+
+    #[test]
+    fn rejects_malformed_dependencies() {
+        assert!("".parse::<Dependency>().is_err());
+        assert!(">=1.0".parse::<Dependency>().is_err());
+        assert!("foo>=".parse::<Dependency>().is_err());
+        assert!("!".parse::<Dependency>().is_err());
+    }
+}
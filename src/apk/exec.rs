@@ -1,21 +1,46 @@
+use std::env;
+use std::io::Read;
 use std::os::unix::process::CommandExt;
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 
+use super::dependency::Dependency;
+
+use crate::util::terminal_columns;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Default)]
+pub struct ApkOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub code: i32,
+}
+
 pub struct Apk {
     root: PathBuf,
+    timeout: Option<Duration>,
 }
 
 impl Apk {
     pub fn new(vellum_root: &str) -> Self {
         Self {
             root: PathBuf::from(vellum_root),
+            timeout: None,
         }
     }
 
-    fn bin_path(&self) -> PathBuf {
+    pub fn with_timeout(mut self, d: Duration) -> Self {
+        self.timeout = Some(d);
+        self
+    }
+
+    pub fn bin_path(&self) -> PathBuf {
         self.root.join("bin").join("apk.vellum")
     }
 
@@ -29,35 +54,74 @@ impl Apk {
         ]
     }
 
-    pub fn run(&self, args: &[&str]) -> Result<()> {
+    fn command(&self, args: &[&str]) -> Command {
         let mut cmd_args = self.base_args();
         cmd_args.extend(args.iter().map(|s| s.to_string()));
 
-        let status = Command::new(self.bin_path())
-            .args(&cmd_args)
-            .env("APK_CONFIG", self.root.join("etc").join("apk").join("config"))
-            .status()?;
+        // apk sizes its package lists and progress bar from the terminal.
+        // Shells rarely export COLUMNS, so it is filled in from the tty when
+        // missing; TERM and the rest are inherited from our own environment.
+        let mut cmd = Command::new(self.bin_path());
+        cmd.args(&cmd_args).env("APK_CONFIG", self.root.join("etc").join("apk").join("config"));
+        if env::var_os("COLUMNS").is_none() {
+            if let Some(columns) = terminal_columns() {
+                cmd.env("COLUMNS", columns.to_string());
+            }
+        }
+        cmd
+    }
 
-        if status.success() {
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!(
-                "apk exited with code {}",
-                status.code().unwrap_or(-1)
-            ))
+    // Waits for the child, killing it if it outlives the configured timeout.
+    fn wait_with_timeout(&self, child: &mut Child) -> Result<ExitStatus> {
+        let Some(timeout) = self.timeout else {
+            return Ok(child.wait()?);
+        };
+
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            if start.elapsed() >= timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow::anyhow!(
+                    "apk timed out after {}s",
+                    timeout.as_secs_f32()
+                ));
+            }
+            thread::sleep(POLL_INTERVAL);
         }
     }
 
-    pub fn run_silent(&self, args: &[&str]) -> Result<()> {
-        let mut cmd_args = self.base_args();
-        cmd_args.extend(args.iter().map(|s| s.to_string()));
+    fn run_with_timeout(&self, cmd: &mut Command) -> Result<ExitStatus> {
+        let mut child = cmd.spawn()?;
+        self.wait_with_timeout(&mut child)
+    }
+
+    // Like `Command::output`, but honoring the timeout. The pipes are drained on
+    // separate threads so a chatty child can't block on a full pipe buffer.
+    fn output_with_timeout(&self, cmd: &mut Command) -> Result<Output> {
+        let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let stdout_reader = thread::spawn(move || read_pipe(stdout));
+        let stderr_reader = thread::spawn(move || read_pipe(stderr));
+
+        let status = self.wait_with_timeout(&mut child)?;
 
-        let status = Command::new(self.bin_path())
-            .args(&cmd_args)
-            .env("APK_CONFIG", self.root.join("etc").join("apk").join("config"))
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()?;
+        Ok(Output {
+            status,
+            stdout: stdout_reader.join().unwrap_or_default(),
+            stderr: stderr_reader.join().unwrap_or_default(),
+        })
+    }
+
+    // `run` and `run_silent` don't capture output: apk's progress needs to reach
+    // the terminal as it happens.
+    fn run_status(&self, cmd: &mut Command) -> Result<()> {
+        let status = self.run_with_timeout(cmd)?;
 
         if status.success() {
             Ok(())
@@ -69,45 +133,115 @@ impl Apk {
         }
     }
 
+    pub fn run(&self, args: &[&str]) -> Result<()> {
+        self.run_status(&mut self.command(args))
+    }
+
+    /// Runs apk, retrying with exponential back-off (100ms, 200ms, 400ms, ...)
+    /// while it exits with code 1, which is what lock contention and transient
+    /// network failures produce.
+    pub fn run_with_retry(&self, args: &[&str], max_attempts: u32) -> Result<()> {
+        let mut delay = RETRY_BASE_DELAY;
+        let mut attempt = 1;
+
+        loop {
+            let status = self.run_with_timeout(&mut self.command(args))?;
+            if status.success() {
+                return Ok(());
+            }
+
+            let code = status.code().unwrap_or(-1);
+            if code != 1 || attempt >= max_attempts {
+                return Err(anyhow::anyhow!("apk exited with code {code}"));
+            }
+
+            eprintln!("apk failed, retrying in {}ms...", delay.as_millis());
+            thread::sleep(delay);
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+
+    pub fn run_silent(&self, args: &[&str]) -> Result<()> {
+        self.run_status(
+            self.command(args)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null()),
+        )
+    }
+
+    pub fn run_capture(&self, args: &[&str]) -> Result<ApkOutput> {
+        let output = self.output_with_timeout(&mut self.command(args))?;
+
+        Ok(ApkOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            code: output.status.code().unwrap_or(-1),
+        })
+    }
+
     pub fn output(&self, args: &[&str]) -> Result<String> {
-        let mut cmd_args = self.base_args();
-        cmd_args.extend(args.iter().map(|s| s.to_string()));
+        Ok(self.run_capture(args)?.stdout)
+    }
 
-        let output = Command::new(self.bin_path())
-            .args(&cmd_args)
-            .env("APK_CONFIG", self.root.join("etc").join("apk").join("config"))
-            .output()?;
+    pub fn output_with_stderr(&self, args: &[&str]) -> Result<(String, String)> {
+        let out = self.run_capture(args)?;
 
-        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        if out.code == 0 {
+            Ok((out.stdout, out.stderr))
+        } else if out.stderr.is_empty() {
+            Err(anyhow::anyhow!("apk exited with code {}", out.code))
+        } else {
+            Err(anyhow::anyhow!(
+                "apk exited with code {}: {}",
+                out.code,
+                out.stderr
+            ))
+        }
     }
 
     pub fn exec(&self, args: &[&str]) -> Result<()> {
-        let mut cmd_args = self.base_args();
-        cmd_args.extend(args.iter().map(|s| s.to_string()));
-
-        let err = Command::new(self.bin_path())
-            .args(&cmd_args)
-            .env("APK_CONFIG", self.root.join("etc").join("apk").join("config"))
-            .exec();
+        let err = self.command(args).exec();
 
         Err(anyhow::anyhow!("exec failed: {err}"))
     }
 
     pub fn list_installed(&self) -> Result<Vec<String>> {
-        let out = self.output(&["info", "-q"])?;
-        if out.is_empty() {
-            return Ok(Vec::new());
-        }
-        Ok(out.lines().map(|s| s.to_string()).collect())
+        let installed = self.list_installed_with_versions()?;
+        Ok(installed.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// Installed packages with their full `version-rN`, from a single
+    /// `apk list -I`.
+    pub fn list_installed_with_versions(&self) -> Result<Vec<(String, String)>> {
+        let out = self.output(&["list", "-I"])?;
+        Ok(out.lines().filter_map(parse_list_line).collect())
+    }
+
+    pub fn get_dependencies(&self, pkg: &str) -> Result<Vec<Dependency>> {
+        let (out, stderr) = self.output_with_stderr(&["info", "-R", pkg])?;
+        warn_stderr(&stderr);
+        Ok(parse_dependencies(&out))
     }
 
-    pub fn get_dependencies(&self, pkg: &str) -> Result<Vec<String>> {
-        let out = self.output(&["info", "-R", pkg])?;
-        Ok(out.lines().map(|s| s.to_string()).collect())
+    /// Names of installed packages that depend on `pkg` (`apk info -r`).
+    pub fn get_reverse_dependencies(&self, pkg: &str) -> Result<Vec<String>> {
+        let (out, stderr) = self.output_with_stderr(&["info", "-r", pkg])?;
+        warn_stderr(&stderr);
+        Ok(parse_reverse_dependencies(&out))
+    }
+
+    /// The description of `pkg` from `apk info -d`, or `None` if apk
+    /// doesn't know the package or it has no description.
+    pub fn get_package_description(&self, pkg: &str) -> Result<Option<String>> {
+        let (out, stderr) = self.output_with_stderr(&["info", "-d", pkg])?;
+        warn_stderr(&stderr);
+        Ok(parse_description(&out))
     }
 
     pub fn get_package_version(&self, pkg: &str) -> Result<Option<String>> {
-        let out = self.output(&["list", "-I", pkg])?;
+        let (out, stderr) = self.output_with_stderr(&["list", "-I", pkg])?;
+        warn_stderr(&stderr);
         if out.is_empty() {
             return Ok(None);
         }
@@ -128,4 +262,240 @@ impl Apk {
     pub fn cache_purge(&self) -> Result<()> {
         self.run_silent(&["cache", "purge"])
     }
+
+    /// Downloads packages into `etc/apk/cache` without installing them, so a
+    /// later `add` or `upgrade` can run offline.
+    pub fn fetch(&self, packages: &[&str]) -> Result<()> {
+        let cache_dir = self.root.join("etc").join("apk").join("cache");
+        std::fs::create_dir_all(&cache_dir)?;
+        let cache_dir = cache_dir.to_string_lossy();
+
+        let mut args = vec!["fetch", "-o", &cache_dir];
+        args.extend_from_slice(packages);
+        self.run(&args)
+    }
+}
+
+fn warn_stderr(stderr: &str) {
+    for line in stderr.lines() {
+        eprintln!("warning: apk: {line}");
+    }
+}
+
+// `apk info -R` prints a `<pkg>-<version> depends on:` header followed by
+// one dependency per line. Lines that don't parse are skipped, as in
+// `Package::dependencies`, rather than losing the whole list.
+fn parse_dependencies(out: &str) -> Vec<Dependency> {
+    out.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.ends_with(':'))
+        .filter_map(|l| l.parse().ok())
+        .collect()
+}
+
+// `apk info -r` prints a `<pkg>-<version> is required by:` header followed by
+// one `name-version-rN` per line.
+fn parse_reverse_dependencies(out: &str) -> Vec<String> {
+    out.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.ends_with(':'))
+        .map(|l| strip_version(l).to_string())
+        .collect()
+}
+
+// `apk info -d` prints a `<pkg>-<version> description:` header followed by
+// the description and a blank line.
+fn parse_description(out: &str) -> Option<String> {
+    out.lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.ends_with("description:"))
+        .map(str::to_string)
+}
+
+/// Splits the leading `name-version-rN` field of an `apk list` line.
+pub fn parse_list_line(line: &str) -> Option<(String, String)> {
+    let field = line.split_whitespace().next()?;
+    let mut parts = field.rsplitn(3, '-');
+    let release = parts.next()?;
+    let version = parts.next()?;
+    let name = parts.next()?;
+    Some((name.to_string(), format!("{version}-{release}")))
+}
+
+// Turns `name-1.0-r0` into `name`; anything else is returned as-is.
+fn strip_version(field: &str) -> &str {
+    let mut parts = field.rsplitn(3, '-');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(rel), Some(_), Some(name))
+            if rel.strip_prefix('r').is_some_and(|n| n.chars().all(|c| c.is_ascii_digit())) =>
+        {
+            name
+        }
+        _ => field,
+    }
+}
+
+fn read_pipe<R: Read>(pipe: Option<R>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(mut pipe) = pipe {
+        let _ = pipe.read_to_end(&mut buf);
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apk::dependency::DependencyOp;
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn fake_apk(script: &str) -> TempDir {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("bin")).unwrap();
+        let bin = root.path().join("bin").join("apk.vellum");
+        fs::write(&bin, script).unwrap();
+        fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+        root
+    }
+
+    #[test]
+    fn timeout_kills_hung_process() {
+        let tmp = fake_apk("#!/bin/sh\nsleep 5\n");
+        let root = tmp.path();
+        let apk = Apk::new(root.to_str().unwrap()).with_timeout(Duration::from_millis(200));
+
+        let start = Instant::now();
+        let err = apk.run(&["fetch"]).unwrap_err();
+
+        assert!(err.to_string().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn command_keeps_environment() {
+        let tmp = fake_apk("#!/bin/sh\necho \"$APK_CONFIG\"\necho \"$PATH\"\n");
+        let root = tmp.path();
+        let apk = Apk::new(root.to_str().unwrap());
+
+        let out = apk.output(&["info"]).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(lines[0], root.join("etc/apk/config").to_str().unwrap());
+        assert_eq!(lines[1], env::var("PATH").unwrap());
+    }
+
+    #[test]
+    fn output_within_timeout_succeeds() {
+        let tmp = fake_apk("#!/bin/sh\necho hello\necho oops >&2\n");
+        let root = tmp.path();
+        let apk = Apk::new(root.to_str().unwrap()).with_timeout(Duration::from_secs(5));
+
+        let (stdout, stderr) = apk.output_with_stderr(&["info"]).unwrap();
+
+        assert_eq!(stdout, "hello");
+        assert_eq!(stderr, "oops");
+    }
+
+    #[test]
+    fn run_with_retry_recovers_from_transient_failure() {
+        let tmp = fake_apk("");
+        let root = tmp.path();
+        let counter = root.join("attempts");
+        let script = format!(
+            "#!/bin/sh\necho x >> {0}\n[ $(wc -l < {0}) -ge 3 ] || exit 1\n",
+            counter.display()
+        );
+        fs::write(root.join("bin").join("apk.vellum"), script).unwrap();
+        let apk = Apk::new(root.to_str().unwrap());
+
+        apk.run_with_retry(&["add"], 3).unwrap();
+
+        assert_eq!(fs::read_to_string(&counter).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn run_with_retry_does_not_retry_other_codes() {
+        let tmp = fake_apk("");
+        let root = tmp.path();
+        let counter = root.join("attempts");
+        let script = format!("#!/bin/sh\necho x >> {}\nexit 2\n", counter.display());
+        fs::write(root.join("bin").join("apk.vellum"), script).unwrap();
+        let apk = Apk::new(root.to_str().unwrap());
+
+        assert!(apk.run_with_retry(&["add"], 3).is_err());
+
+        assert_eq!(fs::read_to_string(&counter).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn parse_dependencies_skips_header() {
+        let out = "foo-1.0-r0 depends on:\nremarkable-os>=3.10\nso:libc.musl-aarch64.so.1\n\n";
+        let deps = parse_dependencies(out);
+        assert_eq!(deps.len(), 2);
+        assert_eq!(deps[0].name, "remarkable-os");
+        assert_eq!(deps[0].version.as_deref(), Some("3.10"));
+        assert_eq!(deps[1].name, "so:libc.musl-aarch64.so.1");
+    }
+
+    #[test]
+    fn parse_dependencies_handles_fuzzy_and_conflicts() {
+        let out = "py3-rmapi-0.0.25-r0 depends on:\n\
+                   python3~3.11\n\
+                   remarkable-os>=3.10\n\
+                   !rmapi-legacy\n\
+                   foo>=\n";
+        let deps = parse_dependencies(out);
+        assert_eq!(deps.len(), 3);
+        assert_eq!(deps[0].constraint(), Some((DependencyOp::Fuzzy, "3.11")));
+        assert_eq!(deps[1].name, "remarkable-os");
+        assert!(deps[2].conflict);
+        assert_eq!(deps[2].name, "rmapi-legacy");
+    }
+
+    #[test]
+    fn parse_reverse_dependencies_skips_header() {
+        let out = "libfoo-1.2-r0 is required by:\nfoo-app-2.0-r1\nbar-0.3-r0\n\n";
+        assert_eq!(parse_reverse_dependencies(out), vec!["foo-app", "bar"]);
+        assert!(parse_reverse_dependencies("libfoo-1.2-r0 is required by:\n").is_empty());
+    }
+
+    #[test]
+    fn parse_list_line_splits_name_and_version() {
+        let line = "py3-foo-bar-1.2.3-r4 aarch64 {py3-foo-bar} (MIT) [installed]";
+        assert_eq!(
+            parse_list_line(line),
+            Some(("py3-foo-bar".to_string(), "1.2.3-r4".to_string()))
+        );
+        assert_eq!(parse_list_line("foo"), None);
+        assert_eq!(parse_list_line(""), None);
+    }
+
+    #[test]
+    fn parse_description_skips_header() {
+        let out = "foo-1.0-r0 description:\nDraws things on the screen\n\n";
+        assert_eq!(parse_description(out).as_deref(), Some("Draws things on the screen"));
+        assert_eq!(parse_description("foo-1.0-r0 description:\n\n"), None);
+        assert_eq!(parse_description(""), None);
+    }
+
+    #[test]
+    fn get_reverse_dependencies_reads_apk_output() {
+        let tmp =
+            fake_apk("#!/bin/sh\necho 'libfoo-1.2-r0 is required by:'\necho 'foo-app-2.0-r1'\n");
+        let root = tmp.path();
+        let apk = Apk::new(root.to_str().unwrap());
+
+        assert_eq!(apk.get_reverse_dependencies("libfoo").unwrap(), vec!["foo-app"]);
+    }
+
+    #[test]
+    fn strip_version_removes_version_and_release() {
+        assert_eq!(strip_version("foo-1.0-r0"), "foo");
+        assert_eq!(strip_version("foo-bar-2.3.4-r12"), "foo-bar");
+        assert_eq!(strip_version("foo"), "foo");
+        assert_eq!(strip_version("foo-bar"), "foo-bar");
+    }
 }
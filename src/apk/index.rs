@@ -1,35 +1,115 @@
-use std::fs::File;
+use std::collections::HashSet;
+use std::fs::{self, File};
 use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, Result};
 use flate2::bufread::MultiGzDecoder;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
 use tar::Archive;
 
-use super::version::{compare_versions, version_gte, version_lt};
+use super::dependency::{Dependency, DependencyOp};
+use super::version::{version_gte, version_lte, Version};
+use crate::util::http_agent;
+
+const REMOTE_INDEX_PREFIX: &str = "remote-index-";
+const REMOTE_INDEX_SUFFIX: &str = ".tar.gz";
+const REMOTE_ETAG_SUFFIX: &str = ".etag";
+const REMOTE_LM_SUFFIX: &str = ".lm";
+const TRUNCATED_INDEX: &str = "APKINDEX file appears truncated; try running 'apk update' or \
+     'vellum upgrade --force-remote'";
 
 #[derive(Debug, Clone, Default)]
 pub struct Package {
     pub name: String,
-    pub version: String,
+    pub version: Version,
     pub depends: Vec<String>,
+    pub provides: Vec<String>,
+    pub arch: Option<String>,
+    pub size: Option<u64>,
+    pub installed_size: Option<u64>,
+    pub description: Option<String>,
+    pub license: Option<String>,
+    pub maintainer: Option<String>,
+    pub build_time: Option<u64>,
+    pub commit: Option<String>,
+    /// Base URL of the repository the package was fetched from, when known.
+    pub repo: Option<String>,
+    /// Tag of the repository the package was fetched from, such as `testing`.
+    /// apk only installs these when asked for with `name@tag`.
+    pub tag: Option<String>,
+}
+
+/// One line of the repositories file: a base URL, optionally preceded by a
+/// tag as in `@testing https://packages.vellum.delivery/testing`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repository {
+    pub url: String,
+    /// The tag without its `@`.
+    pub tag: Option<String>,
+}
+
+impl Repository {
+    pub fn untagged(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            tag: None,
+        }
+    }
+
+    /// Parses a repositories file line, or `None` for blank lines and
+    /// comments.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let Some(tagged) = line.strip_prefix('@') else {
+            return Some(Self::untagged(line));
+        };
+        let (tag, url) = tagged.split_once(char::is_whitespace)?;
+        Some(Self {
+            url: url.trim().to_string(),
+            tag: Some(tag.to_string()),
+        })
+    }
+
+    /// The name apk gives its cached copy of this repository's index: the
+    /// first four bytes of the SHA-1 of the URL, as written in the file.
+    pub fn apk_cache_name(&self) -> String {
+        let key: String =
+            Sha1::digest(self.url.as_bytes()).iter().take(4).map(|b| format!("{b:02x}")).collect();
+        format!("APKINDEX.{key}.tar.gz")
+    }
 }
 
 impl Package {
+    /// Parsed `depends`; entries that don't parse are skipped.
+    pub fn dependencies(&self) -> impl Iterator<Item = Dependency> + '_ {
+        self.depends.iter().filter_map(|d| d.parse().ok())
+    }
+
     pub fn get_os_constraints(&self) -> (Option<String>, Option<String>) {
         let mut min_ver = None;
         let mut max_ver = None;
 
-        for dep in &self.depends {
-            if let Some(v) = dep.strip_prefix("remarkable-os>=") {
-                min_ver = Some(v.to_string());
-            } else if let Some(v) = dep.strip_prefix("remarkable-os<") {
-                max_ver = Some(v.to_string());
+        for dep in self.dependencies().filter(|d| d.name == "remarkable-os" && !d.conflict) {
+            match dep.constraint() {
+                Some((DependencyOp::Gte, v)) => min_ver = Some(v.to_string()),
+                Some((DependencyOp::Lt, v)) => max_ver = Some(v.to_string()),
+                _ => {}
             }
         }
 
         (min_ver, max_ver)
     }
 
+    pub fn has_os_constraint(&self) -> bool {
+        let (min_ver, max_ver) = self.get_os_constraints();
+        min_ver.is_some() || max_ver.is_some()
+    }
+
     pub fn is_compatible_with_os(&self, os_version: &str) -> bool {
         let (min_ver, max_ver) = self.get_os_constraints();
 
@@ -44,7 +124,7 @@ impl Package {
         }
 
         if let Some(ref max) = max_ver {
-            if !version_lt(os_version, max) {
+            if version_lte(max, os_version) {
                 return false;
             }
         }
@@ -58,19 +138,182 @@ pub fn parse_index_tar_gz(path: &str) -> Result<Vec<Package>> {
     parse_index_from_tar_gz(f)
 }
 
-pub fn fetch_remote_index(repo_url: &str, arch: &str) -> Result<Vec<Package>> {
-    let url = format!("{}/{}/APKINDEX.tar.gz", repo_url.trim_end_matches('/'), arch);
+/// Fetches the repository's APKINDEX. With a `cache_dir`, the last download is
+/// kept there along with its ETag/Last-Modified headers, and later fetches are
+/// conditional so an unchanged index isn't downloaded again.
+pub fn fetch_remote_index(
+    repo_url: &str,
+    arch: &str,
+    cache_dir: Option<&str>,
+) -> Result<Vec<Package>> {
+    let repo_url = repo_url.trim_end_matches('/');
+    let url = format!("{repo_url}/{arch}/APKINDEX.tar.gz");
+
+    let mut req = http_agent(&url)?.get(&url);
+    if let Some(dir) = cache_dir {
+        let dir = Path::new(dir);
+        if remote_cache_path(dir, &url, REMOTE_INDEX_SUFFIX).exists() {
+            if let Ok(etag) = fs::read_to_string(remote_cache_path(dir, &url, REMOTE_ETAG_SUFFIX)) {
+                req = req.set("If-None-Match", etag.trim());
+            }
+            if let Ok(lm) = fs::read_to_string(remote_cache_path(dir, &url, REMOTE_LM_SUFFIX)) {
+                req = req.set("If-Modified-Since", lm.trim());
+            }
+        }
+    }
+
+    let resp = req.call().map_err(|e| anyhow!("HTTP request failed: {e}"))?;
 
-    let resp = ureq::get(&url).call().map_err(|e| anyhow!("HTTP request failed: {e}"))?;
+    if resp.status() == 304 {
+        if let Some(dir) = cache_dir {
+            let path = remote_cache_path(Path::new(dir), &url, REMOTE_INDEX_SUFFIX);
+            return Ok(with_repo(parse_index_from_tar_gz(File::open(path)?)?, repo_url));
+        }
+    }
 
     if resp.status() != 200 {
         return Err(anyhow!("HTTP {}", resp.status()));
     }
 
+    let etag = resp.header("ETag").map(|s| s.to_string());
+    let last_modified = resp.header("Last-Modified").map(|s| s.to_string());
+
     let mut data = Vec::new();
     resp.into_reader().read_to_end(&mut data)?;
 
-    parse_index_from_tar_gz(Cursor::new(data))
+    let packages = parse_index_from_tar_gz(Cursor::new(&data))?;
+
+    if let Some(dir) = cache_dir {
+        if let Err(e) = store_remote_index(Path::new(dir), &url, &data, etag, last_modified) {
+            eprintln!("warning: failed to cache package index: {e}");
+        }
+    }
+
+    Ok(with_repo(packages, repo_url))
+}
+
+fn with_repo(mut packages: Vec<Package>, repo_url: &str) -> Vec<Package> {
+    for pkg in &mut packages {
+        pkg.repo = Some(repo_url.to_string());
+    }
+    packages
+}
+
+/// Fetches every repository and merges their indexes, as apk does with the
+/// repositories file, so custom and tagged repositories are searched
+/// alongside the default one. A repository that fails is skipped with a
+/// warning as long as another one answers, which lets mirrors back each other
+/// up. If every repository fails, the error lists each URL with its reason.
+/// Returns the merged index along with the first untagged URL that answered.
+pub fn fetch_index_with_mirrors(
+    repos: &[Repository],
+    arch: &str,
+    cache_dir: Option<&str>,
+) -> Result<(Vec<Package>, Option<String>)> {
+    if repos.is_empty() {
+        return Err(anyhow!("no repository URLs configured"));
+    }
+
+    let results = repos.iter().map(|repo| (repo, fetch_remote_index(&repo.url, arch, cache_dir)));
+    merge_indexes(results.collect())
+}
+
+fn merge_indexes(
+    results: Vec<(&Repository, Result<Vec<Package>>)>,
+) -> Result<(Vec<Package>, Option<String>)> {
+    let mut failures = Vec::new();
+    let mut indexes = Vec::new();
+    let mut working_url = None;
+    for (repo, result) in results {
+        match result {
+            Ok(packages) => {
+                if repo.tag.is_none() {
+                    working_url.get_or_insert_with(|| repo.url.clone());
+                }
+                indexes.push(with_tag(packages, repo.tag.as_deref()));
+            }
+            Err(e) => failures.push(format!("{}: {e}", repo.url)),
+        }
+    }
+
+    if indexes.is_empty() {
+        return Err(anyhow!("all repositories failed:\n  {}", failures.join("\n  ")));
+    }
+    for failure in failures {
+        eprintln!("warning: skipping repository {failure}");
+    }
+    Ok((merge_tagged(indexes), working_url))
+}
+
+pub fn with_tag(mut packages: Vec<Package>, tag: Option<&str>) -> Vec<Package> {
+    for pkg in &mut packages {
+        pkg.tag = tag.map(str::to_string);
+    }
+    packages
+}
+
+/// Merges indexes, untagged ones first, so a release that a tagged
+/// repository also carries is kept as the untagged copy.
+pub fn merge_tagged(mut indexes: Vec<Vec<Package>>) -> Vec<Package> {
+    indexes.sort_by_key(|index| index.first().is_some_and(|p| p.tag.is_some()));
+    dedup_packages(indexes.into_iter().flatten())
+}
+
+/// Drops repeated `name-version` entries, such as the same package served by
+/// two mirrors, keeping the first.
+fn dedup_packages(packages: impl IntoIterator<Item = Package>) -> Vec<Package> {
+    let mut seen = HashSet::new();
+    packages
+        .into_iter()
+        .filter(|p| seen.insert((p.name.clone(), p.version.to_string())))
+        .collect()
+}
+
+/// Forgets the cached validators of every repository so the next fetch
+/// downloads each index unconditionally.
+pub fn clear_remote_index_cache(cache_dir: &str) {
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(REMOTE_INDEX_PREFIX)
+            && (name.ends_with(REMOTE_ETAG_SUFFIX) || name.ends_with(REMOTE_LM_SUFFIX))
+        {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+// Each index URL gets its own cached copy and validators, so one mirror's
+// ETag is never sent to another and a 304 can't return a different repo.
+fn remote_cache_path(dir: &Path, url: &str, suffix: &str) -> PathBuf {
+    let key: String =
+        Sha256::digest(url.as_bytes()).iter().take(8).map(|b| format!("{b:02x}")).collect();
+    dir.join(format!("{REMOTE_INDEX_PREFIX}{key}{suffix}"))
+}
+
+fn store_remote_index(
+    dir: &Path,
+    url: &str,
+    data: &[u8],
+    etag: Option<String>,
+    last_modified: Option<String>,
+) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(remote_cache_path(dir, url, REMOTE_INDEX_SUFFIX), data)?;
+
+    for (suffix, value) in [(REMOTE_ETAG_SUFFIX, etag), (REMOTE_LM_SUFFIX, last_modified)] {
+        let path = remote_cache_path(dir, url, suffix);
+        match value {
+            Some(v) => fs::write(path, v)?,
+            None => {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+    Ok(())
 }
 
 pub fn find_best_compatible_version<'a>(
@@ -83,7 +326,14 @@ pub fn find_best_compatible_version<'a>(
         .filter(|p| p.name == pkg_name && p.is_compatible_with_os(os_version))
         .collect();
 
-    compatible.sort_by(|a, b| compare_versions(&b.version, &a.version));
+    // A release from an untagged repository wins over any tagged one; the
+    // user asks for tagged builds explicitly with `@testing`.
+    compatible.sort_by(|a, b| {
+        a.tag
+            .is_some()
+            .cmp(&b.tag.is_some())
+            .then_with(|| b.version.cmp(&a.version))
+    });
 
     compatible.first().copied()
 }
@@ -100,23 +350,34 @@ fn parse_index_from_tar_gz<R: Read>(reader: R) -> Result<Vec<Package>> {
     let gz = MultiGzDecoder::new(Cursor::new(data));
     let mut archive = Archive::new(gz);
 
-    for entry in archive.entries()? {
-        let mut entry = entry?;
-        let path = entry.path()?;
-
-        if path.to_string_lossy() == "APKINDEX" {
-            let mut content = String::new();
-            entry.read_to_string(&mut content)?;
-            return parse_apkindex(BufReader::new(content.as_bytes()));
+    // A download cut short leaves a gzip stream or tar entry that ends early,
+    // which surfaces as an unexpected EOF somewhere in here.
+    let content = (|| -> std::io::Result<Option<String>> {
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.path()?.to_string_lossy() == "APKINDEX" {
+                let mut content = String::new();
+                entry.read_to_string(&mut content)?;
+                return Ok(Some(content));
+            }
         }
+        Ok(None)
+    })()
+    .map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => anyhow!("{TRUNCATED_INDEX} ({e})"),
+        _ => anyhow!("failed to read APKINDEX archive: {e}"),
+    })?;
+
+    match content {
+        Some(content) => parse_apkindex(BufReader::new(content.as_bytes())),
+        None => Err(anyhow!("APKINDEX not found in archive")),
     }
-
-    Err(anyhow!("APKINDEX not found in archive"))
 }
 
 fn parse_apkindex<R: BufRead>(reader: R) -> Result<Vec<Package>> {
     let mut packages = Vec::new();
     let mut current = Package::default();
+    let mut last_key = None;
 
     for line in reader.lines() {
         let line = line?;
@@ -126,20 +387,40 @@ fn parse_apkindex<R: BufRead>(reader: R) -> Result<Vec<Package>> {
                 packages.push(current);
             }
             current = Package::default();
+            last_key = None;
+            continue;
+        }
+
+        // A folded value continues on lines starting with whitespace.
+        if line.starts_with([' ', '\t']) {
+            if let Some(key) = last_key {
+                append_continuation(&mut current, key, line.trim());
+            }
             continue;
         }
 
         if line.len() < 2 || line.as_bytes()[1] != b':' {
+            last_key = None;
             continue;
         }
 
         let key = line.as_bytes()[0];
         let val = &line[2..];
+        last_key = Some(key);
 
         match key {
             b'P' => current.name = val.to_string(),
-            b'V' => current.version = val.to_string(),
+            b'V' => current.version = val.into(),
             b'D' => current.depends = val.split_whitespace().map(|s| s.to_string()).collect(),
+            b'p' => current.provides = val.split_whitespace().map(|s| s.to_string()).collect(),
+            b'A' => current.arch = Some(val.to_string()),
+            b'S' => current.size = val.parse().ok(),
+            b'I' => current.installed_size = val.parse().ok(),
+            b'T' => current.description = Some(val.to_string()),
+            b'L' => current.license = Some(val.to_string()),
+            b'm' => current.maintainer = Some(val.to_string()),
+            b't' => current.build_time = val.parse().ok(),
+            b'c' => current.commit = Some(val.to_string()),
             _ => {}
         }
     }
@@ -151,6 +432,28 @@ fn parse_apkindex<R: BufRead>(reader: R) -> Result<Vec<Package>> {
     Ok(packages)
 }
 
+fn append_continuation(pkg: &mut Package, key: u8, val: &str) {
+    if val.is_empty() {
+        return;
+    }
+    let append = |field: &mut Option<String>| match field {
+        Some(existing) => {
+            existing.push(' ');
+            existing.push_str(val);
+        }
+        None => *field = Some(val.to_string()),
+    };
+
+    match key {
+        b'D' => pkg.depends.extend(val.split_whitespace().map(|s| s.to_string())),
+        b'p' => pkg.provides.extend(val.split_whitespace().map(|s| s.to_string())),
+        b'T' => append(&mut pkg.description),
+        b'L' => append(&mut pkg.license),
+        b'm' => append(&mut pkg.maintainer),
+        _ => {}
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,11 +462,36 @@ mod tests {
     fn make_package(name: &str, version: &str, depends: Vec<&str>) -> Package {
         Package {
             name: name.to_string(),
-            version: version.to_string(),
+            version: version.into(),
             depends: depends.into_iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
         }
     }
 
+    #[test]
+    fn remote_cache_is_keyed_by_url() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let a = "https://a.example.com/aarch64/APKINDEX.tar.gz";
+        let b = "https://b.example.com/aarch64/APKINDEX.tar.gz";
+        assert_ne!(
+            remote_cache_path(dir, a, REMOTE_INDEX_SUFFIX),
+            remote_cache_path(dir, b, REMOTE_INDEX_SUFFIX)
+        );
+
+        store_remote_index(dir, a, b"index a", Some("\"a\"".to_string()), None).unwrap();
+        store_remote_index(dir, b, b"index b", Some("\"b\"".to_string()), None).unwrap();
+        let read = |url, suffix| fs::read_to_string(remote_cache_path(dir, url, suffix)).unwrap();
+        assert_eq!(read(a, REMOTE_INDEX_SUFFIX), "index a");
+        assert_eq!(read(a, REMOTE_ETAG_SUFFIX), "\"a\"");
+        assert_eq!(read(b, REMOTE_ETAG_SUFFIX), "\"b\"");
+
+        clear_remote_index_cache(dir.to_str().unwrap());
+        assert!(!remote_cache_path(dir, a, REMOTE_ETAG_SUFFIX).exists());
+        assert!(!remote_cache_path(dir, b, REMOTE_ETAG_SUFFIX).exists());
+        assert!(remote_cache_path(dir, b, REMOTE_INDEX_SUFFIX).exists());
+    }
+
     #[test]
     fn get_os_constraints_with_min_only() {
         let pkg = make_package("test", "1.0", vec!["remarkable-os>=3.10.0.0"]);
@@ -264,6 +592,127 @@ mod tests {
         assert!(!pkg.is_compatible_with_os("4.0.0.0"));
     }
 
+    fn from_repo(mut pkg: Package, repo: &str) -> Package {
+        pkg.repo = Some(repo.to_string());
+        pkg
+    }
+
+    fn tagged(mut pkg: Package, tag: &str) -> Package {
+        pkg.tag = Some(tag.to_string());
+        pkg
+    }
+
+    #[test]
+    fn parses_repository_lines() {
+        let stable = "https://packages.vellum.delivery";
+        assert_eq!(Repository::parse(&format!("  {stable}\n")), Some(Repository::untagged(stable)));
+        assert_eq!(
+            Repository::parse("@testing  https://packages.vellum.delivery/testing"),
+            Some(Repository {
+                url: "https://packages.vellum.delivery/testing".to_string(),
+                tag: Some("testing".to_string()),
+            })
+        );
+        assert_eq!(Repository::parse("# https://example.com"), None);
+        assert_eq!(Repository::parse("@testing"), None);
+        assert_eq!(Repository::parse(""), None);
+    }
+
+    #[test]
+    fn apk_cache_name_hashes_the_url() {
+        let repo = Repository::untagged("https://packages.vellum.delivery");
+        assert_eq!(repo.apk_cache_name(), "APKINDEX.01e1c010.tar.gz");
+    }
+
+    #[test]
+    fn merge_indexes_combines_repositories() {
+        let main = Repository::untagged("https://packages.vellum.delivery");
+        let mirror = Repository::untagged("https://mirror.example.com");
+        let custom = Repository::untagged("https://example.com/repo");
+        let down = Repository::untagged("https://down.example.com");
+        let results = vec![
+            (&main, Ok(vec![from_repo(make_package("foo", "1.0", vec![]), &main.url)])),
+            (&mirror, Ok(vec![from_repo(make_package("foo", "1.0", vec![]), &mirror.url)])),
+            (&custom, Ok(vec![from_repo(make_package("bar", "2.0", vec![]), &custom.url)])),
+            (&down, Err(anyhow!("HTTP 503"))),
+        ];
+
+        let (index, _) = merge_indexes(results).unwrap();
+        let names: Vec<(&str, Option<&str>)> =
+            index.iter().map(|p| (p.name.as_str(), p.repo.as_deref())).collect();
+        let expected = vec![("foo", Some(main.url.as_str())), ("bar", Some(custom.url.as_str()))];
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn merge_indexes_marks_tagged_packages() {
+        let testing = Repository::parse("@testing https://packages.vellum.delivery/testing");
+        let testing = testing.unwrap();
+        let stable = Repository::untagged("https://packages.vellum.delivery");
+        let testing_index =
+            vec![make_package("foo", "1.0", vec![]), make_package("foo", "1.1", vec![])];
+        let results = vec![
+            (&testing, Ok(testing_index)),
+            (&stable, Ok(vec![make_package("foo", "1.0", vec![])])),
+        ];
+
+        let (index, url) = merge_indexes(results).unwrap();
+        let entries: Vec<(&str, Option<&str>)> =
+            index.iter().map(|p| (p.version.as_str(), p.tag.as_deref())).collect();
+        assert_eq!(entries, vec![("1.0", None), ("1.1", Some("testing"))]);
+        assert_eq!(url.as_deref(), Some(stable.url.as_str()));
+    }
+
+    #[test]
+    fn merge_indexes_reports_the_repository_that_answered() {
+        let main = Repository::untagged("https://packages.vellum.delivery");
+        let mirror = Repository::untagged("https://mirror.example.com");
+        let results = vec![
+            (&main, Err(anyhow!("HTTP 503"))),
+            (&mirror, Ok(vec![make_package("foo", "1.0", vec![])])),
+        ];
+        let (_, url) = merge_indexes(results).unwrap();
+        assert_eq!(url, Some(mirror.url));
+    }
+
+    #[test]
+    fn merge_indexes_fails_when_every_repository_fails() {
+        let a = Repository::untagged("https://a.example.com");
+        let b = Repository::untagged("https://b.example.com");
+        let results = vec![(&a, Err(anyhow!("HTTP 503"))), (&b, Err(anyhow!("timed out")))];
+        let err = merge_indexes(results).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "all repositories failed:\n  https://a.example.com: HTTP 503\n  \
+             https://b.example.com: timed out"
+        );
+    }
+
+    #[test]
+    fn best_compatible_version_prefers_untagged() {
+        let index = vec![
+            make_package("foo", "1.0", vec![]),
+            tagged(make_package("foo", "1.1", vec![]), "testing"),
+            make_package("bar", "2.0", vec!["remarkable-os>=4.0.0.0"]),
+            tagged(make_package("bar", "1.0", vec![]), "testing"),
+        ];
+
+        let foo = find_best_compatible_version("foo", "3.10.0.0", &index).unwrap();
+        assert_eq!(foo.version.as_str(), "1.0");
+        assert_eq!(foo.tag, None);
+
+        let bar = find_best_compatible_version("bar", "3.10.0.0", &index).unwrap();
+        assert_eq!(bar.version.as_str(), "1.0");
+        assert_eq!(bar.tag.as_deref(), Some("testing"));
+    }
+
+    #[test]
+    fn best_compatible_version_without_repo_takes_newest() {
+        let index = vec![make_package("foo", "1.0", vec![]), make_package("foo", "1.1", vec![])];
+        let foo = find_best_compatible_version("foo", "3.10.0.0", &index).unwrap();
+        assert_eq!(foo.version.as_str(), "1.1");
+    }
+
     #[test]
     fn parse_apkindex_single_package() {
         let input = "P:test-pkg\nV:1.0.0\nD:dep1 dep2\n";
@@ -272,7 +721,7 @@ mod tests {
 
         assert_eq!(packages.len(), 1);
         assert_eq!(packages[0].name, "test-pkg");
-        assert_eq!(packages[0].version, "1.0.0");
+        assert_eq!(packages[0].version.as_str(), "1.0.0");
         assert_eq!(packages[0].depends, vec!["dep1", "dep2"]);
     }
 
@@ -287,6 +736,28 @@ mod tests {
         assert_eq!(packages[1].name, "pkg2");
     }
 
+    #[test]
+    fn parse_apkindex_folded_description() {
+        let input = "P:test-pkg\nT:A long\n  description\n\tcontinued\nV:1.0.0\n";
+        let reader = BufReader::new(input.as_bytes());
+        let packages = parse_apkindex(reader).unwrap();
+
+        assert_eq!(
+            packages[0].description.as_deref(),
+            Some("A long description continued")
+        );
+        assert_eq!(packages[0].version.as_str(), "1.0.0");
+    }
+
+    #[test]
+    fn parse_apkindex_folded_depends() {
+        let input = "P:test-pkg\nD:dep1\n dep2 dep3\n";
+        let reader = BufReader::new(input.as_bytes());
+        let packages = parse_apkindex(reader).unwrap();
+
+        assert_eq!(packages[0].depends, vec!["dep1", "dep2", "dep3"]);
+    }
+
     #[test]
     fn parse_apkindex_skips_malformed_lines() {
         let input = "P:test-pkg\nmalformed line\nV:1.0.0\nX\n";
@@ -295,7 +766,7 @@ mod tests {
 
         assert_eq!(packages.len(), 1);
         assert_eq!(packages[0].name, "test-pkg");
-        assert_eq!(packages[0].version, "1.0.0");
+        assert_eq!(packages[0].version.as_str(), "1.0.0");
     }
 
     #[test]
@@ -325,7 +796,135 @@ mod tests {
 
         assert_eq!(packages.len(), 1);
         assert_eq!(packages[0].name, "test-pkg");
-        assert_eq!(packages[0].version, "1.0.0");
+        assert_eq!(packages[0].version.as_str(), "1.0.0");
         assert!(packages[0].depends.is_empty());
     }
+
+    #[test]
+    fn parse_apkindex_arch() {
+        let input = "P:test-pkg\nV:1.0.0\nA:aarch64\n";
+        let packages = parse_apkindex(BufReader::new(input.as_bytes())).unwrap();
+
+        assert_eq!(packages[0].arch.as_deref(), Some("aarch64"));
+    }
+
+    #[test]
+    fn parse_apkindex_sizes() {
+        let input = "P:test-pkg\nV:1.0.0\nS:12345\nI:67890\n";
+        let packages = parse_apkindex(BufReader::new(input.as_bytes())).unwrap();
+
+        assert_eq!(packages[0].size, Some(12345));
+        assert_eq!(packages[0].installed_size, Some(67890));
+    }
+
+    #[test]
+    fn parse_apkindex_invalid_size_is_none() {
+        let input = "P:test-pkg\nV:1.0.0\nS:lots\nI:\n";
+        let packages = parse_apkindex(BufReader::new(input.as_bytes())).unwrap();
+
+        assert_eq!(packages[0].size, None);
+        assert_eq!(packages[0].installed_size, None);
+    }
+
+    #[test]
+    fn parse_apkindex_description() {
+        let input = "P:test-pkg\nV:1.0.0\nT:A test package: with colons\n";
+        let packages = parse_apkindex(BufReader::new(input.as_bytes())).unwrap();
+
+        assert_eq!(packages[0].description.as_deref(), Some("A test package: with colons"));
+    }
+
+    #[test]
+    fn parse_apkindex_license() {
+        let input = "P:test-pkg\nV:1.0.0\nL:MIT\n";
+        let packages = parse_apkindex(BufReader::new(input.as_bytes())).unwrap();
+
+        assert_eq!(packages[0].license.as_deref(), Some("MIT"));
+    }
+
+    #[test]
+    fn parse_apkindex_maintainer() {
+        let input = "P:test-pkg\nV:1.0.0\nm:Jane Doe <jane@example.com>\n";
+        let packages = parse_apkindex(BufReader::new(input.as_bytes())).unwrap();
+
+        assert_eq!(packages[0].maintainer.as_deref(), Some("Jane Doe <jane@example.com>"));
+    }
+
+    #[test]
+    fn parse_apkindex_build_time() {
+        let input = "P:test-pkg\nV:1.0.0\nt:1700000000\n";
+        let packages = parse_apkindex(BufReader::new(input.as_bytes())).unwrap();
+
+        assert_eq!(packages[0].build_time, Some(1700000000));
+    }
+
+    #[test]
+    fn parse_apkindex_commit() {
+        let input = "P:test-pkg\nV:1.0.0\nc:0123456789abcdef\n";
+        let packages = parse_apkindex(BufReader::new(input.as_bytes())).unwrap();
+
+        assert_eq!(packages[0].commit.as_deref(), Some("0123456789abcdef"));
+    }
+
+    #[test]
+    fn parse_apkindex_optional_fields_default_to_none() {
+        let input = "P:test-pkg\nV:1.0.0\n";
+        let packages = parse_apkindex(BufReader::new(input.as_bytes())).unwrap();
+
+        let pkg = &packages[0];
+        assert!(pkg.arch.is_none());
+        assert!(pkg.size.is_none());
+        assert!(pkg.installed_size.is_none());
+        assert!(pkg.description.is_none());
+        assert!(pkg.license.is_none());
+        assert!(pkg.maintainer.is_none());
+        assert!(pkg.build_time.is_none());
+        assert!(pkg.commit.is_none());
+    }
+
+    #[test]
+    fn parse_apkindex_fields_do_not_leak_between_packages() {
+        let input = "P:pkg1\nV:1.0\nT:first\nS:10\n\nP:pkg2\nV:2.0\n";
+        let packages = parse_apkindex(BufReader::new(input.as_bytes())).unwrap();
+
+        assert_eq!(packages[0].description.as_deref(), Some("first"));
+        assert!(packages[1].description.is_none());
+        assert!(packages[1].size.is_none());
+    }
+
+    #[test]
+    fn parse_apkindex_provides() {
+        let input = "P:new-pkg\nV:2.0\np:old-pkg=2.0 cmd:tool so:libfoo.so.1\n\nP:other\nV:1.0\n";
+        let packages = parse_apkindex(BufReader::new(input.as_bytes())).unwrap();
+
+        assert_eq!(packages[0].provides, vec!["old-pkg=2.0", "cmd:tool", "so:libfoo.so.1"]);
+        assert!(packages[1].provides.is_empty());
+    }
+
+    fn index_tar_gz(apkindex: &str) -> Vec<u8> {
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(apkindex.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "APKINDEX", apkindex.as_bytes()).unwrap();
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut gz, &tar.into_inner().unwrap()).unwrap();
+        gz.finish().unwrap()
+    }
+
+    #[test]
+    fn parse_index_from_tar_gz_reads_archive() {
+        let data = index_tar_gz("P:foo\nV:1.0-r0\n\n");
+        let packages = parse_index_from_tar_gz(Cursor::new(data)).unwrap();
+        assert_eq!(packages[0].name, "foo");
+    }
+
+    #[test]
+    fn parse_index_from_tar_gz_reports_truncation() {
+        let data = index_tar_gz("P:foo\nV:1.0-r0\n\n");
+        let err = parse_index_from_tar_gz(Cursor::new(&data[..data.len() / 2])).unwrap_err();
+        assert!(err.to_string().starts_with(TRUNCATED_INDEX), "{err}");
+    }
 }
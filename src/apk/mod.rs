@@ -1,11 +1,21 @@
 mod compat;
+mod dependency;
 mod exec;
 mod index;
 mod package;
 mod version;
 
-pub use compat::check_os_compatibility;
-pub use exec::Apk;
-pub use index::{fetch_remote_index, find_best_compatible_version, parse_index_tar_gz, Package};
-pub use package::{generate_device_package, generate_remarkable_os_package};
-pub use version::version_lt;
+pub use compat::{check_os_compatibility, CompatResult};
+pub use dependency::Dependency;
+pub use exec::{parse_list_line, Apk};
+pub use index::{
+    clear_remote_index_cache, fetch_index_with_mirrors, fetch_remote_index,
+    find_best_compatible_version, merge_tagged,
+    parse_index_tar_gz, with_tag, Package, Repository,
+};
+pub use package::{
+    generate_device_package, generate_remarkable_os_package, read_package_identity, resign_package,
+};
+pub use version::{
+    compare_package_versions, version_eq, version_gt, version_lt, Version,
+};
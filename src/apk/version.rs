@@ -1,28 +1,76 @@
 use std::cmp::Ordering;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+/// A package version such as `1.2.3-r1`, ordered the way apk orders them so
+/// versions can be sorted and kept in sets or used as map keys.
+///
+/// Versions that apk considers equal but are spelled differently (`1.0` and
+/// `1.00`) are ordered by their text, so equality still means the same string.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Version(String);
+
+impl Version {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Version {
+    fn from(s: &str) -> Self {
+        Version(s.to_string())
+    }
+}
+
+impl FromStr for Version {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_package_versions(&self.0, &other.0).then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// Rank of a version without any `_suffix`: after `_rc`, before `_p`.
+const NO_SUFFIX: (u8, u64) = (4, 0);
 
 pub fn compare_versions(a: &str, b: &str) -> Ordering {
     if a == b {
         return Ordering::Equal;
     }
 
+    let (a_epoch, a) = split_epoch(a);
+    let (b_epoch, b) = split_epoch(b);
+    if a_epoch != b_epoch {
+        return a_epoch.cmp(&b_epoch);
+    }
+
     let a_parts: Vec<&str> = a.split('.').collect();
     let b_parts: Vec<&str> = b.split('.').collect();
 
     let min_len = a_parts.len().min(b_parts.len());
 
     for i in 0..min_len {
-        let a_num: i32 = a_parts[i]
-            .split('-')
-            .next()
-            .unwrap_or("0")
-            .parse()
-            .unwrap_or(0);
-        let b_num: i32 = b_parts[i]
-            .split('-')
-            .next()
-            .unwrap_or("0")
-            .parse()
-            .unwrap_or(0);
+        let a_num = leading_number(a_parts[i]);
+        let b_num = leading_number(b_parts[i]);
 
         match a_num.cmp(&b_num) {
             Ordering::Greater => return Ordering::Greater,
@@ -31,21 +79,134 @@ pub fn compare_versions(a: &str, b: &str) -> Ordering {
         }
     }
 
-    a_parts.len().cmp(&b_parts.len())
+    match a_parts.len().cmp(&b_parts.len()) {
+        Ordering::Equal => compare_suffixes(a, b),
+        ord => ord,
+    }
+}
+
+// Splits an `N:` epoch prefix off a version; no prefix means epoch 0.
+fn split_epoch(version: &str) -> (u64, &str) {
+    match version.split_once(':') {
+        Some((epoch, rest)) if !epoch.is_empty() && epoch.chars().all(|c| c.is_ascii_digit()) => {
+            (epoch.parse().unwrap_or(0), rest)
+        }
+        _ => (0, version),
+    }
+}
+
+fn leading_number(part: &str) -> u64 {
+    let end = part
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(part.len());
+    part[..end].parse().unwrap_or(0)
+}
+
+// Alpine orders `_alpha < _beta < _pre < _rc < (none) < _p`.
+fn suffix_rank(name: &str) -> u8 {
+    match name {
+        "alpha" => 0,
+        "beta" => 1,
+        "pre" => 2,
+        "rc" => 3,
+        "p" => 5,
+        _ => NO_SUFFIX.0,
+    }
+}
+
+// Parses the `_suffixN` tokens of a version (ignoring any `-rN` release) into
+// (rank, number) pairs.
+fn parse_suffixes(version: &str) -> Vec<(u8, u64)> {
+    let version = version.split('-').next().unwrap_or("");
+    version
+        .split('_')
+        .skip(1)
+        .map(|token| {
+            let split = token
+                .find(|c: char| c.is_ascii_digit())
+                .unwrap_or(token.len());
+            let (name, num) = token.split_at(split);
+            (suffix_rank(name), num.parse().unwrap_or(0))
+        })
+        .collect()
+}
+
+fn compare_suffixes(a: &str, b: &str) -> Ordering {
+    let a_suffixes = parse_suffixes(a);
+    let b_suffixes = parse_suffixes(b);
+
+    for i in 0..a_suffixes.len().max(b_suffixes.len()) {
+        let a_suffix = a_suffixes.get(i).copied().unwrap_or(NO_SUFFIX);
+        let b_suffix = b_suffixes.get(i).copied().unwrap_or(NO_SUFFIX);
+
+        match a_suffix.cmp(&b_suffix) {
+            Ordering::Equal => continue,
+            ord => return ord,
+        }
+    }
+
+    Ordering::Equal
+}
+
+/// Like `compare_versions`, but two builds of the same version are ordered
+/// by their `-rN` release.
+pub fn compare_package_versions(a: &str, b: &str) -> Ordering {
+    let release = |v: &str| -> u64 {
+        v.rsplit_once("-r")
+            .and_then(|(_, r)| r.parse().ok())
+            .unwrap_or(0)
+    };
+    compare_versions(a, b).then_with(|| release(a).cmp(&release(b)))
 }
 
 pub fn version_gte(a: &str, b: &str) -> bool {
     compare_versions(a, b) != Ordering::Less
 }
 
+pub fn version_gt(a: &str, b: &str) -> bool {
+    compare_versions(a, b) == Ordering::Greater
+}
+
 pub fn version_lt(a: &str, b: &str) -> bool {
     compare_versions(a, b) == Ordering::Less
 }
 
+pub fn version_lte(a: &str, b: &str) -> bool {
+    compare_versions(a, b) != Ordering::Greater
+}
+
+/// Whether two versions compare equal. Unlike `==` on the strings, this
+/// ignores the `-rN` release, so `1.0-r0` and `1.0-r1` are the same version.
+pub fn version_eq(a: &str, b: &str) -> bool {
+    compare_versions(a, b) == Ordering::Equal
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn v(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn version_sorts_like_apk() {
+        let mut versions = [v("1.10-r0"), v("1.2-r1"), v("1.2-r0"), v("1.2_rc1-r0")];
+        versions.sort();
+        let sorted: Vec<&str> = versions.iter().map(Version::as_str).collect();
+        assert_eq!(sorted, vec!["1.2_rc1-r0", "1.2-r0", "1.2-r1", "1.10-r0"]);
+        assert_eq!(v("2.0").to_string(), "2.0");
+    }
+
+    #[test]
+    fn version_equality_matches_text() {
+        use std::collections::BTreeSet;
+
+        let set: BTreeSet<Version> = [v("1.0"), v("1.0"), v("1.00")].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        assert_eq!(v("1.0").cmp(&v("1.0")), Ordering::Equal);
+    }
+
     #[test]
     fn compare_equal_versions() {
         assert_eq!(compare_versions("3.10.0.0", "3.10.0.0"), Ordering::Equal);
@@ -120,4 +281,92 @@ mod tests {
         assert!(!version_lt("3.10.0.0", "3.10.0.0"));
         assert!(!version_lt("3.10.0.0", "3.9.0.0"));
     }
+
+    #[test]
+    fn version_gt_is_strict() {
+        assert!(version_gt("3.10.0.0", "3.9.0.0"));
+        assert!(version_gt("3.0", "3"));
+        assert!(!version_gt("3.10.0.0", "3.10.0.0"));
+        assert!(!version_gt("3.10.0-r2", "3.10.0-r1"));
+        assert!(!version_gt("3.9.0.0", "3.10.0.0"));
+    }
+
+    #[test]
+    fn version_lte_includes_equal() {
+        assert!(version_lte("3.10.0.0", "3.10.0.0"));
+        assert!(version_lte("3.9.0.0", "3.10.0.0"));
+        assert!(version_lte("1.0_rc1", "1.0"));
+        assert!(!version_lte("3.10.0.0", "3.9.0.0"));
+        assert!(!version_lte("3.0", "3"));
+    }
+
+    #[test]
+    fn version_eq_ignores_release() {
+        assert!(version_eq("3.10.0.0", "3.10.0.0"));
+        assert!(version_eq("1.0-r0", "1.0-r1"));
+        assert!(!version_eq("3.0", "3"));
+        assert!(!version_eq("1.0_rc1", "1.0"));
+    }
+
+    #[test]
+    fn compare_alpha_beta_pre_rc() {
+        assert_eq!(compare_versions("1.0_alpha", "1.0_beta"), Ordering::Less);
+        assert_eq!(compare_versions("1.0_beta", "1.0_pre"), Ordering::Less);
+        assert_eq!(compare_versions("1.0_pre", "1.0_rc"), Ordering::Less);
+        assert_eq!(compare_versions("1.0_rc", "1.0_alpha"), Ordering::Greater);
+    }
+
+    #[test]
+    fn compare_prerelease_before_release() {
+        assert_eq!(compare_versions("1.0_alpha", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.0_beta", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.0_pre", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.0_rc", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.0", "1.0_rc1"), Ordering::Greater);
+    }
+
+    #[test]
+    fn compare_patch_after_release() {
+        assert_eq!(compare_versions("1.0_p1", "1.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0_p1", "1.0_rc1"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0_p2", "1.0_p1"), Ordering::Greater);
+    }
+
+    #[test]
+    fn compare_suffix_numbers() {
+        assert_eq!(compare_versions("1.0_rc1", "1.0_rc2"), Ordering::Less);
+        assert_eq!(compare_versions("1.0_alpha10", "1.0_alpha9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0_beta", "1.0_beta1"), Ordering::Less);
+    }
+
+    #[test]
+    fn compare_suffix_does_not_override_numeric_parts() {
+        assert_eq!(compare_versions("1.1_alpha", "1.0_p1"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0_rc1", "1.0.1"), Ordering::Less);
+    }
+
+    #[test]
+    fn compare_suffix_with_release() {
+        assert_eq!(compare_versions("1.0_rc1-r2", "1.0-r0"), Ordering::Less);
+        assert_eq!(compare_versions("1.0_p1-r0", "1.0-r5"), Ordering::Greater);
+    }
+
+    #[test]
+    fn compare_epoch_beats_version() {
+        assert_eq!(compare_versions("1:3.10.0.0", "2:1.0"), Ordering::Less);
+        assert_eq!(compare_versions("2:1.0", "1:3.10.0.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1:1.0", "9.9.9.9"), Ordering::Greater);
+    }
+
+    #[test]
+    fn compare_missing_epoch_is_zero() {
+        assert_eq!(compare_versions("0:3.10.0.0", "3.10.0.0"), Ordering::Equal);
+        assert_eq!(compare_versions("3.10.0.0", "1:3.9.0.0"), Ordering::Less);
+    }
+
+    #[test]
+    fn compare_same_epoch_uses_version() {
+        assert_eq!(compare_versions("1:3.10.0.0", "1:3.9.0.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1:1.0_rc1-r0", "1:1.0-r0"), Ordering::Less);
+    }
 }
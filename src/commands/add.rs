@@ -1,30 +1,52 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io::{self, BufRead, Write};
 use std::process;
+use std::time::SystemTime;
 
-use crate::apk::{fetch_remote_index, find_best_compatible_version, parse_index_tar_gz, Apk, Package};
+use super::index::get_index;
+use crate::apk::{find_best_compatible_version, Apk, Package};
+use crate::config::Config;
 use crate::constants::VELLUM_ROOT;
-use crate::device::get_apk_arch;
+use crate::state::State;
+use crate::util::line_ending;
+
+pub fn handle_add(state: &State, apk: &Apk, config: &Config, args: &[String]) {
+    let simulate = args.iter().any(|a| a == "--simulate" || a == "--dry-run");
+    let no_cache = args.iter().any(|a| a == "--no-cache");
+    let verbose = args.iter().any(|a| a == "--verbose");
+    let assume_yes = args.iter().any(|a| a == "-y" || a == "--yes");
+    let args: Vec<String> = args
+        .iter()
+        .filter(|a| {
+            !matches!(
+                a.as_str(),
+                "--simulate" | "--dry-run" | "--no-cache" | "--verbose" | "-y" | "--yes"
+            )
+        })
+        .cloned()
+        .collect();
 
-pub fn handle_add(apk: &Apk, args: &[String]) {
     let os_version = match apk.get_package_version("remarkable-os") {
         Ok(Some(v)) => v,
         Ok(None) | Err(_) => {
-            return run_add_directly(apk, args);
+            return run_add_directly(state, apk, &args, simulate, verbose, assume_yes);
         }
     };
 
-    let index = match get_index() {
+    let index = match get_index((!simulate).then_some(state), config, no_cache) {
         Ok(idx) => idx,
         Err(_) => {
-            return run_add_directly(apk, args);
+            return run_add_directly(state, apk, &args, simulate, verbose, assume_yes);
         }
     };
 
     let mut resolved_args: Vec<String> = Vec::new();
     let mut resolved_packages: Vec<String> = Vec::new();
+    let mut resolved: Vec<&Package> = Vec::new();
     let mut has_incompatible = false;
 
-    for arg in args {
+    for arg in &args {
         if arg.contains('=') || arg.contains('<') || arg.contains('>') || arg.starts_with('-') {
             resolved_args.push(arg.clone());
             continue;
@@ -34,6 +56,7 @@ pub fn handle_add(apk: &Apk, args: &[String]) {
             Some(pkg) => {
                 resolved_args.push(format!("{}={}", pkg.name, pkg.version));
                 resolved_packages.push(pkg.name.clone());
+                resolved.push(pkg);
             }
             None => {
                 let has_any_version = index.iter().any(|p| p.name == *arg);
@@ -51,10 +74,30 @@ pub fn handle_add(apk: &Apk, args: &[String]) {
         process::exit(1);
     }
 
+    // apk settles a clash between providers by removing the installed one, so
+    // say so before the confirmation below.
+    let installed = apk.list_installed_with_versions().ok();
+    let installed_with_versions = installed.as_deref().unwrap_or_default();
+    for warning in provides_conflicts(&resolved, installed_with_versions, &index) {
+        eprintln!("{warning}");
+    }
+
+    if simulate {
+        print_simulation(&resolved_args);
+        return;
+    }
+    if !assume_yes && !confirm_install(apk, &resolved_args) {
+        println!("Aborted.");
+        return;
+    }
+    if verbose {
+        print_descriptions(apk, &resolved_args);
+    }
+
     let mut cmd_args = vec!["add", "--cache-predownload"];
     cmd_args.extend(resolved_args.iter().map(|s| s.as_str()));
 
-    let result = apk.run(&cmd_args);
+    let result = apk.run_with_retry(&cmd_args, 3);
     let _ = apk.cache_purge();
 
     if result.is_err() {
@@ -64,80 +107,314 @@ pub fn handle_add(apk: &Apk, args: &[String]) {
     if !resolved_packages.is_empty() {
         clean_world_file_pins(&resolved_packages);
     }
+
+    let installed: Option<Vec<String>> =
+        installed.map(|list| list.into_iter().map(|(name, _)| name).collect());
+    record_add(state, apk, &args, installed.as_deref());
 }
 
-fn run_add_directly(apk: &Apk, args: &[String]) {
+fn run_add_directly(
+    state: &State,
+    apk: &Apk,
+    args: &[String],
+    simulate: bool,
+    verbose: bool,
+    assume_yes: bool,
+) {
+    if simulate {
+        print_simulation(args);
+        return;
+    }
+    if !assume_yes && !confirm_install(apk, args) {
+        println!("Aborted.");
+        return;
+    }
+    if verbose {
+        print_descriptions(apk, args);
+    }
+
+    let installed = apk.list_installed().ok();
     let mut cmd_args = vec!["add", "--cache-predownload"];
     cmd_args.extend(args.iter().map(|s| s.as_str()));
 
-    let result = apk.run(&cmd_args);
+    let result = apk.run_with_retry(&cmd_args, 3);
     let _ = apk.cache_purge();
 
     if result.is_err() {
         process::exit(1);
     }
+
+    record_add(state, apk, args, installed.as_deref());
 }
 
-fn get_index() -> anyhow::Result<Vec<Package>> {
-    let cache_dir = format!("{VELLUM_ROOT}/etc/apk/cache");
-
-    if let Ok(entries) = fs::read_dir(&cache_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.starts_with("APKINDEX.") && name.ends_with(".tar.gz") {
-                    if let Some(path_str) = path.to_str() {
-                        return parse_index_tar_gz(path_str);
-                    }
-                }
-            }
+// `installed_before` is what was installed before the add, used to tell the
+// dependencies it pulled in apart from the packages that were asked for.
+// Without it, only the requested packages are recorded.
+fn record_add(state: &State, apk: &Apk, args: &[String], installed_before: Option<&[String]>) {
+    let packages: Vec<String> = args.iter().filter(|a| !a.starts_with('-')).cloned().collect();
+    let os = state.get_os_version().unwrap_or_default();
+    if let Err(e) = state.append_install_event("add", &packages, &os) {
+        eprintln!("warning: failed to record install log: {e}");
+    }
+
+    let now = SystemTime::now();
+    for spec in &packages {
+        if let Err(e) = state.set_manual(package_name(spec)) {
+            eprintln!("warning: failed to record {spec} as manually installed: {e}");
+        }
+        if let Err(e) = state.set_install_time(package_name(spec), now) {
+            eprintln!("warning: failed to record install time for {spec}: {e}");
         }
     }
 
-    let repo_url = match get_repo_url() {
-        Some(url) => url,
-        None => return Err(anyhow::anyhow!("no cached index and could not determine repository URL")),
+    let (Some(before), Ok(after)) = (installed_before, apk.list_installed()) else {
+        return;
     };
+    for pkg in pulled_in_dependencies(before, &after, &packages) {
+        if let Err(e) = state.remove_manual(pkg) {
+            eprintln!("warning: failed to record {pkg} as automatically installed: {e}");
+        }
+    }
+}
 
-    let arch = get_apk_arch();
-    fetch_remote_index(&repo_url, &arch)
+// Packages that are newly installed but weren't asked for came in as
+// dependencies.
+fn pulled_in_dependencies<'a>(
+    before: &[String],
+    after: &'a [String],
+    requested: &[String],
+) -> Vec<&'a str> {
+    let before: HashSet<&str> = before.iter().map(String::as_str).collect();
+    let requested: HashSet<&str> = requested.iter().map(|s| package_name(s)).collect();
+    after
+        .iter()
+        .map(String::as_str)
+        .filter(|p| !before.contains(p) && !requested.contains(p))
+        .collect()
 }
 
-fn get_repo_url() -> Option<String> {
-    let repos_file = format!("{VELLUM_ROOT}/etc/apk/repositories");
-    let content = fs::read_to_string(repos_file).ok()?;
+// Strips any version constraint or repository tag from a package spec.
+pub fn package_name(spec: &str) -> &str {
+    spec.split(['=', '<', '>', '~', '@']).next().unwrap_or(spec)
+}
 
-    for line in content.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
+fn print_descriptions(apk: &Apk, specs: &[String]) {
+    for spec in specs.iter().filter(|s| !s.starts_with('-')) {
+        let name = package_name(spec);
+        match apk.get_package_description(name) {
+            Ok(Some(desc)) => println!("Installing {name}: {desc}"),
+            _ => println!("Installing {name}"),
+        }
+    }
+}
+
+fn print_simulation(specs: &[String]) {
+    let packages: Vec<&String> = specs.iter().filter(|s| !s.starts_with('-')).collect();
+    let total = packages.len();
+
+    for (i, spec) in packages.iter().enumerate() {
+        match spec.split_once('=') {
+            Some((name, version)) => println!("({}/{total}) Installing {name} ({version})", i + 1),
+            None => println!("({}/{total}) Installing {spec}", i + 1),
         }
-        if !line.contains("local-repo") {
-            return Some(line.to_string());
+    }
+    println!("OK: simulated, nothing was installed");
+}
+
+// Lists what is about to be installed and asks to go ahead, like the upgrade
+// confirmation.
+fn confirm_install(apk: &Apk, specs: &[String]) -> bool {
+    let installed: HashMap<String, String> = apk
+        .list_installed_with_versions()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+
+    println!("The following packages will be installed:");
+    for line in install_preview(specs, &installed) {
+        println!("  {line}");
+    }
+    print!("\nProceed with installation? [y/N] ");
+    let _ = io::stdout().flush();
+
+    let mut line = String::new();
+    let _ = io::stdin().lock().read_line(&mut line);
+    let confirm = line.trim().to_lowercase();
+    confirm == "y" || confirm == "yes"
+}
+
+/// Warnings for each virtual package that something in `new` provides and an
+/// installed package other than those being installed provides too.
+fn provides_conflicts(
+    new: &[&Package],
+    installed: &[(String, String)],
+    index: &[Package],
+) -> Vec<String> {
+    let installed_provides = |name: &str, version: &str| -> Vec<String> {
+        let entry = index
+            .iter()
+            .find(|p| p.name == name && p.version.as_str() == version)
+            .or_else(|| index.iter().find(|p| p.name == name));
+        entry
+            .map(|p| p.provides.iter().map(|v| provided_name(v).to_string()).collect())
+            .unwrap_or_default()
+    };
+
+    let mut warnings = Vec::new();
+    for pkg in new {
+        for virtual_name in pkg.provides.iter().map(|v| provided_name(v)) {
+            if virtual_name == pkg.name {
+                continue;
+            }
+            for (other, version) in installed {
+                if new.iter().any(|p| &p.name == other) {
+                    continue;
+                }
+                if installed_provides(other, version).iter().any(|v| v == virtual_name) {
+                    warnings.push(format!(
+                        "Warning: {} provides {virtual_name}, which is also provided by \
+                         installed {other}. Proceeding may remove {other}.",
+                        pkg.name
+                    ));
+                }
+            }
         }
     }
-    None
+    warnings
+}
+
+// `provides` entries are `name` or `name=version`.
+fn provided_name(provided: &str) -> &str {
+    provided.split('=').next().unwrap_or(provided)
 }
 
-fn clean_world_file_pins(packages: &[String]) {
+fn install_preview(specs: &[String], installed: &HashMap<String, String>) -> Vec<String> {
+    specs
+        .iter()
+        .filter(|s| !s.starts_with('-'))
+        .map(|spec| match spec.split_once('=') {
+            Some((name, version)) if installed.get(name).is_some_and(|v| v == version) => {
+                format!("{name} ({version}) (already up to date)")
+            }
+            Some((name, version)) => format!("{name} ({version})"),
+            None => spec.clone(),
+        })
+        .collect()
+}
+
+pub fn clean_world_file_pins(packages: &[String]) {
     let world_path = format!("{VELLUM_ROOT}/etc/apk/world");
     let content = match fs::read_to_string(&world_path) {
         Ok(c) => c,
         Err(_) => return,
     };
 
-    let new_content: String = content
+    let _ = fs::write(&world_path, strip_world_pins(&content, packages));
+}
+
+// World entries can be `name`, `name=version`, `name@tag` or
+// `name=version@tag`. Entries for `packages` are reduced to the bare name, so
+// a package once added from @testing is tracked from the stable repo again.
+fn strip_world_pins(content: &str, packages: &[String]) -> String {
+    let eol = line_ending(content);
+    let new_content = content
         .lines()
         .map(|line| {
-            for pkg in packages {
-                if line.starts_with(&format!("{pkg}=")) {
-                    return pkg.clone();
-                }
+            let name = line.split(['=', '@']).next().unwrap_or(line);
+            if packages.iter().any(|pkg| pkg == name) {
+                name
+            } else {
+                line
             }
-            line.to_string()
         })
         .collect::<Vec<_>>()
-        .join("\n");
+        .join(eol);
+    new_content + eol
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packages(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
 
-    let _ = fs::write(&world_path, new_content + "\n");
+    #[test]
+    fn pulled_in_dependencies_are_new_and_unrequested() {
+        let before = packages(&["vellum", "libfoo"]);
+        let after = packages(&["vellum", "libfoo", "app", "libbar", "libbaz"]);
+        let requested = packages(&["app=1.0-r0", "libfoo"]);
+        assert_eq!(pulled_in_dependencies(&before, &after, &requested), vec!["libbar", "libbaz"]);
+    }
+
+    #[test]
+    fn preview_marks_installed_versions() {
+        let installed: HashMap<String, String> =
+            [("foo".to_string(), "1.0-r0".to_string())].into_iter().collect();
+        let specs = packages(&["foo=1.0-r0", "bar=2.0-r1", "baz", "--force"]);
+
+        assert_eq!(
+            install_preview(&specs, &installed),
+            vec!["foo (1.0-r0) (already up to date)", "bar (2.0-r1)", "baz"]
+        );
+    }
+
+    fn provider(name: &str, version: &str, provides: &[&str]) -> Package {
+        Package {
+            name: name.to_string(),
+            version: version.into(),
+            provides: packages(provides),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn provides_conflicts_name_installed_providers() {
+        let index = vec![
+            provider("foo", "1.0-r0", &["libbar=1.0", "foo-doc"]),
+            provider("baz", "2.0-r0", &["libbar=2.0"]),
+            provider("qux", "1.0-r0", &["foo-doc"]),
+        ];
+        let installed = vec![
+            ("baz".to_string(), "2.0-r0".to_string()),
+            ("other".to_string(), "1.0-r0".to_string()),
+        ];
+
+        assert_eq!(
+            provides_conflicts(&[&index[0]], &installed, &index),
+            vec![
+                "Warning: foo provides libbar, which is also provided by installed baz. \
+                 Proceeding may remove baz."
+            ]
+        );
+        assert!(provides_conflicts(&[&index[0], &index[1]], &installed, &index).is_empty());
+    }
+
+    #[test]
+    fn strips_version_pins() {
+        let content = "foo=1.0-r0\nbar=2.0-r0\n";
+        assert_eq!(strip_world_pins(content, &packages(&["foo"])), "foo\nbar=2.0-r0\n");
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let content = "foo=1.0-r0\r\nbar=2.0-r0\r\n";
+        assert_eq!(strip_world_pins(content, &packages(&["foo"])), "foo\r\nbar=2.0-r0\r\n");
+    }
+
+    #[test]
+    fn strips_repository_tags() {
+        let content = "foo@testing\nbar=1.0-r0@testing\nbaz@testing\n";
+        assert_eq!(
+            strip_world_pins(content, &packages(&["foo", "bar"])),
+            "foo\nbar\nbaz@testing\n"
+        );
+    }
+
+    #[test]
+    fn leaves_packages_sharing_a_prefix() {
+        let content = "foo-extra@testing\nfoo\n";
+        assert_eq!(strip_world_pins(content, &packages(&["foo"])), content);
+    }
 }
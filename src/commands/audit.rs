@@ -0,0 +1,195 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::process;
+
+use anyhow::{anyhow, Result};
+
+use crate::apk::{compare_package_versions, Apk};
+use crate::util::{http_agent, parse_json_object_array, JsonField};
+
+const ADVISORY_URL: &str = "https://packages.vellum.delivery/advisories.json";
+const USAGE: &str = "Usage: vellum audit [--advisory-url <url>|--local <file>]";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Advisory {
+    name: String,
+    affected_versions: String,
+    severity: String,
+    message: String,
+}
+
+impl Advisory {
+    fn is_serious(&self) -> bool {
+        matches!(self.severity.to_lowercase().as_str(), "critical" | "high")
+    }
+}
+
+pub fn handle_audit(apk: &Apk, args: &[String]) {
+    let mut url = ADVISORY_URL.to_string();
+    let mut local: Option<String> = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--advisory-url" | "--local" => {
+                let Some(value) = iter.next() else {
+                    eprintln!("Error: {arg} requires a value");
+                    process::exit(1);
+                };
+                if arg == "--local" {
+                    local = Some(value.clone());
+                } else {
+                    url = value.clone();
+                }
+            }
+            a => {
+                eprintln!("Unknown audit option: {a}");
+                eprintln!("{USAGE}");
+                process::exit(1);
+            }
+        }
+    }
+
+    let source = local.as_deref().unwrap_or(&url);
+    let advisories = match load_advisories(local.as_deref(), &url) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("Could not load advisories from {source}: {e}");
+            process::exit(1);
+        }
+    };
+
+    let installed = match apk.list_installed_with_versions() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Could not list installed packages: {e}");
+            process::exit(1);
+        }
+    };
+
+    let mut matched = 0;
+    let mut serious = false;
+    for (name, version) in &installed {
+        for adv in advisories.iter().filter(|a| &a.name == name) {
+            if !version_in_range(version, &adv.affected_versions) {
+                continue;
+            }
+            println!(
+                "[{}] {name} {version}: {}",
+                adv.severity.to_uppercase(),
+                adv.message
+            );
+            matched += 1;
+            serious |= adv.is_serious();
+        }
+    }
+
+    if matched == 0 {
+        println!("No known issues in {} installed package(s).", installed.len());
+        return;
+    }
+
+    println!();
+    println!("{matched} advisory match(es) found.");
+    if serious {
+        process::exit(1);
+    }
+}
+
+fn load_advisories(local: Option<&str>, url: &str) -> Result<Vec<Advisory>> {
+    let content = match local {
+        Some(path) => fs::read_to_string(path)?,
+        None => http_agent(url)?
+            .get(url)
+            .call()
+            .map_err(|e| anyhow!("HTTP request failed: {e}"))?
+            .into_string()?,
+    };
+    parse_advisories(&content).ok_or_else(|| anyhow!("malformed advisory file"))
+}
+
+fn parse_advisories(content: &str) -> Option<Vec<Advisory>> {
+    parse_json_object_array(content)?
+        .into_iter()
+        .map(|mut fields| {
+            let mut take = |key: &str| match fields.remove(key) {
+                Some(JsonField::Str(s)) => Some(s),
+                _ => None,
+            };
+            Some(Advisory {
+                name: take("name")?,
+                affected_versions: take("affected_versions").unwrap_or_default(),
+                severity: take("severity").unwrap_or_else(|| "unknown".to_string()),
+                message: take("message").unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+// A range is a space-separated list of constraints that must all hold, such
+// as `>=1.0 <1.2.3`. A bare version matches exactly; `*` or an empty range
+// matches every version.
+fn version_in_range(version: &str, range: &str) -> bool {
+    range.split_whitespace().all(|constraint| {
+        if constraint == "*" {
+            return true;
+        }
+        let (allowed, bound): (&[Ordering], &str) =
+            if let Some(v) = constraint.strip_prefix(">=") {
+                (&[Ordering::Greater, Ordering::Equal], v)
+            } else if let Some(v) = constraint.strip_prefix("<=") {
+                (&[Ordering::Less, Ordering::Equal], v)
+            } else if let Some(v) = constraint.strip_prefix('>') {
+                (&[Ordering::Greater], v)
+            } else if let Some(v) = constraint.strip_prefix('<') {
+                (&[Ordering::Less], v)
+            } else {
+                (&[Ordering::Equal], constraint.strip_prefix('=').unwrap_or(constraint))
+            };
+        allowed.contains(&compare_package_versions(version, bound))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_in_range_bounds() {
+        assert!(version_in_range("1.1-r0", ">=1.0 <1.2"));
+        assert!(version_in_range("1.0-r0", ">=1.0-r0 <1.2"));
+        assert!(!version_in_range("1.2-r0", ">=1.0 <1.2"));
+        assert!(!version_in_range("0.9-r0", ">=1.0"));
+        assert!(version_in_range("1.0-r0", "<=1.0-r0"));
+        assert!(version_in_range("2.0-r1", ">2.0-r0"));
+    }
+
+    #[test]
+    fn version_in_range_exact_and_wildcard() {
+        assert!(version_in_range("1.0-r0", "1.0-r0"));
+        assert!(version_in_range("1.0-r0", "=1.0-r0"));
+        assert!(!version_in_range("1.0-r1", "1.0-r0"));
+        assert!(version_in_range("3.4-r2", "*"));
+        assert!(version_in_range("3.4-r2", ""));
+    }
+
+    #[test]
+    fn parse_advisories_reads_fields() {
+        let content = r#"[
+            {"name":"foo","affected_versions":"<1.2","severity":"high","message":"bad"},
+            {"name":"bar"}
+        ]"#;
+        let advisories = parse_advisories(content).unwrap();
+
+        assert_eq!(advisories.len(), 2);
+        assert_eq!(advisories[0].affected_versions, "<1.2");
+        assert!(advisories[0].is_serious());
+        assert_eq!(advisories[1].severity, "unknown");
+        assert!(!advisories[1].is_serious());
+    }
+
+    #[test]
+    fn parse_advisories_requires_name() {
+        assert!(parse_advisories(r#"[{"severity":"low"}]"#).is_none());
+    }
+}
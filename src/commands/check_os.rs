@@ -1,33 +1,62 @@
 use std::collections::HashMap;
-use std::fs;
 use std::process;
 
-use crate::apk::{fetch_remote_index, parse_index_tar_gz, Apk, Package};
+use super::index::{get_index, get_remote_index};
+use crate::apk::{
+    check_os_compatibility, compare_package_versions, parse_index_tar_gz, Apk, Package,
+};
+use crate::config::Config;
 use crate::constants::{VELLUM_ROOT, VIRTUAL_PKGS};
-use crate::device::get_apk_arch;
+use crate::device::{get_apk_arch, get_device_type};
+use crate::util::json_string;
+
+pub fn handle_check_os(apk: &Apk, config: &Config, args: &[String]) {
+    let force_refresh = args.iter().any(|a| a == "--force-refresh" || a == "--no-cache");
+    let force_remote = args.iter().any(|a| a == "--force-remote");
+    let json = args.iter().any(|a| a == "--json");
+    let Some(target_os) = args.iter().find(|a| !a.starts_with('-')) else {
+        eprintln!("Usage: vellum check-os [--no-cache|--force-remote] [--json] <version>");
+        eprintln!("Check if installed packages are compatible with a given OS version.");
+        process::exit(1);
+    };
 
-pub fn handle_check_os(apk: &Apk, target_os: &str) {
-    println!("Checking package compatibility with OS {target_os}...\n");
+    if !json {
+        println!("Checking package compatibility with OS {target_os}...\n");
+    }
 
-    let installed = match apk.list_installed() {
+    let installed = match apk.list_installed_with_versions() {
         Ok(pkgs) => pkgs,
         Err(_) => {
             eprintln!("Could not list installed packages.");
             process::exit(1);
         }
     };
+    let installed_versions: HashMap<&str, &str> = installed
+        .iter()
+        .map(|(name, version)| (name.as_str(), version.as_str()))
+        .collect();
 
     let user_pkgs: Vec<String> = installed
-        .into_iter()
+        .iter()
+        .map(|(name, _)| name.clone())
         .filter(|p| !VIRTUAL_PKGS.contains(&p.as_str()))
         .collect();
 
     if user_pkgs.is_empty() {
-        println!("No user packages installed.");
+        if json {
+            println!("{{\"compatible\":[],\"incompatible\":[],\"no_constraint\":[]}}");
+        } else {
+            println!("No user packages installed.");
+        }
         return;
     }
 
-    let index = match get_index() {
+    let index = if force_remote {
+        get_remote_index(config)
+    } else {
+        get_index(None, config, force_refresh)
+    };
+    let index = match index {
         Ok(idx) => idx,
         Err(e) => {
             eprintln!("Could not get package index: {e}");
@@ -35,41 +64,75 @@ pub fn handle_check_os(apk: &Apk, target_os: &str) {
         }
     };
 
+    let result = check_os_compatibility(target_os, &user_pkgs, &index, Some(&get_apk_arch()));
+    if let Err(msg) = check_device_package(target_os) {
+        eprintln!("warning: {msg}");
+        eprintln!("The local repository may need regenerating; run 'vellum doctor'.");
+        if !json {
+            eprintln!();
+        }
+    }
+    let compatible = &result.compatible;
+    let incompatible = &result.incompatible;
+
     let mut pkg_versions: HashMap<&str, Vec<&Package>> = HashMap::new();
     for pkg in &index {
         pkg_versions.entry(&pkg.name).or_default().push(pkg);
     }
 
-    let mut compatible = Vec::new();
-    let mut incompatible = Vec::new();
-    let mut no_constraint = Vec::new();
-
-    for pkg in &user_pkgs {
-        let versions = match pkg_versions.get(pkg.as_str()) {
-            Some(v) => v,
-            None => continue,
-        };
-
-        let has_os_constraint = versions.iter().any(|v| {
-            let (min, max) = v.get_os_constraints();
-            min.is_some() || max.is_some()
-        });
-
-        let has_compatible_version = versions.iter().any(|v| v.is_compatible_with_os(target_os));
+    let no_constraint: Vec<&String> = user_pkgs
+        .iter()
+        .filter(|pkg| !incompatible.contains(pkg))
+        .filter(|pkg| {
+            pkg_versions.get(pkg.as_str()).is_some_and(|versions| {
+                versions.iter().all(|v| !v.has_os_constraint())
+            })
+        })
+        .collect();
 
-        if !has_os_constraint {
-            no_constraint.push(pkg.clone());
-        } else if has_compatible_version {
-            compatible.push(pkg.clone());
-        } else {
-            incompatible.push(pkg.clone());
+    if json {
+        let compatible_entries: Vec<String> = compatible
+            .iter()
+            .map(|pkg| json_entry(pkg, result.compatible_versions.get(pkg).map(String::as_str)))
+            .collect();
+        let incompatible_entries: Vec<String> =
+            incompatible.iter().map(|pkg| json_entry(pkg, None)).collect();
+        let no_constraint_entries: Vec<String> = no_constraint
+            .iter()
+            .map(|pkg| {
+                let newest = pkg_versions.get(pkg.as_str()).and_then(|versions| {
+                    versions
+                        .iter()
+                        .max_by(|a, b| a.version.cmp(&b.version))
+                        .map(|p| p.version.as_str())
+                });
+                json_entry(pkg, newest)
+            })
+            .collect();
+
+        println!(
+            "{{\"compatible\":[{}],\"incompatible\":[{}],\"no_constraint\":[{}]}}",
+            compatible_entries.join(","),
+            incompatible_entries.join(","),
+            no_constraint_entries.join(",")
+        );
+        if !incompatible.is_empty() {
+            process::exit(1);
         }
+        return;
     }
 
     if !compatible.is_empty() {
         println!("Compatible packages:");
-        for pkg in &compatible {
-            println!("  + {pkg}");
+        for pkg in compatible {
+            let current = installed_versions.get(pkg.as_str()).copied();
+            let target = result.compatible_versions.get(pkg);
+            match (current, target) {
+                (Some(current), Some(target)) => {
+                    println!("  + {pkg}  {}", version_change(current, target));
+                }
+                _ => println!("  + {pkg}"),
+            }
         }
         println!();
     }
@@ -84,8 +147,11 @@ pub fn handle_check_os(apk: &Apk, target_os: &str) {
 
     if !incompatible.is_empty() {
         println!("Incompatible packages (no version available for this OS):");
-        for pkg in &incompatible {
-            println!("  x {pkg}");
+        for pkg in incompatible {
+            match result.incompatible_nearest.get(pkg) {
+                Some(Some(os)) => println!("  x {pkg} (available for OS {os} and later)"),
+                _ => println!("  x {pkg}"),
+            }
         }
         println!();
         process::exit(1);
@@ -94,42 +160,53 @@ pub fn handle_check_os(apk: &Apk, target_os: &str) {
     println!("All packages are compatible.");
 }
 
-fn get_index() -> anyhow::Result<Vec<Package>> {
-    let cache_dir = format!("{VELLUM_ROOT}/etc/apk/cache");
-
-    if let Ok(entries) = fs::read_dir(&cache_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.starts_with("APKINDEX.") && name.ends_with(".tar.gz") {
-                    if let Some(path_str) = path.to_str() {
-                        return parse_index_tar_gz(path_str);
-                    }
-                }
-            }
-        }
+// Device packages are generated locally rather than fetched, so they never
+// show up in the remote index checked above.
+fn check_device_package(target_os: &str) -> Result<(), String> {
+    let Some(device) = get_device_type() else {
+        return Ok(());
+    };
+    let index_path = format!("{VELLUM_ROOT}/local-repo/{}/APKINDEX.tar.gz", device.arch);
+    let local = parse_index_tar_gz(&index_path)
+        .map_err(|e| format!("could not read local repository index: {e}"))?;
+
+    let Some(pkg) = local.iter().find(|p| p.name == device.id) else {
+        return Err(format!("device package {} is missing from the local repository", device.id));
+    };
+    if !pkg.is_compatible_with_os(target_os) {
+        return Err(format!(
+            "device package {} {} does not support OS {target_os}",
+            pkg.name, pkg.version
+        ));
     }
+    Ok(())
+}
 
-    let repo_url = get_repo_url().ok_or_else(|| {
-        anyhow::anyhow!("no cached index and could not determine repository URL")
-    })?;
+fn version_change(current: &str, target: &str) -> String {
+    if compare_package_versions(current, target).is_eq() {
+        "(up to date)".to_string()
+    } else {
+        format!("({current} -> {target})")
+    }
+}
 
-    let arch = get_apk_arch();
-    fetch_remote_index(&repo_url, &arch)
+fn json_entry(name: &str, best_version: Option<&str>) -> String {
+    let best = match best_version {
+        Some(v) => json_string(v),
+        None => "null".to_string(),
+    };
+    format!("{{\"name\":{},\"best_version\":{best}}}", json_string(name))
 }
 
-fn get_repo_url() -> Option<String> {
-    let repos_file = format!("{VELLUM_ROOT}/etc/apk/repositories");
-    let content = fs::read_to_string(repos_file).ok()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for line in content.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
-        if !line.contains("local-repo") {
-            return Some(line.to_string());
-        }
+    #[test]
+    fn version_change_shows_upgrades_and_current_packages() {
+        assert_eq!(version_change("1.0-r0", "1.0-r0"), "(up to date)");
+        assert_eq!(version_change("1.0-r0", "1.2-r0"), "(1.0-r0 -> 1.2-r0)");
+        assert_eq!(version_change("1.0-r0", "1.0-r1"), "(1.0-r0 -> 1.0-r1)");
+        assert_eq!(version_change("2.0-r0", "1.5-r0"), "(2.0-r0 -> 1.5-r0)");
     }
-    None
 }
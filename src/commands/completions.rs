@@ -0,0 +1,271 @@
+use std::process;
+
+const USAGE: &str = "Usage: vellum completions <bash|zsh|fish>";
+
+// Top-level commands and the flags each accepts. Keep in sync with main.rs.
+const COMMANDS: &[(&str, &[&str])] = &[
+    ("add", &["--no-cache", "--verbose", "--simulate", "--dry-run", "--yes"]),
+    ("install", &["--no-cache", "--verbose", "--simulate", "--dry-run", "--yes"]),
+    ("del", &["--purge", "--yes"]),
+    ("remove", &["--purge", "--yes"]),
+    ("purge", &["--yes"]),
+    (
+        "upgrade",
+        &[
+            "--yes",
+            "--dry-run",
+            "--skip",
+            "--ignore-hold",
+            "--ignore-disk-check",
+            "--no-cache",
+            "--force-remote",
+            "--no-reenable",
+            "--auto-reenable",
+            "--unpin-all",
+        ],
+    ),
+    ("rollback", &["--yes"]),
+    ("fetch", &["--os"]),
+    ("outdated", &["--all", "--json"]),
+    ("list", &["--installed", "--available", "--upgradeable", "--sort-by-age"]),
+    ("hold", &[]),
+    ("unhold", &[]),
+    ("pin", &[]),
+    ("unpin", &[]),
+    ("log", &["--since", "--pkg"]),
+    ("diff", &["--since"]),
+    ("why", &["--depth"]),
+    ("graph", &["--depth", "--installed-only", "--output"]),
+    ("search", &["--description", "--exact", "--json"]),
+    ("info", &["--json"]),
+    ("show", &[]),
+    ("check-os", &["--json", "--no-cache", "--force-remote"]),
+    ("reenable", &["--quiet", "--dry-run", "--timeout"]),
+    ("repo", &[]),
+    ("key", &[]),
+    ("testing", &[]),
+    ("doctor", &[]),
+    ("env", &[]),
+    ("stats", &["--json"]),
+    ("verify", &["--fix"]),
+    ("audit", &["--local", "--advisory-url"]),
+    ("freeze", &[]),
+    ("restore", &["--dry-run", "--yes"]),
+    ("self", &[]),
+    ("completions", &[]),
+    ("config", &[]),
+];
+
+// Commands whose first argument is a subcommand, and the flags those take.
+const GROUPS: &[(&str, &[&str], &[&str])] = &[
+    (
+        "testing",
+        &["enable", "disable", "status", "list"],
+        &["--force", "--downgrade", "--yes", "--installed"],
+    ),
+    ("self", &["uninstall"], &["--all", "--yes"]),
+    ("repo", &["add", "add-tagged", "remove", "list", "add-local"], &["--tag"]),
+    ("key", &["generate"], &["--bits", "--force"]),
+    ("completions", &["bash", "zsh", "fish"], &[]),
+    ("config", &["get", "set", "list"], &[]),
+];
+
+const INSTALLABLE: &[&str] = &["add", "install", "fetch", "info", "show"];
+const INSTALLED: &[&str] =
+    &["del", "remove", "purge", "hold", "unhold", "pin", "unpin", "verify", "why", "graph"];
+
+// Package rows from `vellum list` end with a `[repo]` tag; anything else is a
+// message such as "No packages found.".
+const LIST_PACKAGES: &str = "vellum list $1 2>/dev/null | awk '/\\]$/ {print $1}'";
+
+pub fn handle_completions(shell: &str) {
+    let script = match shell {
+        "bash" => bash(),
+        "zsh" => zsh(),
+        "fish" => fish(),
+        _ => {
+            eprintln!("{USAGE}");
+            process::exit(1);
+        }
+    };
+    print!("{script}");
+}
+
+fn command_names() -> String {
+    COMMANDS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(" ")
+}
+
+fn flags_for(cmd: &str) -> String {
+    let command_flags = COMMANDS.iter().filter(|(name, _)| *name == cmd).map(|(_, f)| f);
+    let group_flags = GROUPS.iter().filter(|(name, _, _)| *name == cmd).map(|(_, _, f)| f);
+    let flags: Vec<&str> =
+        command_flags.chain(group_flags).flat_map(|f| f.iter().copied()).collect();
+    flags.join(" ")
+}
+
+fn bash() -> String {
+    let mut groups = String::new();
+    for (name, subs, _) in GROUPS {
+        groups.push_str(&format!(
+            "        {name}) if [ \"$COMP_CWORD\" -eq 2 ]; then\n            \
+             COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return\n        fi ;;\n",
+            subs.join(" ")
+        ));
+    }
+    let mut flags = String::new();
+    for (name, _) in COMMANDS.iter().filter(|(n, _)| !flags_for(n).is_empty()) {
+        flags.push_str(&format!("            {name}) flags=\"{}\" ;;\n", flags_for(name)));
+    }
+
+    format!(
+        "# bash completion for vellum
+_vellum_packages() {{
+    {LIST_PACKAGES}
+}}
+
+_vellum() {{
+    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"
+    local cmd=\"${{COMP_WORDS[1]}}\"
+    COMPREPLY=()
+
+    if [ \"$COMP_CWORD\" -eq 1 ]; then
+        COMPREPLY=($(compgen -W \"{commands}\" -- \"$cur\"))
+        return
+    fi
+
+    case \"$cmd\" in
+{groups}    esac
+
+    if [[ \"$cur\" == -* ]]; then
+        local flags=\"\"
+        case \"$cmd\" in
+{flags}        esac
+        COMPREPLY=($(compgen -W \"$flags\" -- \"$cur\"))
+        return
+    fi
+
+    local mode=\"\"
+    case \"$cmd\" in
+        {installable}) mode=--available ;;
+        {installed}) mode=--installed ;;
+        freeze|restore) COMPREPLY=($(compgen -f -- \"$cur\")) ;;
+    esac
+    if [ -n \"$mode\" ]; then
+        COMPREPLY=($(compgen -W \"$(_vellum_packages $mode)\" -- \"$cur\"))
+    fi
+}}
+
+complete -F _vellum vellum
+",
+        commands = command_names(),
+        installable = INSTALLABLE.join("|"),
+        installed = INSTALLED.join("|"),
+    )
+}
+
+fn zsh() -> String {
+    let mut groups = String::new();
+    for (name, subs, _) in GROUPS {
+        groups.push_str(&format!(
+            "        {name}) if (( CURRENT == 3 )); then compadd -- {}; return; fi ;;\n",
+            subs.join(" ")
+        ));
+    }
+    let mut flags = String::new();
+    for (name, _) in COMMANDS.iter().filter(|(n, _)| !flags_for(n).is_empty()) {
+        flags.push_str(&format!("            {name}) compadd -- {} ;;\n", flags_for(name)));
+    }
+
+    format!(
+        "#compdef vellum
+_vellum_packages() {{
+    {LIST_PACKAGES}
+}}
+
+_vellum() {{
+    local cmd=${{words[2]}}
+
+    if (( CURRENT == 2 )); then
+        compadd -- {commands}
+        return
+    fi
+
+    case $cmd in
+{groups}    esac
+
+    if [[ $PREFIX == -* ]]; then
+        case $cmd in
+{flags}        esac
+        return
+    fi
+
+    case $cmd in
+        {installable}) compadd -- ${{(f)\"$(_vellum_packages --available)\"}} ;;
+        {installed}) compadd -- ${{(f)\"$(_vellum_packages --installed)\"}} ;;
+        freeze|restore) _files ;;
+    esac
+}}
+
+compdef _vellum vellum
+",
+        commands = command_names(),
+        installable = INSTALLABLE.join("|"),
+        installed = INSTALLED.join("|"),
+    )
+}
+
+fn fish() -> String {
+    let mut script = String::from(
+        "# fish completion for vellum\ncomplete -c vellum -f\n\
+         complete -c vellum -n __fish_use_subcommand -a '",
+    );
+    script.push_str(&command_names());
+    script.push_str("'\n");
+
+    for (name, subs, _) in GROUPS {
+        script.push_str(&format!(
+            "complete -c vellum -n '__fish_seen_subcommand_from {name}; and not \
+             __fish_seen_subcommand_from {subs}' -a '{subs}'\n",
+            subs = subs.join(" ")
+        ));
+    }
+    for (name, _) in COMMANDS {
+        for flag in flags_for(name).split_whitespace() {
+            script.push_str(&format!(
+                "complete -c vellum -n '__fish_seen_subcommand_from {name}' -l {}\n",
+                flag.trim_start_matches("--")
+            ));
+        }
+    }
+    for (cmds, mode) in [(INSTALLABLE, "--available"), (INSTALLED, "--installed")] {
+        script.push_str(&format!(
+            "complete -c vellum -n '__fish_seen_subcommand_from {}' \
+             -a \"(vellum list {mode} 2>/dev/null | awk '/\\]\\$/ {{print \\$1}}')\"\n",
+            cmds.join(" ")
+        ));
+    }
+    script.push_str("complete -c vellum -n '__fish_seen_subcommand_from freeze restore' -F\n");
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bash_completes_subcommand_groups() {
+        let script = bash();
+        assert!(script.contains("testing) if [ \"$COMP_CWORD\" -eq 2 ]"));
+        assert!(script.contains("\"enable disable status list\""));
+        assert!(script.contains("\"uninstall\""));
+        assert!(script.contains("\"add add-tagged remove list add-local\""));
+        assert!(script.contains("complete -F _vellum vellum"));
+    }
+
+    #[test]
+    fn flags_include_group_flags() {
+        assert_eq!(flags_for("info"), "--json");
+        assert_eq!(flags_for("self"), "--all --yes");
+        assert_eq!(flags_for("doctor"), "");
+    }
+}
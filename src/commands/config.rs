@@ -0,0 +1,61 @@
+use std::process;
+
+use crate::config::{Config, KEYS};
+
+const USAGE: &str = "Usage: vellum config <get <key>|set <key> <value>|list>";
+
+pub fn handle_config(vellum_root: &str, args: &[String]) {
+    match args {
+        [cmd, key] if cmd == "get" => handle_config_get(vellum_root, key),
+        [cmd, key, value] if cmd == "set" => handle_config_set(vellum_root, key, value),
+        [cmd] if cmd == "list" => handle_config_list(vellum_root),
+        _ => {
+            eprintln!("{USAGE}");
+            eprintln!("Keys: {}", KEYS.join(", "));
+            process::exit(1);
+        }
+    }
+}
+
+fn handle_config_get(vellum_root: &str, key: &str) {
+    match load(vellum_root).get(key) {
+        Ok(Some(value)) => println!("{value}"),
+        Ok(None) => println!("(unset)"),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+fn handle_config_set(vellum_root: &str, key: &str, value: &str) {
+    let mut config = load(vellum_root);
+    let result = config.set(key, value).and_then(|()| config.save_key(vellum_root, key));
+    if let Err(e) = result {
+        eprintln!("Error: {e}");
+        process::exit(1);
+    }
+    let value = config.get(key).ok().flatten().unwrap_or_default();
+    println!("Set {key} to {value} in {}", Config::path(vellum_root).display());
+}
+
+fn handle_config_list(vellum_root: &str) {
+    let config = load(vellum_root);
+    for key in KEYS {
+        let value = config.get(key).ok().flatten().unwrap_or_else(|| "(unset)".to_string());
+        let default = Config::default_value(key).unwrap_or_default();
+        println!("{key:<18} {value:<40} (default: {default})");
+    }
+}
+
+// A config file that doesn't parse can't be edited safely either, so every
+// subcommand stops on it instead of falling back to the defaults.
+fn load(vellum_root: &str) -> Config {
+    match Config::load(vellum_root) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error reading {}: {e}", Config::path(vellum_root).display());
+            process::exit(1);
+        }
+    }
+}
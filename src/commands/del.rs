@@ -1,15 +1,14 @@
+use std::io::{self, BufRead, Write};
 use std::process;
 
 use crate::apk::Apk;
+use crate::constants::VIRTUAL_PKGS;
+use crate::state::State;
 
-pub fn handle_del(apk: &Apk, args: &[String]) {
-    for arg in args {
-        if arg == "vellum" {
-            eprintln!("Error: Cannot add/remove vellum package directly.");
-            eprintln!("Use 'vellum self uninstall' to remove vellum.");
-            process::exit(1);
-        }
-    }
+pub fn handle_del(state: &State, apk: &Apk, args: &[String]) {
+    reject_protected(args);
+
+    let args = confirm_reverse_dependencies(apk, args);
 
     let mut cmd_args = vec!["del"];
     cmd_args.extend(args.iter().map(|s| s.as_str()));
@@ -17,23 +16,109 @@ pub fn handle_del(apk: &Apk, args: &[String]) {
     if apk.run(&cmd_args).is_err() {
         process::exit(1);
     }
+
+    record_removal(state, "del", &args);
 }
 
-pub fn handle_purge(apk: &Apk, args: &[String]) {
+pub fn handle_purge(state: &State, apk: &Apk, args: &[String]) {
+    reject_protected(args);
+
+    let args = confirm_reverse_dependencies(apk, args);
+
+    std::env::set_var("VELLUM_PURGE", "1");
+
+    let mut cmd_args = vec!["del", "--purge", "--preserve-env"];
+    cmd_args.extend(args.iter().map(|s| s.as_str()));
+
+    if apk.run(&cmd_args).is_err() {
+        process::exit(1);
+    }
+
+    record_removal(state, "purge", &args);
+}
+
+// vellum and its virtual packages are managed by vellum itself; removing
+// them by hand breaks OS and device tracking.
+fn reject_protected(args: &[String]) {
     for arg in args {
         if arg == "vellum" {
             eprintln!("Error: Cannot add/remove vellum package directly.");
             eprintln!("Use 'vellum self uninstall' to remove vellum.");
             process::exit(1);
         }
+        if arg == "remarkable-os" {
+            eprintln!("Error: Cannot remove remarkable-os.");
+            eprintln!(
+                "This package tracks your OS version; removing it will cause reinstallation on next run."
+            );
+            process::exit(1);
+        }
+        if VIRTUAL_PKGS.contains(&arg.as_str()) {
+            eprintln!("Error: Cannot remove device package {arg}.");
+            eprintln!("Use 'vellum self uninstall' to remove all vellum components.");
+            process::exit(1);
+        }
     }
+}
 
-    std::env::set_var("VELLUM_PURGE", "1");
+// Warns about installed packages that depend on what is being removed and
+// asks the user to confirm, unless `-y`/`--yes` was given. Returns the
+// arguments with those flags stripped, ready for `apk del`.
+fn confirm_reverse_dependencies(apk: &Apk, args: &[String]) -> Vec<String> {
+    let assume_yes = args.iter().any(|a| a == "-y" || a == "--yes");
+    let args: Vec<String> = args
+        .iter()
+        .filter(|a| *a != "-y" && *a != "--yes")
+        .cloned()
+        .collect();
+    let removing: Vec<&String> = args.iter().filter(|a| !a.starts_with('-')).collect();
 
-    let mut cmd_args = vec!["del", "--purge", "--preserve-env"];
-    cmd_args.extend(args.iter().map(|s| s.as_str()));
+    let mut has_dependents = false;
+    for pkg in &removing {
+        let Ok(rdeps) = apk.get_reverse_dependencies(pkg) else {
+            continue;
+        };
+        let dependents: Vec<String> = rdeps
+            .into_iter()
+            .filter(|d| !VIRTUAL_PKGS.contains(&d.as_str()))
+            .filter(|d| !removing.contains(&d))
+            .collect();
+        if !dependents.is_empty() {
+            eprintln!("warning: {pkg} is required by: {}", dependents.join(", "));
+            has_dependents = true;
+        }
+    }
 
-    if apk.run(&cmd_args).is_err() {
-        process::exit(1);
+    if has_dependents && !assume_yes {
+        print!("Dependent packages may be removed or broken. Continue? [y/N] ");
+        let _ = io::stdout().flush();
+
+        let stdin = io::stdin();
+        let mut line = String::new();
+        let _ = stdin.lock().read_line(&mut line);
+        let confirm = line.trim().to_lowercase();
+
+        if confirm != "y" && confirm != "yes" {
+            println!("Aborted.");
+            process::exit(1);
+        }
+    }
+
+    args
+}
+
+fn record_removal(state: &State, op: &str, args: &[String]) {
+    let packages: Vec<String> = args.iter().filter(|a| !a.starts_with('-')).cloned().collect();
+    let os = state.get_os_version().unwrap_or_default();
+    if let Err(e) = state.append_install_event(op, &packages, &os) {
+        eprintln!("warning: failed to record install log: {e}");
+    }
+    for pkg in &packages {
+        if let Err(e) = state.remove_install_time(pkg) {
+            eprintln!("warning: failed to clear install time for {pkg}: {e}");
+        }
+        if let Err(e) = state.remove_manual(pkg) {
+            eprintln!("warning: failed to clear manual install mark for {pkg}: {e}");
+        }
     }
 }
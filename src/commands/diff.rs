@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::process;
+
+use crate::apk::Apk;
+use crate::state::State;
+use crate::util::format_rfc3339;
+
+pub fn handle_diff(apk: &Apk, state: &State, args: &[String]) {
+    let mut since: Option<&str> = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--since" => match iter.next() {
+                Some(s) => since = Some(s),
+                None => {
+                    eprintln!("Error: --since requires a timestamp");
+                    process::exit(1);
+                }
+            },
+            a => {
+                eprintln!("Unknown diff option: {a}");
+                eprintln!("Usage: vellum diff [--since <YYYY-MM-DD|unix time>]");
+                process::exit(1);
+            }
+        }
+    }
+
+    let snapshots = match state.list_snapshots() {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Could not read snapshots: {e}");
+            process::exit(1);
+        }
+    };
+    if snapshots.is_empty() {
+        println!("No snapshot to compare against.");
+        println!("A snapshot of installed packages is saved after each 'vellum upgrade'.");
+        return;
+    }
+
+    // With --since, compare against the first snapshot taken at or after that
+    // time; otherwise against the latest one.
+    let snapshot = match since {
+        Some(s) => match snapshots.iter().find(|ts| snapshot_is_after(**ts, s)) {
+            Some(ts) => *ts,
+            None => {
+                eprintln!("No snapshot taken since {s}.");
+                process::exit(1);
+            }
+        },
+        None => snapshots[snapshots.len() - 1],
+    };
+
+    let old = match state.read_snapshot(snapshot) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Could not read snapshot: {e}");
+            process::exit(1);
+        }
+    };
+    let current = match apk.list_installed_with_versions() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Could not list installed packages: {e}");
+            process::exit(1);
+        }
+    };
+
+    let changes = diff_packages(&old, &current);
+    if changes.is_empty() {
+        println!("No changes since {}.", format_rfc3339(snapshot));
+        return;
+    }
+    for line in changes {
+        println!("{line}");
+    }
+}
+
+pub fn save_snapshot(apk: &Apk, state: &State) {
+    let result = apk
+        .list_installed_with_versions()
+        .and_then(|p| state.write_snapshot(&p));
+    if let Err(e) = result {
+        eprintln!("warning: failed to save package snapshot: {e}");
+    }
+}
+
+// `since` is either unix seconds or an RFC3339 prefix such as `2024-01-31`,
+// which compares correctly against a formatted timestamp as a plain string.
+fn snapshot_is_after(ts: u64, since: &str) -> bool {
+    match since.parse::<u64>() {
+        Ok(secs) => ts >= secs,
+        Err(_) => format_rfc3339(ts).as_str() >= since,
+    }
+}
+
+/// `+name version` for added packages, `-name version` for removed ones and
+/// `~name old -> new` for version changes, sorted by name.
+fn diff_packages(old: &[(String, String)], new: &[(String, String)]) -> Vec<String> {
+    let old: BTreeMap<&str, &str> = old.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+    let new: BTreeMap<&str, &str> = new.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+
+    let mut names: Vec<&str> = old.keys().chain(new.keys()).copied().collect();
+    names.sort_unstable();
+    names.dedup();
+
+    names
+        .into_iter()
+        .filter_map(|name| match (old.get(name), new.get(name)) {
+            (None, Some(v)) => Some(format!("+{name} {v}")),
+            (Some(v), None) => Some(format!("-{name} {v}")),
+            (Some(a), Some(b)) if a != b => Some(format!("~{name} {a} -> {b}")),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pkgs(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_changed() {
+        let old = pkgs(&[("bar", "1.0-r0"), ("foo", "1.0-r0"), ("same", "2.0-r0")]);
+        let new = pkgs(&[("baz", "0.1-r0"), ("foo", "1.1-r0"), ("same", "2.0-r0")]);
+
+        assert_eq!(
+            diff_packages(&old, &new),
+            vec!["-bar 1.0-r0", "+baz 0.1-r0", "~foo 1.0-r0 -> 1.1-r0"]
+        );
+    }
+
+    #[test]
+    fn diff_of_identical_lists_is_empty() {
+        let list = pkgs(&[("foo", "1.0-r0")]);
+        assert!(diff_packages(&list, &list).is_empty());
+    }
+
+    #[test]
+    fn since_accepts_unix_time_and_dates() {
+        // 2024-01-31T00:00:00Z
+        let ts = 1706659200;
+        assert!(snapshot_is_after(ts, "1706659200"));
+        assert!(!snapshot_is_after(ts, "1706659201"));
+        assert!(snapshot_is_after(ts, "2024-01-31"));
+        assert!(!snapshot_is_after(ts, "2024-02-01"));
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process;
+
+use crate::apk::Apk;
+use crate::constants::VELLUM_VERSION;
+use crate::device::{get_apk_arch, get_device_type, get_os_version};
+use crate::state::State;
+
+type Check = Result<(), String>;
+
+pub fn handle_doctor(apk: &Apk, vellum_root: &str) {
+    let checks: Vec<(&str, Check)> = vec![
+        ("vellum binary", check_executable(&format!("{vellum_root}/bin/vellum"))),
+        ("apk binary", check_executable(&apk.bin_path().to_string_lossy())),
+        ("repositories file", check_repositories(vellum_root)),
+        ("local repository index", check_local_repo(vellum_root)),
+        ("recorded OS version", check_os_version(vellum_root)),
+        ("virtual packages", check_virtual_packages(apk)),
+        ("signing key", check_exists(&format!("{vellum_root}/etc/apk/keys/local.rsa"))),
+        ("post-os-upgrade hooks", check_hooks_dir(vellum_root)),
+    ];
+
+    let device = get_device_type();
+    let state = State::new(vellum_root);
+    let repo_url = state.get_repo_url().ok();
+    let last_upgrade = state.get_last_upgrade_report().ok().flatten();
+    if let Some(device) = &device {
+        println!("Device: {} ({})", device.name, device.arch);
+        if !device.machine_string.is_empty() {
+            println!("Hardware: {}", device.machine_string);
+        }
+    }
+    if let Some(url) = &repo_url {
+        println!("Repository: {url}");
+    }
+    if let Some(report) = &last_upgrade {
+        println!(
+            "Last upgrade: {} ({} package(s), {} failed hook(s))",
+            report.timestamp,
+            report.packages_upgraded.len(),
+            report.hooks_failed.len()
+        );
+    }
+    if device.is_some() || repo_url.is_some() || last_upgrade.is_some() {
+        println!();
+    }
+
+    for warning in state_version_warnings(&state) {
+        eprintln!("warning: {warning}");
+    }
+
+    let mut failed = false;
+    for (name, result) in &checks {
+        match result {
+            Ok(()) => println!("[ OK ] {name}"),
+            Err(msg) => {
+                println!("[FAIL] {name}: {msg}");
+                failed = true;
+            }
+        }
+    }
+
+    println!();
+    if failed {
+        println!("Some checks failed.");
+        process::exit(1);
+    }
+    println!("All checks passed.");
+}
+
+// State files written by a different major version of vellum may not mean
+// what this version expects, so they are worth pointing out.
+fn state_version_warnings(state: &State) -> Vec<String> {
+    let Some(current) = major_version(VELLUM_VERSION) else {
+        return Vec::new();
+    };
+    ["osver", "device", "repo-url"]
+        .into_iter()
+        .filter_map(|key| {
+            let (_, writer) = state.read_with_version(key).ok()?;
+            let writer = writer?;
+            (major_version(&writer)? != current).then(|| {
+                format!(
+                    "state file {key} was written by vellum {writer} (running {VELLUM_VERSION})"
+                )
+            })
+        })
+        .collect()
+}
+
+// `git describe` output such as `v1.2.0-3-gabcdef`, or a bare commit hash
+// for untagged builds, which has no major version.
+fn major_version(version: &str) -> Option<u64> {
+    let version = version.strip_prefix('v').unwrap_or(version);
+    version.split(['.', '-']).next()?.parse().ok()
+}
+
+fn check_exists(path: &str) -> Check {
+    if Path::new(path).exists() {
+        Ok(())
+    } else {
+        Err(format!("{path} not found"))
+    }
+}
+
+fn check_executable(path: &str) -> Check {
+    let metadata = fs::metadata(path).map_err(|_| format!("{path} not found"))?;
+    if metadata.permissions().mode() & 0o111 == 0 {
+        return Err(format!("{path} is not executable"));
+    }
+    Ok(())
+}
+
+fn check_repositories(vellum_root: &str) -> Check {
+    let path = format!("{vellum_root}/etc/apk/repositories");
+    let content = fs::read_to_string(&path).map_err(|_| format!("{path} not found"))?;
+
+    let has_remote = content.lines().map(str::trim).any(|line| {
+        !line.is_empty() && !line.starts_with('#') && !line.contains("local-repo")
+    });
+    if has_remote {
+        Ok(())
+    } else {
+        Err(format!("{path} has no remote repository"))
+    }
+}
+
+fn check_local_repo(vellum_root: &str) -> Check {
+    let repo_dir = format!("{vellum_root}/local-repo/{}", get_apk_arch());
+    if !Path::new(&repo_dir).is_dir() {
+        return Err(format!("{repo_dir} not found"));
+    }
+    check_exists(&format!("{repo_dir}/APKINDEX.tar.gz"))
+}
+
+fn check_os_version(vellum_root: &str) -> Check {
+    let detected = get_os_version().map_err(|e| e.to_string())?;
+    let recorded = State::new(vellum_root)
+        .get_os_version()
+        .map_err(|_| "no OS version recorded".to_string())?;
+
+    if detected == recorded {
+        Ok(())
+    } else {
+        Err(format!(
+            "recorded {recorded}, detected {detected} (run 'vellum upgrade')"
+        ))
+    }
+}
+
+// Only one device package can be installed at a time, so this checks for
+// remarkable-os and the package matching the detected device.
+fn check_virtual_packages(apk: &Apk) -> Check {
+    let installed = apk
+        .list_installed()
+        .map_err(|e| format!("could not list installed packages: {e}"))?;
+
+    let mut expected = vec!["remarkable-os".to_string()];
+    if let Some(device) = get_device_type() {
+        expected.push(device.id);
+    }
+
+    let missing: Vec<String> = expected
+        .into_iter()
+        .filter(|p| !installed.contains(p))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("not registered: {}", missing.join(", ")))
+    }
+}
+
+fn check_hooks_dir(vellum_root: &str) -> Check {
+    let hooks_dir = format!("{vellum_root}/hooks/post-os-upgrade");
+    if !Path::new(&hooks_dir).exists() {
+        return Ok(());
+    }
+    fs::read_dir(&hooks_dir)
+        .map(|_| ())
+        .map_err(|e| format!("{hooks_dir} is not readable: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn major_version_of_tags_and_hashes() {
+        assert_eq!(major_version("v1.2.0"), Some(1));
+        assert_eq!(major_version("v2.0.1-3-gabcdef0"), Some(2));
+        assert_eq!(major_version("0.4.0"), Some(0));
+        assert_eq!(major_version("abcdef0"), None);
+        assert_eq!(major_version("unknown"), None);
+    }
+}
@@ -0,0 +1,20 @@
+use crate::constants::VELLUM_VERSION;
+use crate::device::{get_apk_arch, get_device_type, get_os_version};
+use crate::state::State;
+
+pub fn handle_env(state: &State, vellum_root: &str) {
+    let device = get_device_type();
+    let os = get_os_version().unwrap_or_else(|_| "unknown".to_string());
+    let (recorded_os, state_version) = match state.read_with_version("osver") {
+        Ok((os, version)) => (os, version.unwrap_or_else(|| "unknown".to_string())),
+        Err(_) => ("none".to_string(), "none".to_string()),
+    };
+
+    println!("VELLUM_VERSION={VELLUM_VERSION}");
+    println!("VELLUM_ROOT={vellum_root}");
+    println!("VELLUM_ARCH={}", get_apk_arch());
+    println!("VELLUM_DEVICE={}", device.map_or("unknown".to_string(), |d| d.id));
+    println!("VELLUM_OS_VERSION={os}");
+    println!("VELLUM_RECORDED_OS_VERSION={recorded_os}");
+    println!("VELLUM_STATE_VERSION={state_version}");
+}
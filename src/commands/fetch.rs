@@ -0,0 +1,88 @@
+use std::process;
+
+use super::index::get_index;
+use crate::apk::{find_best_compatible_version, Apk};
+use crate::config::Config;
+use crate::util::format_mb;
+
+const USAGE: &str = "Usage: vellum fetch [--os <version>] <package>...";
+
+pub fn handle_fetch(apk: &Apk, config: &Config, args: &[String]) {
+    let mut os: Option<String> = None;
+    let mut packages = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--os" => match iter.next() {
+                Some(v) => os = Some(v.clone()),
+                None => {
+                    eprintln!("Error: --os requires a version");
+                    process::exit(1);
+                }
+            },
+            a if a.starts_with('-') => {
+                eprintln!("Unknown fetch option: {a}");
+                eprintln!("{USAGE}");
+                process::exit(1);
+            }
+            _ => packages.push(arg.clone()),
+        }
+    }
+
+    if packages.is_empty() {
+        eprintln!("{USAGE}");
+        process::exit(1);
+    }
+
+    let index = match get_index(None, config, false) {
+        Ok(idx) => idx,
+        Err(e) => {
+            eprintln!("Could not get package index: {e}");
+            process::exit(1);
+        }
+    };
+
+    // Without --os, fetch what `vellum add` would install on this device.
+    let os = os.or_else(|| apk.get_package_version("remarkable-os").ok().flatten());
+
+    let mut specs = Vec::new();
+    let mut total = 0;
+    let mut failed = false;
+    for name in &packages {
+        if name.contains(['=', '<', '>', '~']) {
+            specs.push(name.clone());
+            continue;
+        }
+        let pkg = match &os {
+            Some(os) => find_best_compatible_version(name, os, &index),
+            None => index
+                .iter()
+                .filter(|p| &p.name == name)
+                .max_by(|a, b| a.version.cmp(&b.version)),
+        };
+        match pkg {
+            Some(pkg) => {
+                total += pkg.size.unwrap_or(0);
+                specs.push(format!("{}={}", pkg.name, pkg.version));
+            }
+            None if index.iter().any(|p| &p.name == name) => {
+                let os = os.as_deref().unwrap_or_default();
+                eprintln!("Error: No version of '{name}' is compatible with OS {os}");
+                failed = true;
+            }
+            None => specs.push(name.clone()),
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+
+    println!("Total download size: {}", format_mb(total));
+    let spec_refs: Vec<&str> = specs.iter().map(|s| s.as_str()).collect();
+    if let Err(e) = apk.fetch(&spec_refs) {
+        eprintln!("Fetch failed: {e}");
+        process::exit(1);
+    }
+    println!("Fetched {} package(s) into the apk cache.", specs.len());
+}
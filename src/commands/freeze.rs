@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+use std::fs;
+use std::process;
+
+use crate::apk::Apk;
+use crate::constants::{VELLUM_ROOT, VIRTUAL_PKGS};
+
+pub const DEFAULT_FREEZE_FILE: &str = "packages.txt";
+
+pub fn handle_freeze(apk: &Apk, args: &[String]) {
+    let path = args.first().map(|s| s.as_str()).unwrap_or(DEFAULT_FREEZE_FILE);
+
+    let world_path = format!("{VELLUM_ROOT}/etc/apk/world");
+    let world = match fs::read_to_string(&world_path) {
+        Ok(w) => w,
+        Err(e) => {
+            eprintln!("Could not read {world_path}: {e}");
+            process::exit(1);
+        }
+    };
+
+    let versions: HashMap<String, String> = apk
+        .list_installed_with_versions()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+
+    let mut lines = vec!["# vellum package list".to_string()];
+    for entry in world.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let spec = entry.split(['=', '<', '>', '~']).next().unwrap_or(entry);
+        let name = spec.split('@').next().unwrap_or(spec);
+        if name == "vellum" || VIRTUAL_PKGS.contains(&name) {
+            continue;
+        }
+        match versions.get(name) {
+            Some(ver) => lines.push(format!("{spec}={ver}")),
+            None => lines.push(spec.to_string()),
+        }
+    }
+
+    let content = lines.join("\n") + "\n";
+    if path == "-" {
+        print!("{content}");
+        return;
+    }
+
+    if let Err(e) = fs::write(path, content) {
+        eprintln!("Could not write {path}: {e}");
+        process::exit(1);
+    }
+    println!("Wrote {} package(s) to {path}.", lines.len() - 1);
+}
@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::fs;
+use std::process;
+
+use crate::apk::{Apk, Dependency};
+use crate::constants::{VELLUM_ROOT, VIRTUAL_PKGS};
+
+const USAGE: &str =
+    "Usage: vellum graph [--depth <n>] [--installed-only] [--output <file.dot>] [package...]";
+const DEFAULT_DEPTH: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shape {
+    /// Explicitly installed, i.e. listed in the world file.
+    Box,
+    Ellipse,
+    /// Virtual packages and provided names such as `so:` libraries.
+    Diamond,
+}
+
+impl Shape {
+    fn as_str(self) -> &'static str {
+        match self {
+            Shape::Box => "box",
+            Shape::Ellipse => "ellipse",
+            Shape::Diamond => "diamond",
+        }
+    }
+}
+
+pub fn handle_graph(apk: &Apk, args: &[String]) {
+    let mut depth = DEFAULT_DEPTH;
+    let mut installed_only = false;
+    let mut output: Option<String> = None;
+    let mut roots: Vec<String> = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--depth" => match iter.next().and_then(|d| d.parse().ok()) {
+                Some(d) => depth = d,
+                None => {
+                    eprintln!("Error: --depth requires a number");
+                    process::exit(1);
+                }
+            },
+            "--installed-only" => installed_only = true,
+            "--output" => match iter.next() {
+                Some(path) => output = Some(path.clone()),
+                None => {
+                    eprintln!("Error: --output requires a file name");
+                    process::exit(1);
+                }
+            },
+            a if a.starts_with('-') => {
+                eprintln!("Unknown graph option: {a}");
+                eprintln!("{USAGE}");
+                process::exit(1);
+            }
+            _ => roots.push(arg.clone()),
+        }
+    }
+
+    let world = read_world_names();
+    if roots.is_empty() {
+        roots = world
+            .iter()
+            .filter(|n| *n != "vellum" && !VIRTUAL_PKGS.contains(&n.as_str()))
+            .cloned()
+            .collect();
+    }
+    let installed: HashSet<String> = match apk.list_installed() {
+        Ok(list) => list.into_iter().collect(),
+        Err(e) => {
+            eprintln!("Could not list installed packages: {e}");
+            process::exit(1);
+        }
+    };
+
+    let mut nodes: BTreeMap<String, Shape> = BTreeMap::new();
+    let mut edges: BTreeSet<(String, String)> = BTreeSet::new();
+    let mut queue: VecDeque<(String, usize)> = VecDeque::new();
+    for root in roots {
+        if installed_only && !installed.contains(&root) {
+            eprintln!("warning: {root} is not installed; skipping");
+            continue;
+        }
+        queue.push_back((root, 0));
+    }
+
+    let mut expanded = HashSet::new();
+    while let Some((pkg, level)) = queue.pop_front() {
+        nodes.entry(pkg.clone()).or_insert_with(|| shape_for(&pkg, &world));
+        if level >= depth || is_virtual(&pkg) || !expanded.insert(pkg.clone()) {
+            continue;
+        }
+        let deps = match apk.get_dependencies(&pkg) {
+            Ok(deps) => deps,
+            Err(e) => {
+                eprintln!("warning: could not read dependencies of {pkg}: {e}");
+                continue;
+            }
+        };
+        for dep in deps.into_iter().filter(|d| !d.conflict) {
+            if installed_only && !installed.contains(&dep.name) {
+                continue;
+            }
+            edges.insert((pkg.clone(), dep.name.clone()));
+            queue.push_back((dep.name, level + 1));
+        }
+    }
+
+    let dot = to_dot(&nodes, &edges);
+    match output {
+        Some(path) => {
+            if let Err(e) = fs::write(&path, dot) {
+                eprintln!("Could not write {path}: {e}");
+                process::exit(1);
+            }
+            eprintln!("Wrote {path}. Render it with: dot -Tpng {path} -o graph.png");
+        }
+        None => {
+            print!("{dot}");
+            eprintln!("Render this graph with: vellum graph | dot -Tpng -o graph.png");
+        }
+    }
+}
+
+fn read_world_names() -> HashSet<String> {
+    let world_path = format!("{VELLUM_ROOT}/etc/apk/world");
+    fs::read_to_string(world_path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.parse::<Dependency>().ok())
+        .map(|dep| dep.name)
+        .collect()
+}
+
+fn is_virtual(name: &str) -> bool {
+    VIRTUAL_PKGS.contains(&name) || name.contains(':')
+}
+
+fn shape_for(name: &str, world: &HashSet<String>) -> Shape {
+    if is_virtual(name) {
+        Shape::Diamond
+    } else if world.contains(name) {
+        Shape::Box
+    } else {
+        Shape::Ellipse
+    }
+}
+
+fn to_dot(nodes: &BTreeMap<String, Shape>, edges: &BTreeSet<(String, String)>) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+
+    let mut dot = String::from("digraph vellum {\n    rankdir=LR;\n");
+    for (name, shape) in nodes {
+        dot.push_str(&format!("    {} [shape={}];\n", quote(name), shape.as_str()));
+    }
+    for (from, to) in edges {
+        dot.push_str(&format!("    {} -> {};\n", quote(from), quote(to)));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes_follow_package_kind() {
+        let world: HashSet<String> = ["foo".to_string()].into_iter().collect();
+        assert_eq!(shape_for("foo", &world), Shape::Box);
+        assert_eq!(shape_for("libfoo", &world), Shape::Ellipse);
+        assert_eq!(shape_for("remarkable-os", &world), Shape::Diamond);
+        assert_eq!(shape_for("so:libc.musl-aarch64.so.1", &world), Shape::Diamond);
+    }
+
+    #[test]
+    fn dot_lists_nodes_then_edges() {
+        let nodes: BTreeMap<String, Shape> = [
+            ("foo".to_string(), Shape::Box),
+            ("remarkable-os".to_string(), Shape::Diamond),
+        ]
+        .into_iter()
+        .collect();
+        let edges: BTreeSet<(String, String)> =
+            [("foo".to_string(), "remarkable-os".to_string())].into_iter().collect();
+
+        assert_eq!(
+            to_dot(&nodes, &edges),
+            "digraph vellum {\n    rankdir=LR;\n    \"foo\" [shape=box];\n    \
+             \"remarkable-os\" [shape=diamond];\n    \"foo\" -> \"remarkable-os\";\n}\n"
+        );
+    }
+}
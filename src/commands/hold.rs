@@ -0,0 +1,183 @@
+use std::fs;
+use std::process;
+
+use anyhow::Result;
+
+use crate::apk::{parse_list_line, Apk};
+use crate::constants::{VELLUM_ROOT, VIRTUAL_PKGS};
+use crate::state::State;
+
+pub fn handle_hold(apk: &Apk, state: &State, args: &[String]) {
+    if args.is_empty() {
+        print_held(state);
+        return;
+    }
+
+    let mut failed = false;
+    for pkg in args {
+        if VIRTUAL_PKGS.contains(&pkg.as_str()) {
+            eprintln!("Error: '{pkg}' is managed by vellum and cannot be held.");
+            failed = true;
+            continue;
+        }
+        let Some(version) = installed_version(apk, pkg) else {
+            eprintln!("Error: '{pkg}' is not installed.");
+            failed = true;
+            continue;
+        };
+        if let Err(e) = set_world_pin(pkg, Some(&version)) {
+            eprintln!("Error: failed to pin {pkg} in world file: {e}");
+            failed = true;
+            continue;
+        }
+        if let Err(e) = state.set_held(pkg, &version) {
+            eprintln!("Error: failed to save hold for {pkg}: {e}");
+            failed = true;
+            continue;
+        }
+        println!("Held {pkg} at {version}");
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+pub fn handle_unhold(_apk: &Apk, state: &State, args: &[String]) {
+    if args.is_empty() {
+        eprintln!("Usage: vellum unhold <package>...");
+        process::exit(1);
+    }
+
+    let mut failed = false;
+    for pkg in args {
+        match state.remove_held(pkg) {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("{pkg} is not held.");
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Error: failed to update holds: {e}");
+                failed = true;
+                continue;
+            }
+        }
+        if let Err(e) = set_world_pin(pkg, None) {
+            eprintln!("Error: failed to unpin {pkg} in world file: {e}");
+            failed = true;
+            continue;
+        }
+        println!("Released {pkg}");
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn print_held(state: &State) {
+    let held = match state.list_held() {
+        Ok(h) => h,
+        Err(e) => {
+            eprintln!("Could not read held packages: {e}");
+            process::exit(1);
+        }
+    };
+
+    if held.is_empty() {
+        println!("No packages are held.");
+        return;
+    }
+
+    let name_width = held.iter().map(|(n, _)| n.len()).max().unwrap_or(0);
+    for (name, version) in &held {
+        println!("{name:<name_width$}  {version}");
+    }
+}
+
+// Full `version-rN` of an installed package, as apk expects in a world pin.
+pub fn installed_version(apk: &Apk, pkg: &str) -> Option<String> {
+    let output = apk.output(&["list", "-I", pkg]).ok()?;
+    output
+        .lines()
+        .filter_map(parse_list_line)
+        .find(|(name, _)| name == pkg)
+        .map(|(_, version)| version)
+}
+
+/// Pins `pkg` to `version` in the world file, or removes its version
+/// constraint when `version` is `None`. A repository tag is kept.
+pub fn set_world_pin(pkg: &str, version: Option<&str>) -> Result<()> {
+    let world_path = format!("{VELLUM_ROOT}/etc/apk/world");
+    let content = fs::read_to_string(&world_path).unwrap_or_default();
+    fs::write(&world_path, pin_world_entry(&content, pkg, version))?;
+    Ok(())
+}
+
+fn pin_world_entry(content: &str, pkg: &str, version: Option<&str>) -> String {
+    let mut found = false;
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let line = line.trim();
+            let name_end = line.find(['@', '=', '<', '>', '~']).unwrap_or(line.len());
+            if &line[..name_end] != pkg {
+                return line.to_string();
+            }
+            found = true;
+
+            let mut entry = pkg.to_string();
+            if let Some(rest) = line[name_end..].strip_prefix('@') {
+                let tag = rest.split(['=', '<', '>', '~']).next().unwrap_or("");
+                entry.push('@');
+                entry.push_str(tag);
+            }
+            if let Some(v) = version {
+                entry.push('=');
+                entry.push_str(v);
+            }
+            entry
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    // Holding a package that was only pulled in as a dependency adds it to
+    // world, which is the only place apk takes pins from.
+    if !found {
+        if let Some(v) = version {
+            lines.push(format!("{pkg}={v}"));
+        }
+    }
+
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pin_replaces_existing_entry() {
+        let world = "foo\nbar>=1.0\nbaz\n";
+        assert_eq!(pin_world_entry(world, "bar", Some("1.2-r0")), "foo\nbar=1.2-r0\nbaz\n");
+    }
+
+    #[test]
+    fn pin_keeps_repository_tag() {
+        let world = "foo@testing\n";
+        assert_eq!(pin_world_entry(world, "foo", Some("2.0-r1")), "foo@testing=2.0-r1\n");
+        assert_eq!(pin_world_entry("foo@testing=2.0-r1\n", "foo", None), "foo@testing\n");
+    }
+
+    #[test]
+    fn pin_adds_missing_entry() {
+        assert_eq!(pin_world_entry("foo\n", "libbar", Some("1.0-r0")), "foo\nlibbar=1.0-r0\n");
+        assert_eq!(pin_world_entry("foo\n", "libbar", None), "foo\n");
+    }
+
+    #[test]
+    fn pin_does_not_match_name_prefix() {
+        assert_eq!(pin_world_entry("foo-extra\n", "foo", None), "foo-extra\n");
+    }
+}
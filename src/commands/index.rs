@@ -0,0 +1,131 @@
+use std::fs;
+
+use crate::apk::{
+    clear_remote_index_cache, fetch_index_with_mirrors, merge_tagged, parse_index_tar_gz, with_tag,
+    Package, Repository,
+};
+use crate::config::Config;
+use crate::constants::VELLUM_ROOT;
+use crate::device::get_apk_arch;
+use crate::state::State;
+
+/// Loads the package index, preferring apk's own cached copy. With
+/// `no_cache`, the cache is skipped and the index is downloaded again. When
+/// the index is downloaded, the repository that answered is remembered in
+/// `state`; pass `None` to leave state untouched, as dry runs must.
+pub fn get_index(
+    state: Option<&State>,
+    config: &Config,
+    no_cache: bool,
+) -> anyhow::Result<Vec<Package>> {
+    let cache_dir = format!("{VELLUM_ROOT}/etc/apk/cache");
+
+    if no_cache {
+        clear_remote_index_cache(&cache_dir);
+    } else if let Some(index) = get_cached_index() {
+        return index;
+    }
+
+    let repos = get_repositories(config);
+    if repos.is_empty() {
+        return Err(anyhow::anyhow!(
+            "no cached index and could not determine repository URL"
+        ));
+    }
+
+    let arch = get_apk_arch();
+    let (index, repo_url) = fetch_index_with_mirrors(&repos, &arch, Some(&cache_dir))?;
+
+    if let (Some(state), Some(repo_url)) = (state, repo_url) {
+        if state.get_repo_url().ok().as_ref() != Some(&repo_url) {
+            if let Err(e) = state.set_repo_url(&repo_url) {
+                eprintln!("warning: failed to save repository URL: {e}");
+            }
+        }
+    }
+    Ok(index)
+}
+
+/// Parses apk's cached copies of the index, one per repository, merged into
+/// one, or returns `None` when there are none. Packages from a tagged
+/// repository are marked with its tag. Never touches the network.
+pub fn get_cached_index() -> Option<anyhow::Result<Vec<Package>>> {
+    let cache_dir = format!("{VELLUM_ROOT}/etc/apk/cache");
+    let mut paths: Vec<String> = fs::read_dir(cache_dir)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| entry.path().to_str().map(str::to_string))
+        .filter(|path| {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            name.starts_with("APKINDEX.") && name.ends_with(".tar.gz")
+        })
+        .collect();
+    if paths.is_empty() {
+        return None;
+    }
+    paths.sort();
+
+    let repos = read_repositories();
+    let mut indexes = Vec::new();
+    for path in paths {
+        let name = path.rsplit('/').next().unwrap_or(&path);
+        let tag = repos.iter().find(|r| r.apk_cache_name() == name).and_then(|r| r.tag.as_deref());
+        match parse_index_tar_gz(&path) {
+            Ok(index) => indexes.push(with_tag(index, tag)),
+            Err(e) => return Some(Err(e)),
+        }
+    }
+    Some(Ok(merge_tagged(indexes)))
+}
+
+/// Downloads the index without reading or writing any cached copy, for when
+/// the cache itself is suspect.
+pub fn get_remote_index(config: &Config) -> anyhow::Result<Vec<Package>> {
+    fetch_index_with_mirrors(&get_repositories(config), &get_apk_arch(), None)
+        .map(|(index, _)| index)
+}
+
+/// The remote repositories from the repositories file, in order, tagged ones
+/// (such as `@testing`) included. The local repo is left out. If the file
+/// lists no untagged remote repository, the config's `repo_url` or else the
+/// last known URL is added.
+pub fn get_repositories(config: &Config) -> Vec<Repository> {
+    let mut repos = read_repositories();
+    if repos.iter().any(|r| r.tag.is_none()) {
+        return repos;
+    }
+    if let Some(url) = &config.repo_url {
+        repos.push(Repository::untagged(url));
+        return repos;
+    }
+
+    match State::new(VELLUM_ROOT).get_repo_url() {
+        Ok(url) if !url.is_empty() => {
+            eprintln!("warning: no repository configured, using last known {url}");
+            repos.push(Repository::untagged(&url));
+        }
+        _ => {}
+    }
+    repos
+}
+
+fn read_repositories() -> Vec<Repository> {
+    let repos_file = format!("{VELLUM_ROOT}/etc/apk/repositories");
+    let content = fs::read_to_string(repos_file).unwrap_or_default();
+    content
+        .lines()
+        .filter_map(Repository::parse)
+        .filter(|repo| !repo.url.contains("local-repo"))
+        .collect()
+}
+
+// Some apk versions ignore a last line that isn't newline-terminated, so the
+// file always ends with exactly one newline.
+pub fn write_repositories<S: AsRef<str>>(path: &str, lines: &[S]) -> anyhow::Result<()> {
+    let mut content: String = lines.iter().map(|l| format!("{}\n", l.as_ref())).collect();
+    while content.ends_with("\n\n") {
+        content.pop();
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
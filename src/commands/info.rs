@@ -0,0 +1,131 @@
+use std::process;
+
+use super::hold::installed_version;
+use super::index::get_index;
+use crate::apk::{Apk, Package};
+use crate::config::Config;
+use crate::util::{json_string, json_string_array};
+
+pub fn handle_info(apk: &Apk, config: &Config, args: &[String]) {
+    let mut json = false;
+    let mut name: Option<&str> = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            a if a.starts_with('-') => {
+                eprintln!("Unknown info option: {a}");
+                print_usage();
+                process::exit(1);
+            }
+            a => name = Some(a),
+        }
+    }
+
+    let Some(name) = name else {
+        print_usage();
+        process::exit(1);
+    };
+
+    let index = match get_index(None, config, false) {
+        Ok(idx) => idx,
+        Err(e) => {
+            eprintln!("Could not get package index: {e}");
+            process::exit(1);
+        }
+    };
+
+    let mut versions: Vec<&Package> = index.iter().filter(|p| p.name == name).collect();
+    if versions.is_empty() {
+        eprintln!("Package '{name}' not found in the index.");
+        process::exit(1);
+    }
+    versions.sort_by(|a, b| b.version.cmp(&a.version));
+
+    let installed = installed_version(apk, name);
+    let latest = versions[0];
+
+    if json {
+        print_json(latest, &versions, installed.as_deref());
+        return;
+    }
+
+    println!("Name:         {}", latest.name);
+    if let Some(desc) = &latest.description {
+        println!("Description:  {desc}");
+    }
+    if let Some(license) = &latest.license {
+        println!("License:      {license}");
+    }
+    if let Some(arch) = &latest.arch {
+        println!("Architecture: {arch}");
+    }
+    if let Some(maintainer) = &latest.maintainer {
+        println!("Maintainer:   {maintainer}");
+    }
+    if !latest.depends.is_empty() {
+        println!("Depends:      {}", latest.depends.join(" "));
+    }
+    if !latest.provides.is_empty() {
+        println!("Provides:     {}", latest.provides.join(" "));
+    }
+    if let Some(ver) = &installed {
+        println!("Installed:    {ver}");
+    }
+
+    println!("Versions:");
+    let ver_width = versions.iter().map(|p| p.version.as_str().len()).max().unwrap_or(0);
+    for pkg in &versions {
+        let tag = if installed.as_deref() == Some(pkg.version.as_str()) {
+            "  [installed]"
+        } else {
+            ""
+        };
+        println!("  {:<ver_width$}  OS {}{tag}", pkg.version, os_range(pkg));
+    }
+}
+
+fn print_json(latest: &Package, versions: &[&Package], installed: Option<&str>) {
+    let optional = |v: Option<&str>| v.map_or("null".to_string(), json_string);
+
+    let entries: Vec<String> = versions
+        .iter()
+        .map(|pkg| {
+            let (min, max) = pkg.get_os_constraints();
+            format!(
+                "{{\"version\":{},\"os_min\":{},\"os_max\":{},\"installed\":{}}}",
+                json_string(pkg.version.as_str()),
+                optional(min.as_deref()),
+                optional(max.as_deref()),
+                installed == Some(pkg.version.as_str())
+            )
+        })
+        .collect();
+
+    println!(
+        "{{\"name\":{},\"description\":{},\"license\":{},\"arch\":{},\"maintainer\":{},\
+         \"depends\":{},\"provides\":{},\"installed\":{},\"versions\":[{}]}}",
+        json_string(&latest.name),
+        optional(latest.description.as_deref()),
+        optional(latest.license.as_deref()),
+        optional(latest.arch.as_deref()),
+        optional(latest.maintainer.as_deref()),
+        json_string_array(&latest.depends),
+        json_string_array(&latest.provides),
+        optional(installed),
+        entries.join(",")
+    );
+}
+
+fn os_range(pkg: &Package) -> String {
+    match pkg.get_os_constraints() {
+        (Some(min), Some(max)) => format!(">= {min}, < {max}"),
+        (Some(min), None) => format!(">= {min}"),
+        (None, Some(max)) => format!("< {max}"),
+        (None, None) => "any".to_string(),
+    }
+}
+
+fn print_usage() {
+    eprintln!("Usage: vellum info [--json] <package>");
+}
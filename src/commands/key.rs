@@ -0,0 +1,111 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::process;
+
+use anyhow::Result;
+use pkcs1::{EncodeRsaPrivateKey, LineEnding};
+use pkcs8::EncodePublicKey;
+use rsa::rand_core::OsRng;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+
+const USAGE: &str = "Usage: vellum key generate [--bits 2048|4096] [--force]";
+const DEFAULT_BITS: usize = 4096;
+
+pub fn handle_key_generate(vellum_root: &str, args: &[String]) {
+    let (bits, force) = match parse_args(args) {
+        Ok(parsed) => parsed,
+        Err(msg) => {
+            eprintln!("Error: {msg}");
+            eprintln!("{USAGE}");
+            process::exit(1);
+        }
+    };
+
+    let keys_dir = format!("{vellum_root}/etc/apk/keys");
+    let key_path = format!("{keys_dir}/local.rsa");
+    if Path::new(&key_path).exists() && !force {
+        eprintln!("{key_path} already exists. Use --force to replace it.");
+        eprintln!("Packages in the local repository will need to be re-signed afterwards.");
+        process::exit(1);
+    }
+
+    println!("Generating {bits}-bit RSA key...");
+    if let Err(e) = generate_key_pair(&keys_dir, bits) {
+        eprintln!("Failed to generate signing key: {e}");
+        process::exit(1);
+    }
+    println!("Wrote {key_path} and {key_path}.pub");
+}
+
+fn parse_args(args: &[String]) -> Result<(usize, bool), String> {
+    let mut bits = DEFAULT_BITS;
+    let mut force = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--force" => force = true,
+            "--bits" => {
+                bits = match iter.next().map(|s| s.as_str()) {
+                    Some("2048") => 2048,
+                    Some("4096") => 4096,
+                    Some(v) => return Err(format!("unsupported key size: {v}")),
+                    None => return Err("--bits requires a value".to_string()),
+                };
+            }
+            a => return Err(format!("unknown option: {a}")),
+        }
+    }
+    Ok((bits, force))
+}
+
+// The private key is PKCS#1 PEM like `openssl genrsa` writes it; the public
+// key is the PEM SubjectPublicKeyInfo form apk loads from etc/apk/keys, the
+// same as `openssl rsa -pubout` in bootstrap.sh.
+fn generate_key_pair(keys_dir: &str, bits: usize) -> Result<()> {
+    let key = RsaPrivateKey::new(&mut OsRng, bits)?;
+    let private_pem = key.to_pkcs1_pem(LineEnding::LF)?;
+    let public_pem = RsaPublicKey::from(&key).to_public_key_pem(LineEnding::LF)?;
+
+    fs::create_dir_all(keys_dir)?;
+    let key_path = format!("{keys_dir}/local.rsa");
+    write_with_mode(&key_path, private_pem.as_bytes(), 0o600)?;
+    write_with_mode(&format!("{key_path}.pub"), public_pem.as_bytes(), 0o644)
+}
+
+fn write_with_mode(path: &str, data: &[u8], mode: u32) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)?;
+    // An existing file keeps its old mode, and a new one is subject to umask.
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_args_defaults_and_flags() {
+        assert_eq!(parse_args(&[]), Ok((4096, false)));
+        assert_eq!(parse_args(&args(&["--bits", "2048", "--force"])), Ok((2048, true)));
+    }
+
+    #[test]
+    fn parse_args_rejects_other_sizes() {
+        assert!(parse_args(&args(&["--bits", "1024"])).is_err());
+        assert!(parse_args(&args(&["--bits"])).is_err());
+        assert!(parse_args(&args(&["--size"])).is_err());
+    }
+}
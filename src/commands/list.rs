@@ -0,0 +1,117 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::process;
+
+use crate::apk::{parse_list_line, Apk};
+use crate::constants::{VELLUM_ROOT, VIRTUAL_PKGS};
+use crate::state::State;
+use crate::util::{format_rfc3339, unix_secs};
+
+pub fn handle_list(state: &State, apk: &Apk, args: &[String]) {
+    let mut mode = "-I";
+    let mut sort_by_age = false;
+    let mut patterns = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "--installed" | "-I" => mode = "-I",
+            "--available" | "-a" => mode = "-a",
+            "--upgradeable" | "--upgradable" | "-u" => mode = "-u",
+            "--sort-by-age" => sort_by_age = true,
+            a if a.starts_with('-') => {
+                eprintln!("Unknown list option: {a}");
+                eprintln!(
+                    "Usage: vellum list [--installed|--available|--upgradeable] [--sort-by-age] \
+                     [pattern...]"
+                );
+                process::exit(1);
+            }
+            _ => patterns.push(arg.as_str()),
+        }
+    }
+
+    let mut cmd_args = vec!["list", mode];
+    cmd_args.extend(patterns);
+
+    let output = match apk.output(&cmd_args) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("Failed to list packages: {e}");
+            process::exit(1);
+        }
+    };
+
+    let repo_tags = read_world_tags();
+    let held: Vec<String> = state
+        .list_held()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let pinned: HashMap<String, String> =
+        state.list_pinned().unwrap_or_default().into_iter().collect();
+
+    let mut rows: Vec<(String, String, String)> = output
+        .lines()
+        .filter_map(parse_list_line)
+        .filter(|(name, _)| !VIRTUAL_PKGS.contains(&name.as_str()))
+        .map(|(name, version)| {
+            let repo = repo_tags
+                .get(&name)
+                .cloned()
+                .unwrap_or_else(|| "main".to_string());
+            (name, version, repo)
+        })
+        .collect();
+
+    if rows.is_empty() {
+        println!("No packages found.");
+        return;
+    }
+
+    // Newest installs first; packages vellum has no install time for (such
+    // as dependencies) go last, by name.
+    let install_times: HashMap<String, u64> = if sort_by_age {
+        let times = state.list_install_times().unwrap_or_default();
+        times.into_iter().map(|(name, t)| (name, unix_secs(t))).collect()
+    } else {
+        HashMap::new()
+    };
+    if sort_by_age {
+        rows.sort_by_key(|(name, _, _)| Reverse(install_times.get(name).copied()));
+    }
+
+    let name_width = rows.iter().map(|(n, _, _)| n.len()).max().unwrap_or(0);
+    let ver_width = rows.iter().map(|(_, v, _)| v.len()).max().unwrap_or(0);
+
+    for (name, version, repo) in &rows {
+        let mut notes = String::new();
+        if held.contains(name) {
+            notes.push_str(" [held]");
+        }
+        if let Some(pin) = pinned.get(name) {
+            notes.push_str(&format!(" [pinned={pin}]"));
+        }
+        if let Some(ts) = install_times.get(name) {
+            notes.push_str(&format!(" installed {}", &format_rfc3339(*ts)[..10]));
+        }
+        println!("{name:<name_width$}  {version:<ver_width$}  [{repo}]{notes}");
+    }
+}
+
+// Maps packages pinned to a tagged repository in the world file (e.g.
+// `foo@testing`) to that tag.
+pub fn read_world_tags() -> HashMap<String, String> {
+    let world_path = format!("{VELLUM_ROOT}/etc/apk/world");
+    let content = fs::read_to_string(world_path).unwrap_or_default();
+
+    content
+        .lines()
+        .filter_map(|line| {
+            let (name, rest) = line.trim().split_once('@')?;
+            let tag = rest.split(['=', '<', '>', '~']).next()?;
+            Some((name.to_string(), tag.to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,70 @@
+use std::process;
+
+use crate::state::{InstallEvent, State};
+use crate::util::{format_rfc3339, unix_secs};
+
+pub fn handle_log(state: &State, args: &[String]) {
+    let mut since: Option<&str> = None;
+    let mut pkg: Option<&str> = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--since" => since = iter.next().map(|s| s.as_str()),
+            "--pkg" => pkg = iter.next().map(|s| s.as_str()),
+            a => {
+                eprintln!("Unknown log option: {a}");
+                eprintln!("Usage: vellum log [--since <YYYY-MM-DD>] [--pkg <name>]");
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut events = match state.get_install_log() {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("Could not read install log: {e}");
+            process::exit(1);
+        }
+    };
+    // Installs made before the log existed still have an install time.
+    if events.is_empty() {
+        events = install_time_events(state);
+    }
+
+    // Timestamps are stored as RFC3339 UTC, so a date prefix such as
+    // `2024-01-31` compares correctly as a plain string.
+    let events: Vec<_> = events
+        .iter()
+        .filter(|e| since.is_none_or(|s| e.ts.as_str() >= s))
+        .filter(|e| pkg.is_none_or(|p| e.packages.iter().any(|ep| ep == p)))
+        .collect();
+
+    if events.is_empty() {
+        println!("No matching log entries.");
+        return;
+    }
+
+    for event in events {
+        let os = if event.os.is_empty() {
+            String::new()
+        } else {
+            format!("  (OS {})", event.os)
+        };
+        println!("{}  {:<7}  {}{os}", event.ts, event.op, event.packages.join(" "));
+    }
+}
+
+fn install_time_events(state: &State) -> Vec<InstallEvent> {
+    let mut times = state.list_install_times().unwrap_or_default();
+    times.sort_by_key(|(_, t)| *t);
+    times
+        .into_iter()
+        .map(|(name, t)| InstallEvent {
+            ts: format_rfc3339(unix_secs(t)),
+            op: "add".to_string(),
+            packages: vec![name],
+            os: String::new(),
+        })
+        .collect()
+}
@@ -1,15 +1,62 @@
 mod add;
+mod audit;
 mod check_os;
+mod completions;
+mod config;
 mod del;
+mod diff;
+mod doctor;
+mod env;
+mod fetch;
+mod freeze;
+mod graph;
+mod hold;
+mod index;
+mod info;
+mod key;
+mod list;
+mod log;
+mod outdated;
+mod pin;
 mod reenable;
+mod repo;
+mod restore;
+mod rollback;
+mod search;
+mod stats;
 mod self_uninstall;
 mod testing;
 mod upgrade;
+mod verify;
+mod why;
 
 pub use add::handle_add;
+pub use audit::handle_audit;
 pub use check_os::handle_check_os;
+pub use completions::handle_completions;
+pub use config::handle_config;
 pub use del::{handle_del, handle_purge};
+pub use diff::handle_diff;
+pub use doctor::handle_doctor;
+pub use env::handle_env;
+pub use fetch::handle_fetch;
+pub use freeze::handle_freeze;
+pub use graph::handle_graph;
+pub use hold::{handle_hold, handle_unhold};
+pub use info::handle_info;
+pub use key::handle_key_generate;
+pub use list::handle_list;
+pub use log::handle_log;
+pub use outdated::handle_outdated;
+pub use pin::{handle_pin, handle_unpin};
 pub use reenable::handle_reenable;
+pub use repo::handle_repo;
+pub use restore::handle_restore;
+pub use rollback::handle_rollback;
+pub use search::handle_search;
+pub use stats::handle_stats;
 pub use self_uninstall::handle_self_uninstall;
 pub use testing::handle_testing;
 pub use upgrade::handle_upgrade;
+pub use verify::handle_verify;
+pub use why::handle_why;
@@ -0,0 +1,127 @@
+use std::process;
+
+use super::index::get_index;
+use crate::apk::{
+    find_best_compatible_version, version_gt, Apk, Package,
+};
+use crate::config::Config;
+use crate::constants::VIRTUAL_PKGS;
+use crate::util::json_string;
+
+struct Row<'a> {
+    name: &'a str,
+    installed: &'a str,
+    available: &'a str,
+    compatible: Option<bool>,
+}
+
+pub fn handle_outdated(apk: &Apk, config: &Config, args: &[String]) {
+    let mut json = false;
+    let mut show_all = false;
+
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--os-aware" => show_all = false,
+            "--all" => show_all = true,
+            a => {
+                eprintln!("Unknown outdated option: {a}");
+                eprintln!("Usage: vellum outdated [--os-aware|--all] [--json]");
+                process::exit(1);
+            }
+        }
+    }
+
+    let installed: Vec<(String, String)> = match apk.list_installed_with_versions() {
+        Ok(pkgs) => pkgs
+            .into_iter()
+            .filter(|(name, _)| !VIRTUAL_PKGS.contains(&name.as_str()))
+            .collect(),
+        Err(e) => {
+            eprintln!("Could not list installed packages: {e}");
+            process::exit(1);
+        }
+    };
+
+    let index = match get_index(None, config, false) {
+        Ok(idx) => idx,
+        Err(e) => {
+            eprintln!("Could not get package index: {e}");
+            process::exit(1);
+        }
+    };
+
+    let os_version = apk.get_package_version("remarkable-os").ok().flatten();
+
+    // Unless --all is given, only offer versions that run on the current OS;
+    // without a known OS there is nothing to filter on.
+    let mut rows = Vec::new();
+    for (name, installed_ver) in &installed {
+        let newest = match &os_version {
+            Some(os) if !show_all => find_best_compatible_version(name, os, &index),
+            _ => newest_version(name, &index),
+        };
+        if let Some(pkg) = newest {
+            if version_gt(pkg.version.as_str(), installed_ver) {
+                rows.push(Row {
+                    name,
+                    installed: installed_ver,
+                    available: pkg.version.as_str(),
+                    compatible: os_version.as_deref().map(|os| pkg.is_compatible_with_os(os)),
+                });
+            }
+        }
+    }
+
+    if json {
+        let entries: Vec<String> = rows
+            .iter()
+            .map(|row| {
+                let compatible = match row.compatible {
+                    Some(c) => c.to_string(),
+                    None => "null".to_string(),
+                };
+                format!(
+                    "{{\"name\":{},\"installed\":{},\"available\":{},\"compatible\":{compatible}}}",
+                    json_string(row.name),
+                    json_string(row.installed),
+                    json_string(row.available)
+                )
+            })
+            .collect();
+        println!("[{}]", entries.join(","));
+        return;
+    }
+
+    if rows.is_empty() {
+        println!("All packages are up to date.");
+        return;
+    }
+
+    let name_width = rows.iter().map(|r| r.name.len()).max().unwrap_or(0);
+    let ver_width = rows.iter().map(|r| r.installed.len()).max().unwrap_or(0);
+    let avail_width = rows.iter().map(|r| r.available.len()).max().unwrap_or(0);
+
+    for row in &rows {
+        let tag = match row.compatible {
+            Some(true) => "[compat]",
+            Some(false) => "[incompat]",
+            None => "",
+        };
+        let line = format!(
+            "{:<name_width$}  {:<ver_width$}  ->  {:<avail_width$}  {tag}",
+            row.name, row.installed, row.available
+        );
+        println!("{}", line.trim_end());
+    }
+}
+
+// Like `find_best_compatible_version`, untagged releases win over tagged ones.
+fn newest_version<'a>(name: &str, index: &'a [Package]) -> Option<&'a Package> {
+    index.iter().filter(|p| p.name == name).max_by(|a, b| {
+        b.tag
+            .is_some()
+            .cmp(&a.tag.is_some())
+            .then_with(|| a.version.cmp(&b.version))
+    })
+}
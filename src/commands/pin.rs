@@ -0,0 +1,111 @@
+use std::process;
+
+use super::hold::{installed_version, set_world_pin};
+use super::list::read_world_tags;
+use crate::apk::Apk;
+use crate::constants::VIRTUAL_PKGS;
+use crate::state::State;
+
+pub fn handle_pin(apk: &Apk, state: &State, args: &[String]) {
+    if args.is_empty() {
+        print_pinned(state);
+        return;
+    }
+
+    let tags = read_world_tags();
+    let mut failed = false;
+    for arg in args {
+        let (pkg, requested) = match arg.split_once('=') {
+            Some((pkg, version)) => (pkg, Some(version.to_string())),
+            None => (arg.as_str(), None),
+        };
+        if VIRTUAL_PKGS.contains(&pkg) {
+            eprintln!("Error: '{pkg}' is managed by vellum and cannot be pinned.");
+            failed = true;
+            continue;
+        }
+        let installed = installed_version(apk, pkg);
+        let Some(version) = requested.or_else(|| installed.clone()) else {
+            eprintln!("Error: '{pkg}' is not installed; use 'vellum pin {pkg}=<version>'.");
+            failed = true;
+            continue;
+        };
+
+        // apk records the spec it was given in world, so installing a
+        // different version pins it in the same step.
+        let result = if installed.as_deref() == Some(version.as_str()) {
+            set_world_pin(pkg, Some(&version))
+        } else {
+            let tag = tags.get(pkg).map(|t| format!("@{t}")).unwrap_or_default();
+            apk.run(&["add", &format!("{pkg}{tag}={version}")])
+        };
+        if let Err(e) = result {
+            eprintln!("Error: failed to pin {pkg} to {version}: {e}");
+            failed = true;
+            continue;
+        }
+        if let Err(e) = state.set_pinned(pkg, &version) {
+            eprintln!("Error: failed to save pin for {pkg}: {e}");
+            failed = true;
+            continue;
+        }
+        println!("Pinned {pkg} to {version}");
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+pub fn handle_unpin(_apk: &Apk, state: &State, args: &[String]) {
+    if args.is_empty() {
+        eprintln!("Usage: vellum unpin <package>...");
+        process::exit(1);
+    }
+
+    let mut failed = false;
+    for pkg in args {
+        match state.remove_pinned(pkg) {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("{pkg} is not pinned.");
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Error: failed to update pins: {e}");
+                failed = true;
+                continue;
+            }
+        }
+        if let Err(e) = set_world_pin(pkg, None) {
+            eprintln!("Error: failed to unpin {pkg} in world file: {e}");
+            failed = true;
+            continue;
+        }
+        println!("Unpinned {pkg}");
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn print_pinned(state: &State) {
+    let pinned = match state.list_pinned() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Could not read pinned packages: {e}");
+            process::exit(1);
+        }
+    };
+
+    if pinned.is_empty() {
+        println!("No packages are pinned.");
+        return;
+    }
+
+    let name_width = pinned.iter().map(|(n, _)| n.len()).max().unwrap_or(0);
+    for (name, version) in &pinned {
+        println!("{name:<name_width$}  {version}");
+    }
+}
@@ -1,72 +1,516 @@
-use std::fs;
+use std::env;
+use std::fs::{self, DirEntry};
+use std::io::Read;
 use std::os::unix::fs::PermissionsExt;
-use std::process::{self, Command};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 
+use crate::config::Config;
 use crate::constants::VELLUM_ROOT;
+use crate::state::State;
+use crate::util::{format_rfc3339, now_unix};
 
-pub fn handle_reenable() {
-    let hooks_dir = format!("{VELLUM_ROOT}/hooks/post-os-upgrade");
+const LOG_TAIL_LINES: usize = 10;
 
-    let entries = match fs::read_dir(&hooks_dir) {
-        Ok(e) => e,
-        Err(_) => {
-            println!("No packages require re-enabling after OS upgrades.");
-            process::exit(0);
+/// The hooks a reenable run started, and those that failed with their exit
+/// code (-1 if the hook could not be started or was killed by a signal).
+#[derive(Debug, Default)]
+pub struct HookSummary {
+    pub run: Vec<String>,
+    pub failed: Vec<(String, i32)>,
+}
+
+/// A finished hook. `timed_out` is set when the watchdog had to kill it.
+#[derive(Debug)]
+struct HookResult {
+    output: Output,
+    timed_out: bool,
+}
+
+pub fn handle_reenable(state: &State, config: &Config, args: &[String]) {
+    let mut quiet = false;
+    let mut dry_run = false;
+    let mut timeout = hook_timeout(config);
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--quiet" | "-q" => quiet = true,
+            "--dry-run" => dry_run = true,
+            "--timeout" => match iter.next().and_then(|s| s.parse().ok()) {
+                Some(secs) => timeout = Duration::from_secs(secs),
+                None => {
+                    eprintln!("Error: --timeout requires a number of seconds");
+                    process::exit(1);
+                }
+            },
+            a => {
+                eprintln!("Unknown reenable option: {a}");
+                eprintln!("Usage: vellum reenable [--quiet] [--dry-run] [--timeout <secs>]");
+                process::exit(1);
+            }
         }
-    };
+    }
 
-    let entries: Vec<_> = entries.flatten().collect();
-    if entries.is_empty() {
-        println!("No packages require re-enabling after OS upgrades.");
-        process::exit(0);
+    if dry_run {
+        list_hooks(Path::new(&hooks_dir()));
+        return;
     }
+    run_hooks(state, quiet, timeout);
+}
+
+/// How long a single hook may run, from `VELLUM_HOOK_TIMEOUT` (in seconds)
+/// or the config's `hook_timeout_secs`.
+pub fn hook_timeout(config: &Config) -> Duration {
+    let secs = env::var("VELLUM_HOOK_TIMEOUT")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(config.hook_timeout_secs);
+    Duration::from_secs(secs)
+}
+
+fn hooks_dir() -> String {
+    format!("{VELLUM_ROOT}/hooks/post-os-upgrade")
+}
+
+pub fn run_hooks(state: &State, quiet: bool, timeout: Duration) -> HookSummary {
+    let mut summary = HookSummary::default();
+    let hooks = match collect_hooks(Path::new(&hooks_dir())) {
+        Some(h) if !h.is_empty() => h,
+        _ => {
+            println!("No packages require re-enabling after OS upgrades.");
+            return summary;
+        }
+    };
 
     println!("Re-enabling packages after OS upgrade...");
 
     let mount_rw = format!("{VELLUM_ROOT}/bin/mount-rw");
     let mount_restore = format!("{VELLUM_ROOT}/bin/mount-restore");
 
-    if run_command(&mount_rw).is_err() {
+    if !run_quietly(&mount_rw) {
         eprintln!("warning: failed to remount filesystem read-write");
     }
 
-    for entry in entries {
-        let path = entry.path();
-        if path.is_dir() {
-            continue;
-        }
+    // Hooks that call mount-restore themselves would otherwise remount the
+    // filesystem read-only before the remaining hooks get to run.
+    env::set_var("VELLUM_REENABLE", "1");
 
-        let metadata = match fs::metadata(&path) {
-            Ok(m) => m,
-            Err(_) => continue,
-        };
+    let mut log = format!("# vellum reenable at {}\n", format_rfc3339(now_unix()));
+    for path in hooks {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        println!("  {name}");
 
-        if metadata.permissions().mode() & 0o111 == 0 {
+        let Some(path_str) = path.to_str() else {
             continue;
+        };
+        summary.run.push(name.clone());
+        match run_command_with_timeout(path_str, timeout) {
+            Ok(HookResult { output: out, .. }) if out.status.success() => {
+                if !quiet {
+                    print_output(&out);
+                }
+            }
+            Ok(HookResult { output: out, timed_out }) => {
+                print_output(&out);
+                let code = out.status.code().unwrap_or(-1);
+                let reason = if timed_out {
+                    format!("hook timed out after {}s", timeout.as_secs())
+                } else {
+                    format!("exit code {code}")
+                };
+                println!("    warning: {name} reenable script failed ({reason})");
+                summary.failed.push((name.clone(), code));
+                log.push_str(&format!("\n== {name} ({reason}) ==\n"));
+                for line in tail_lines(&combined_output(&out), LOG_TAIL_LINES) {
+                    log.push_str(line);
+                    log.push('\n');
+                }
+            }
+            Err(e) => {
+                println!("    warning: {name} reenable script failed: {e}");
+                summary.failed.push((name.clone(), -1));
+                log.push_str(&format!("\n== {name} ==\n{e}\n"));
+            }
         }
+    }
 
-        let name = entry.file_name();
-        let name = name.to_string_lossy();
-        println!("  {name}");
+    env::remove_var("VELLUM_REENABLE");
 
-        if let Some(path_str) = path.to_str() {
-            if run_command(path_str).is_err() {
-                println!("    warning: {name} reenable script failed");
-            }
-        }
+    if let Err(e) = state.write_reenable_log(&log) {
+        eprintln!("warning: failed to write reenable log: {e}");
     }
 
-    if run_command(&mount_restore).is_err() {
+    if !run_quietly(&mount_restore) {
         eprintln!("warning: failed to restore filesystem mounts");
     }
     println!("Done.");
+    summary
 }
 
-fn run_command(path: &str) -> anyhow::Result<()> {
-    let status = Command::new(path).status()?;
-    if status.success() {
-        Ok(())
-    } else {
-        Err(anyhow::anyhow!("command failed"))
+/// The packages in `packages` that have a post-upgrade hook installed.
+pub fn packages_with_hooks(packages: &[String]) -> Vec<String> {
+    let hooks: Vec<String> = hook_files(Path::new(&hooks_dir()))
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+        .collect();
+    packages
+        .iter()
+        .filter(|pkg| hooks.iter().any(|hook| hook_belongs_to(hook, pkg)))
+        .cloned()
+        .collect()
+}
+
+// Hooks are named after their package, behind an optional ordering prefix:
+// `50-xochitl` or `60-toltec-reenable`.
+fn hook_belongs_to(hook: &str, pkg: &str) -> bool {
+    let name = hook.trim_start_matches(|c: char| c.is_ascii_digit());
+    let name = name.strip_prefix('-').unwrap_or(name);
+    name.strip_prefix(pkg)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(['-', '.']))
+}
+
+// Runs a mount helper, showing its output only if it fails.
+fn run_quietly(path: &str) -> bool {
+    match run_command(path) {
+        Ok(out) if out.status.success() => true,
+        Ok(out) => {
+            print_output(&out);
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+fn print_output(out: &Output) {
+    for line in combined_output(out).lines() {
+        println!("    {line}");
+    }
+}
+
+fn combined_output(out: &Output) -> String {
+    let mut text = String::from_utf8_lossy(&out.stdout).to_string();
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str(&String::from_utf8_lossy(&out.stderr));
+    text
+}
+
+fn tail_lines(text: &str, n: usize) -> Vec<&str> {
+    let lines: Vec<&str> = text.lines().collect();
+    lines[lines.len().saturating_sub(n)..].to_vec()
+}
+
+// Prints what `reenable` would run without remounting or running anything,
+// flagging hooks that would be skipped or fail to start.
+fn list_hooks(hooks_dir: &Path) {
+    let hooks = hook_files(hooks_dir).unwrap_or_default();
+    if hooks.is_empty() {
+        println!("No packages require re-enabling after OS upgrades.");
+        return;
+    }
+
+    for path in hooks {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let note = match fs::metadata(&path) {
+            Err(_) => " [not found]",
+            Ok(m) if !is_executable(&m) => " [not executable]",
+            Ok(_) if fs::File::open(&path).is_err() => " [not readable]",
+            Ok(_) => "",
+        };
+        println!("would run: {name}{note}");
+    }
+}
+
+// Returns the executable files in `hooks_dir` in the order they should run.
+fn collect_hooks(hooks_dir: &Path) -> Option<Vec<PathBuf>> {
+    let hooks = hook_files(hooks_dir)?;
+    Some(
+        hooks
+            .into_iter()
+            .filter(|path| fs::metadata(path).is_ok_and(|m| is_executable(&m)))
+            .collect(),
+    )
+}
+
+// Every non-directory entry in `hooks_dir`, sorted into run order.
+fn hook_files(hooks_dir: &Path) -> Option<Vec<PathBuf>> {
+    let entries = fs::read_dir(hooks_dir).ok()?;
+    let files: Vec<DirEntry> = entries.flatten().filter(|e| !e.path().is_dir()).collect();
+    Some(sort_hooks(files).iter().map(|e| e.path()).collect())
+}
+
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+// SysV-style ordering: hooks with a numeric prefix run first, by that number
+// (so `5-foo` comes before `10-bar`), then by the rest of the name. Hooks
+// without a prefix follow, sorted by name.
+fn sort_hooks(mut entries: Vec<DirEntry>) -> Vec<DirEntry> {
+    entries.sort_by_cached_key(|entry| {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let digits = name.find(|c: char| !c.is_ascii_digit()).unwrap_or(name.len());
+        match name[..digits].parse::<u32>() {
+            Ok(prefix) => (false, prefix, name[digits..].to_string()),
+            Err(_) => (true, 0, name),
+        }
+    });
+    entries
+}
+
+fn run_command(path: &str) -> anyhow::Result<Output> {
+    Ok(Command::new(path).output()?)
+}
+
+// Like `run_command`, but a watchdog thread kills the hook once `timeout` has
+// passed so a hung hook can't block the ones after it. The hook runs in its own
+// process group and the whole group is killed, since anything it started in
+// the background would otherwise keep the output pipes open.
+fn run_command_with_timeout(path: &str, timeout: Duration) -> anyhow::Result<HookResult> {
+    let mut child = Command::new(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+    let mut stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let pgid = child.id() as libc::pid_t;
+
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let watchdog = thread::spawn(move || match done_rx.recv_timeout(timeout) {
+        Err(RecvTimeoutError::Timeout) => unsafe { libc::kill(-pgid, libc::SIGKILL) == 0 },
+        _ => false,
+    });
+
+    // Both pipes are drained at once so a hook filling one of them can't
+    // stall while we wait on the other.
+    let stderr_reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut stderr) = stderr {
+            let _ = stderr.read_to_end(&mut buf);
+        }
+        buf
+    });
+    let mut out = Vec::new();
+    if let Some(stdout) = stdout.as_mut() {
+        let _ = stdout.read_to_end(&mut out);
+    }
+    let err = stderr_reader.join().unwrap_or_default();
+
+    let status = child.wait()?;
+    let _ = done_tx.send(());
+    let killed = watchdog.join().unwrap_or(false);
+
+    Ok(HookResult {
+        output: Output {
+            status,
+            stdout: out,
+            stderr: err,
+        },
+        timed_out: killed && !status.success(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_hook(dir: &Path, name: &str, body: &str, mode: u32) {
+        let path = dir.join(name);
+        fs::write(&path, body).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    fn file_names(hooks: &[PathBuf]) -> Vec<String> {
+        hooks
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn hook_files_include_non_executable_hooks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        write_hook(dir, "10-runs", "#!/bin/sh\n", 0o755);
+        write_hook(dir, "20-disabled", "#!/bin/sh\n", 0o644);
+        fs::create_dir(dir.join("30-subdir")).unwrap();
+
+        assert_eq!(
+            file_names(&hook_files(dir).unwrap()),
+            vec!["10-runs", "20-disabled"]
+        );
+        assert_eq!(file_names(&collect_hooks(dir).unwrap()), vec!["10-runs"]);
+    }
+
+    #[test]
+    fn collect_hooks_sorts_by_filename() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for name in ["60-toltec-reenable", "10-first", "50-xochitl", "99-last"] {
+            write_hook(dir, name, "#!/bin/sh\n", 0o755);
+        }
+
+        let hooks = collect_hooks(dir).unwrap();
+
+        assert_eq!(
+            file_names(&hooks),
+            vec!["10-first", "50-xochitl", "60-toltec-reenable", "99-last"]
+        );
+    }
+
+    #[test]
+    fn collect_hooks_skips_non_executables_and_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        write_hook(dir, "20-run", "#!/bin/sh\n", 0o755);
+        write_hook(dir, "10-readme", "not a script", 0o644);
+        fs::create_dir_all(dir.join("30-subdir")).unwrap();
+
+        let hooks = collect_hooks(dir).unwrap();
+
+        assert_eq!(file_names(&hooks), vec!["20-run"]);
+    }
+
+    fn sorted_names(dir: &Path) -> Vec<String> {
+        let entries: Vec<DirEntry> = fs::read_dir(dir).unwrap().flatten().collect();
+        sort_hooks(entries)
+            .iter()
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn sort_hooks_by_numeric_prefix() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for name in ["100-c", "20-b", "5-a", "20-a"] {
+            write_hook(dir, name, "", 0o755);
+        }
+
+        assert_eq!(sorted_names(dir), vec!["5-a", "20-a", "20-b", "100-c"]);
+    }
+
+    #[test]
+    fn sort_hooks_mixed_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for name in ["zeta", "50-xochitl", "alpha", "9-early", "10toltec"] {
+            write_hook(dir, name, "", 0o755);
+        }
+
+        assert_eq!(
+            sorted_names(dir),
+            vec!["9-early", "10toltec", "50-xochitl", "alpha", "zeta"]
+        );
+    }
+
+    #[test]
+    fn sort_hooks_without_prefixes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for name in ["toltec", "ddvk", "koreader"] {
+            write_hook(dir, name, "", 0o755);
+        }
+
+        assert_eq!(sorted_names(dir), vec!["ddvk", "koreader", "toltec"]);
+    }
+
+    #[test]
+    fn collect_hooks_missing_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(collect_hooks(&tmp.path().join("missing")).is_none());
+    }
+
+    #[test]
+    fn hooks_execute_in_sorted_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let log = dir.join("order.log");
+        for name in ["60-c", "05-a", "50-b"] {
+            let body = format!("#!/bin/sh\necho {name} >> {}\n", log.display());
+            write_hook(dir, name, &body, 0o755);
+        }
+
+        for hook in collect_hooks(dir).unwrap() {
+            assert!(run_command(hook.to_str().unwrap()).unwrap().status.success());
+        }
+
+        let order = fs::read_to_string(&log).unwrap();
+        assert_eq!(order.lines().collect::<Vec<_>>(), vec!["05-a", "50-b", "60-c"]);
+    }
+
+    #[test]
+    fn run_command_captures_output() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        write_hook(dir, "10-fail", "#!/bin/sh\necho out\necho err >&2\nexit 3\n", 0o755);
+
+        let out = run_command(dir.join("10-fail").to_str().unwrap()).unwrap();
+
+        assert_eq!(out.status.code(), Some(3));
+        assert_eq!(combined_output(&out), "out\nerr\n");
+    }
+
+    #[test]
+    fn run_command_with_timeout_kills_hung_hook() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        write_hook(dir, "10-hang", "#!/bin/sh\necho started\nexec sleep 30\n", 0o755);
+        write_hook(dir, "20-quick", "#!/bin/sh\necho done\n", 0o755);
+        write_hook(dir, "30-child", "#!/bin/sh\necho started\nsleep 30\necho late\n", 0o755);
+
+        let hung = dir.join("10-hang");
+        let result = run_command_with_timeout(hung.to_str().unwrap(), Duration::from_secs(1));
+        let result = result.unwrap();
+        assert!(result.timed_out);
+        assert_eq!(combined_output(&result.output), "started\n");
+
+        let quick = dir.join("20-quick");
+        let result = run_command_with_timeout(quick.to_str().unwrap(), Duration::from_secs(5));
+        let result = result.unwrap();
+        assert!(!result.timed_out);
+        assert!(result.output.status.success());
+
+        // A child that isn't exec'd holds the pipes open too.
+        let start = std::time::Instant::now();
+        let child = dir.join("30-child");
+        let result = run_command_with_timeout(child.to_str().unwrap(), Duration::from_secs(1));
+        let result = result.unwrap();
+        assert!(result.timed_out);
+        assert_eq!(combined_output(&result.output), "started\n");
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn hooks_match_their_package() {
+        assert!(hook_belongs_to("50-xochitl", "xochitl"));
+        assert!(hook_belongs_to("60-toltec-reenable", "toltec"));
+        assert!(hook_belongs_to("koreader.sh", "koreader"));
+        assert!(!hook_belongs_to("60-toltec-reenable", "toltec-reenable-extra"));
+        assert!(!hook_belongs_to("50-xochitl", "xochitl-extra"));
+        assert!(!hook_belongs_to("50-ddvk", "dd"));
+    }
+
+    #[test]
+    fn tail_lines_keeps_last_lines() {
+        let text = (1..=15).map(|i| i.to_string()).collect::<Vec<_>>().join("\n");
+        let tail = tail_lines(&text, 10);
+        assert_eq!(tail.len(), 10);
+        assert_eq!(tail[0], "6");
+        assert_eq!(tail_lines("a\nb", 10), vec!["a", "b"]);
     }
 }
@@ -0,0 +1,257 @@
+use std::fs;
+use std::process;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+use super::index::write_repositories;
+use super::testing::TestingManager;
+use crate::device::get_apk_arch;
+use crate::repo::add_external_apk;
+use crate::util::http_agent;
+
+const DEFAULT_REPO_URL: &str = "https://packages.vellum.delivery";
+const CONNECTIVITY_TIMEOUT: Duration = Duration::from_secs(10);
+const USAGE: &str = "Usage: vellum repo <add <url> [--tag <@tag>]|add-tagged <@tag> <url>|\
+                     remove <url>|list|add-local <file.apk>>";
+
+pub fn handle_repo(vellum_root: &str, args: &[String]) {
+    let repos_path = format!("{vellum_root}/etc/apk/repositories");
+
+    match args.first().map(|s| s.as_str()) {
+        Some("add") => add_repo(&repos_path, &args[1..]),
+        Some("add-tagged") => add_tagged(vellum_root, &args[1..]),
+        Some("remove") => remove_repo(&repos_path, &args[1..]),
+        Some("list") => list_repos(&repos_path),
+        Some("add-local") => add_local(vellum_root, &args[1..]),
+        Some(cmd) => {
+            eprintln!("Unknown repo command: {cmd}");
+            eprintln!("{USAGE}");
+            process::exit(1);
+        }
+        None => {
+            eprintln!("{USAGE}");
+            process::exit(1);
+        }
+    }
+}
+
+fn add_repo(repos_path: &str, args: &[String]) {
+    let mut url: Option<&str> = None;
+    let mut tag: Option<String> = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--tag" => match iter.next() {
+                Some(t) if t.trim_start_matches('@').is_empty() => {
+                    eprintln!("Error: --tag requires a name");
+                    process::exit(1);
+                }
+                Some(t) => tag = Some(format!("@{}", t.trim_start_matches('@'))),
+                None => {
+                    eprintln!("Error: --tag requires a name");
+                    process::exit(1);
+                }
+            },
+            a if a.starts_with('-') || url.is_some() => {
+                eprintln!("{USAGE}");
+                process::exit(1);
+            }
+            a => url = Some(a.trim_end_matches('/')),
+        }
+    }
+
+    let Some(url) = url else {
+        eprintln!("{USAGE}");
+        process::exit(1);
+    };
+    if !url.starts_with("https://") {
+        eprintln!("Error: repository URL must start with https://");
+        process::exit(1);
+    }
+
+    let lines = read_repo_lines(repos_path);
+    if lines.iter().any(|line| repo_url(line) == url) {
+        println!("{url} is already configured.");
+        return;
+    }
+
+    if let Err(e) = check_reachable(url) {
+        eprintln!("Error: {e}");
+        process::exit(1);
+    }
+
+    let mut lines = lines;
+    lines.push(match &tag {
+        Some(tag) => format!("{tag} {url}"),
+        None => url.to_string(),
+    });
+    if let Err(e) = write_repositories(repos_path, &lines) {
+        eprintln!("Error writing {repos_path}: {e}");
+        process::exit(1);
+    }
+
+    println!("Added {url}.");
+    if let Some(tag) = tag {
+        println!("Install packages from it with: vellum add <package>{tag}");
+    }
+    println!("Run 'vellum update' to refresh the package index.");
+}
+
+fn add_tagged(vellum_root: &str, args: &[String]) {
+    let [tag, url] = args else {
+        eprintln!("{USAGE}");
+        process::exit(1);
+    };
+    let name = tag.trim_start_matches('@');
+    if name.is_empty() || !url.starts_with("https://") {
+        eprintln!("Error: expected a tag such as @mine and an https:// URL");
+        process::exit(1);
+    }
+    if name == "testing" {
+        eprintln!("Use 'vellum testing enable' for the official testing repository.");
+        process::exit(1);
+    }
+
+    let tag = format!("@{name}");
+    let mgr = TestingManager::new_custom(vellum_root, &tag, url);
+    if mgr.is_enabled() {
+        println!("A repository tagged {tag} is already configured.");
+        return;
+    }
+    if let Err(e) = mgr.enable(false) {
+        eprintln!("Error adding {url}: {e}");
+        process::exit(1);
+    }
+
+    println!("Added {url} as {tag}.");
+    println!("Install packages from it with: vellum add <package>{tag}");
+    println!("Run 'vellum update' to refresh the package index.");
+}
+
+fn remove_repo(repos_path: &str, args: &[String]) {
+    let [url] = args else {
+        eprintln!("{USAGE}");
+        process::exit(1);
+    };
+    let url = url.trim_end_matches('/');
+
+    let lines = read_repo_lines(repos_path);
+    let Some(kept) = without_repo(&lines, url) else {
+        eprintln!("{url} is not configured.");
+        process::exit(1);
+    };
+    if let Err(e) = write_repositories(repos_path, &kept) {
+        eprintln!("Error writing {repos_path}: {e}");
+        process::exit(1);
+    }
+    println!("Removed {url}.");
+    println!("Run 'vellum update' to refresh the package index.");
+}
+
+fn list_repos(repos_path: &str) {
+    for line in read_repo_lines(repos_path) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        println!("{line}  [{}]", repo_kind(line));
+    }
+}
+
+fn add_local(vellum_root: &str, args: &[String]) {
+    let [apk_path] = args else {
+        eprintln!("{USAGE}");
+        process::exit(1);
+    };
+
+    let repo_dir = format!("{vellum_root}/local-repo/{}", get_apk_arch());
+    let key_path = format!("{vellum_root}/etc/apk/keys/local.rsa");
+
+    match add_external_apk(apk_path, &repo_dir, Some(&key_path)) {
+        Ok((name, version)) => {
+            println!("Added {name} {version} to the local repository.");
+            println!("Install it with: vellum add {name}");
+        }
+        Err(e) => {
+            eprintln!("Could not add {apk_path}: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+fn read_repo_lines(repos_path: &str) -> Vec<String> {
+    match fs::read_to_string(repos_path) {
+        Ok(content) => content.lines().map(str::to_string).collect(),
+        Err(e) => {
+            eprintln!("Error reading {repos_path}: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+fn check_reachable(url: &str) -> Result<()> {
+    let index_url = format!("{url}/{}/APKINDEX.tar.gz", get_apk_arch());
+    let resp = http_agent(&index_url)?
+        .head(&index_url)
+        .timeout(CONNECTIVITY_TIMEOUT)
+        .call()
+        .map_err(|e| anyhow!("could not reach {url}: {e}"))?;
+
+    if !(200..300).contains(&resp.status()) {
+        return Err(anyhow!("{url} returned HTTP {}", resp.status()));
+    }
+    Ok(())
+}
+
+// A repositories line is a URL or path, optionally preceded by an `@tag`.
+fn repo_url(line: &str) -> &str {
+    line.split_whitespace().last().unwrap_or("").trim_end_matches('/')
+}
+
+fn repo_kind(line: &str) -> &'static str {
+    let url = repo_url(line);
+    if url.contains("local-repo") || url.starts_with('/') {
+        "local"
+    } else if line.starts_with("@testing") {
+        "testing"
+    } else if url == DEFAULT_REPO_URL {
+        "default"
+    } else {
+        "custom"
+    }
+}
+
+fn without_repo(lines: &[String], url: &str) -> Option<Vec<String>> {
+    let kept: Vec<String> = lines.iter().filter(|l| repo_url(l) != url).cloned().collect();
+    (kept.len() < lines.len()).then_some(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repo_kinds() {
+        assert_eq!(repo_kind("/home/root/.vellum/local-repo"), "local");
+        assert_eq!(repo_kind("@testing https://packages.vellum.delivery/testing"), "testing");
+        assert_eq!(repo_kind("https://packages.vellum.delivery/"), "default");
+        assert_eq!(repo_kind("@extra https://example.com/repo"), "custom");
+        assert_eq!(repo_kind("https://example.com/repo"), "custom");
+    }
+
+    #[test]
+    fn without_repo_matches_tagged_lines() {
+        let lines: Vec<String> = ["/home/root/.vellum/local-repo", "@extra https://example.com/"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        assert_eq!(
+            without_repo(&lines, "https://example.com"),
+            Some(vec!["/home/root/.vellum/local-repo".to_string()])
+        );
+        assert_eq!(without_repo(&lines, "https://other.example.com"), None);
+    }
+}
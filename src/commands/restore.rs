@@ -0,0 +1,77 @@
+use std::fs;
+use std::process;
+
+use super::add::handle_add;
+use super::freeze::DEFAULT_FREEZE_FILE;
+use super::index::get_index;
+use crate::apk::Apk;
+use crate::config::Config;
+use crate::state::State;
+
+pub fn handle_restore(state: &State, apk: &Apk, config: &Config, args: &[String]) {
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let assume_yes = args.iter().any(|a| a == "-y" || a == "--yes");
+    let path = args
+        .iter()
+        .find(|a| !a.starts_with('-'))
+        .map(|s| s.as_str())
+        .unwrap_or(DEFAULT_FREEZE_FILE);
+
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Could not read {path}: {e}");
+            process::exit(1);
+        }
+    };
+
+    let installed = apk.list_installed().unwrap_or_default();
+    let index = match get_index((!dry_run).then_some(state), config, false) {
+        Ok(idx) => Some(idx),
+        Err(e) => {
+            eprintln!("warning: could not get package index, skipping availability check: {e}");
+            None
+        }
+    };
+
+    // Entries are restored by name (keeping any @tag) rather than pinned to the
+    // frozen version, so `vellum add` can pick a release compatible with the
+    // current OS.
+    let mut to_install = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let spec = line.split(['=', '<', '>', '~']).next().unwrap_or(line);
+        let name = spec.split('@').next().unwrap_or(spec);
+
+        if installed.iter().any(|p| p == name) {
+            continue;
+        }
+        if let Some(index) = &index {
+            if !index.iter().any(|p| p.name == name) {
+                eprintln!("warning: {name} is no longer available, skipping");
+                continue;
+            }
+        }
+        to_install.push(spec.to_string());
+    }
+
+    if to_install.is_empty() {
+        println!("All packages in {path} are already installed.");
+        return;
+    }
+
+    if dry_run {
+        println!("The following {} package(s) would be installed:", to_install.len());
+        for spec in &to_install {
+            println!("  - {spec}");
+        }
+        return;
+    }
+
+    if assume_yes {
+        to_install.push("--yes".to_string());
+    }
+    handle_add(state, apk, config, &to_install);
+}
@@ -0,0 +1,172 @@
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::time::Instant;
+
+use super::add::clean_world_file_pins;
+use super::upgrade::rebuild_os_package;
+use crate::apk::Apk;
+use crate::constants::VELLUM_ROOT;
+use crate::state::{State, UpgradeReport, UpgradedPackage};
+use crate::util::{format_rfc3339, now_unix};
+
+const USAGE: &str = "Usage: vellum rollback [--yes]";
+
+pub fn handle_rollback(apk: &Apk, state: &State, args: &[String]) {
+    let mut yes = false;
+    for arg in args {
+        match arg.as_str() {
+            "-y" | "--yes" => yes = true,
+            a => {
+                eprintln!("Unknown rollback option: {a}");
+                eprintln!("{USAGE}");
+                process::exit(1);
+            }
+        }
+    }
+
+    let report = match state.get_last_upgrade_report() {
+        Ok(Some(r)) => r,
+        Ok(None) => {
+            println!("No upgrade to roll back.");
+            return;
+        }
+        Err(e) => {
+            eprintln!("Could not read {VELLUM_ROOT}/state/last-upgrade.json: {e}");
+            process::exit(1);
+        }
+    };
+
+    let reverted = reverted_packages(&report.packages_upgraded);
+    let os_changed = !report.os_before.is_empty() && report.os_before != report.os_after;
+    if reverted.is_empty() && !os_changed {
+        println!("Nothing to roll back from the {} at {}.", report.op, report.timestamp);
+        return;
+    }
+
+    println!("Rolling back the {} at {}:", report.op, report.timestamp);
+    if os_changed {
+        println!("  remarkable-os {} -> {}", report.os_after, report.os_before);
+    }
+    for pkg in &reverted {
+        println!("  {} {} -> {}", pkg.name, pkg.from_version, pkg.to_version);
+    }
+    for pkg in report.packages_upgraded.iter().filter(|p| p.from_version.is_empty()) {
+        println!("  {} {} (newly installed, kept)", pkg.name, pkg.to_version);
+    }
+
+    if !yes && !confirm() {
+        println!("Rollback aborted.");
+        return;
+    }
+
+    let started = Instant::now();
+    let mut specs: Vec<String> = reverted
+        .iter()
+        .map(|p| format!("{}={}", p.name, p.to_version))
+        .collect();
+    if os_changed {
+        rebuild_os_package(&report.os_before);
+        specs.push(format!("remarkable-os={}", report.os_before));
+    }
+
+    let mut cmd_args = vec!["add"];
+    cmd_args.extend(specs.iter().map(|s| s.as_str()));
+    if let Err(e) = apk.run_with_retry(&cmd_args, 3) {
+        eprintln!("Rollback failed: {e}");
+        process::exit(1);
+    }
+
+    // `apk add name=version` pins the version in world; keep tracking the
+    // packages so a later upgrade can move them forward again.
+    let mut names: Vec<String> = reverted.iter().map(|p| p.name.clone()).collect();
+    if os_changed {
+        names.push("remarkable-os".to_string());
+    }
+    clean_world_file_pins(&names);
+
+    if os_changed {
+        match state.set_os_version(&report.os_before) {
+            Ok(()) => println!("OS version set back to {}", report.os_before),
+            Err(e) => eprintln!("warning: failed to save OS version: {e}"),
+        }
+    }
+    if let Err(e) = state.append_install_event("rollback", &names, &report.os_before) {
+        eprintln!("warning: failed to record install log: {e}");
+    }
+
+    let rollback = UpgradeReport {
+        op: "rollback".to_string(),
+        timestamp: format_rfc3339(now_unix()),
+        os_before: report.os_after.clone(),
+        os_after: report.os_before.clone(),
+        packages_upgraded: reverted,
+        hooks_run: Vec::new(),
+        hooks_failed: Vec::new(),
+        duration_secs: started.elapsed().as_secs(),
+    };
+    match state.set_last_upgrade_report(&rollback) {
+        Ok(()) => println!("Rollback report saved to {VELLUM_ROOT}/state/last-upgrade.json"),
+        Err(e) => eprintln!("warning: failed to save rollback report: {e}"),
+    }
+
+    println!("Rolled back {} package(s).", specs.len());
+    if os_changed {
+        println!("Run 'vellum reenable' to restore packages that modify the system partition.");
+    }
+}
+
+fn confirm() -> bool {
+    print!("\nProceed with rollback? [y/N] ");
+    let _ = io::stdout().flush();
+
+    let mut line = String::new();
+    let _ = io::stdin().lock().read_line(&mut line);
+    let confirm = line.trim().to_lowercase();
+    confirm == "y" || confirm == "yes"
+}
+
+/// The reverse of each upgrade in `upgraded`, so `to_version` is what the
+/// package goes back to. Packages the upgrade newly installed have no
+/// earlier version and are left alone.
+fn reverted_packages(upgraded: &[UpgradedPackage]) -> Vec<UpgradedPackage> {
+    upgraded
+        .iter()
+        .filter(|p| !p.from_version.is_empty() && p.from_version != p.to_version)
+        .map(|p| UpgradedPackage {
+            name: p.name.clone(),
+            from_version: p.to_version.clone(),
+            to_version: p.from_version.clone(),
+            installed_at: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgraded(name: &str, from: &str, to: &str) -> UpgradedPackage {
+        UpgradedPackage {
+            name: name.to_string(),
+            from_version: from.to_string(),
+            to_version: to.to_string(),
+            installed_at: Some("2024-01-31T00:00:00Z".to_string()),
+        }
+    }
+
+    #[test]
+    fn reverted_packages_swap_versions() {
+        let list = [
+            upgraded("foo", "1.0-r0", "1.1-r0"),
+            upgraded("new", "", "0.1-r0"),
+            upgraded("same", "2.0-r0", "2.0-r0"),
+        ];
+
+        let reverted = reverted_packages(&list);
+        assert_eq!(reverted.len(), 1);
+        assert_eq!(reverted[0].name, "foo");
+        assert_eq!(reverted[0].from_version, "1.1-r0");
+        assert_eq!(reverted[0].to_version, "1.0-r0");
+        assert_eq!(reverted[0].installed_at, None);
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::process;
+
+use super::index::get_index;
+use crate::apk::{Apk, Package};
+use crate::config::Config;
+use crate::util::json_string;
+
+pub fn handle_search(apk: &Apk, config: &Config, args: &[String]) {
+    let mut match_description = false;
+    let mut exact = false;
+    let mut json = false;
+    let mut all_versions = false;
+    let mut query: Option<String> = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--description" | "-d" => match_description = true,
+            "--exact" | "-e" => exact = true,
+            "--json" => json = true,
+            "--all-versions" | "-a" => all_versions = true,
+            a if a.starts_with('-') => {
+                eprintln!("Unknown search option: {a}");
+                print_usage();
+                process::exit(1);
+            }
+            _ => query = Some(arg.to_lowercase()),
+        }
+    }
+
+    let Some(query) = query else {
+        print_usage();
+        process::exit(1);
+    };
+
+    let index = match get_index(None, config, false) {
+        Ok(idx) => idx,
+        Err(e) => {
+            eprintln!("Could not get package index: {e}");
+            process::exit(1);
+        }
+    };
+
+    let os_version = apk.get_package_version("remarkable-os").ok().flatten();
+
+    let mut matches: Vec<&Package> = index
+        .iter()
+        .filter(|p| {
+            let name = p.name.to_lowercase();
+            let name_match = if exact { name == query } else { name.contains(&query) };
+            let desc_match = match_description
+                && p.description
+                    .as_deref()
+                    .is_some_and(|d| d.to_lowercase().contains(&query));
+            name_match || desc_match
+        })
+        .collect();
+
+    if !all_versions {
+        let mut latest: HashMap<&str, &Package> = HashMap::new();
+        for pkg in matches {
+            latest
+                .entry(&pkg.name)
+                .and_modify(|cur| {
+                    if pkg.version > cur.version {
+                        *cur = pkg;
+                    }
+                })
+                .or_insert(pkg);
+        }
+        matches = latest.into_values().collect();
+    }
+
+    matches.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then_with(|| b.version.cmp(&a.version))
+    });
+
+    let compat = |pkg: &Package| os_version.as_deref().map(|os| pkg.is_compatible_with_os(os));
+
+    if json {
+        let entries: Vec<String> = matches
+            .iter()
+            .map(|pkg| {
+                let compatible = match compat(pkg) {
+                    Some(c) => c.to_string(),
+                    None => "null".to_string(),
+                };
+                format!(
+                    "{{\"name\":{},\"version\":{},\"description\":{},\"compatible\":{compatible}}}",
+                    json_string(&pkg.name),
+                    json_string(pkg.version.as_str()),
+                    json_string(pkg.description.as_deref().unwrap_or(""))
+                )
+            })
+            .collect();
+        println!("[{}]", entries.join(","));
+        return;
+    }
+
+    if matches.is_empty() {
+        println!("No packages found.");
+        return;
+    }
+
+    let name_width = matches.iter().map(|p| p.name.len()).max().unwrap_or(0);
+    let ver_width = matches.iter().map(|p| p.version.as_str().len()).max().unwrap_or(0);
+
+    for pkg in &matches {
+        let desc = pkg.description.as_deref().unwrap_or("");
+        let tag = match compat(pkg) {
+            Some(true) => "  [compatible]",
+            Some(false) => "  [incompatible]",
+            None => "",
+        };
+        println!(
+            "{:<name_width$}  {:<ver_width$}  {desc}{tag}",
+            pkg.name, pkg.version
+        );
+    }
+}
+
+fn print_usage() {
+    eprintln!("Usage: vellum search [--description] [--exact] [--json] [--all-versions] <query>");
+}
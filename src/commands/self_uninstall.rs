@@ -1,10 +1,25 @@
 use std::env;
-use std::fs;
+use std::fs::{self, OpenOptions};
 use std::io::{self, BufRead, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
 use std::process;
 
-use crate::apk::Apk;
-use crate::constants::VIRTUAL_PKGS;
+use anyhow::Result;
+
+use crate::apk::{Apk, Dependency};
+use crate::constants::{VELLUM_VERSION, VIRTUAL_PKGS};
+use crate::state::State;
+use crate::util::{format_rfc3339, now_unix};
+
+const RELEASES_URL: &str = "https://github.com/vellum-dev/vellum-cli/releases/download";
+// Checksum of the bootstrap.sh this build was made from, set by build.rs.
+const BOOTSTRAP_SHA256: &str = env!("VELLUM_BOOTSTRAP_SHA256");
+
+// Files in $HOME the installer may have added vellum's PATH setup to. Login
+// shells (SSH, `su -`) read the profiles rather than .bashrc.
+const SHELL_CONFIGS: &[&str] = &[".bashrc", ".profile", ".bash_profile", ".zshrc"];
+const PROFILE_SCRIPT: &str = "/etc/profile.d/vellum.sh";
 
 pub fn handle_self_uninstall(apk: &Apk, vellum_root: &str, args: &[String]) {
     let mut uninstall_all = false;
@@ -38,10 +53,13 @@ pub fn handle_self_uninstall(apk: &Apk, vellum_root: &str, args: &[String]) {
         }
     }
 
+    write_undo_scripts(apk, vellum_root);
+
     if uninstall_all {
         println!("Removing all installed packages...");
         env::set_var("VELLUM_PURGE", "1");
         if let Ok(installed) = apk.list_installed() {
+            warn_auto_installed(&State::new(vellum_root), &installed);
             for pkg in installed {
                 if pkg == "vellum" || VIRTUAL_PKGS.contains(&pkg.as_str()) {
                     continue;
@@ -55,21 +73,241 @@ pub fn handle_self_uninstall(apk: &Apk, vellum_root: &str, args: &[String]) {
 
     println!("Removing vellum...");
 
+    clean_shell_configs();
+
+    if let Err(e) = fs::remove_dir_all(vellum_root) {
+        eprintln!("warning: failed to remove {vellum_root}: {e}");
+    }
+    println!("Vellum has been removed.");
+}
+
+fn clean_shell_configs() {
     if let Ok(home) = env::var("HOME") {
-        let bashrc = format!("{home}/.bashrc");
-        if let Ok(content) = fs::read_to_string(&bashrc) {
-            let new_lines: Vec<&str> = content
-                .lines()
-                .filter(|line| !line.contains(".vellum"))
-                .collect();
-            if let Err(e) = fs::write(&bashrc, new_lines.join("\n")) {
-                eprintln!("warning: failed to update {bashrc}: {e}");
+        for name in SHELL_CONFIGS {
+            let path = format!("{home}/{name}");
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let Some(cleaned) = without_vellum_lines(&content) else {
+                continue;
+            };
+            match fs::write(&path, cleaned) {
+                Ok(()) => println!("Removed vellum from {path}"),
+                Err(e) => eprintln!("warning: failed to update {path}: {e}"),
             }
         }
     }
 
-    if let Err(e) = fs::remove_dir_all(vellum_root) {
-        eprintln!("warning: failed to remove {vellum_root}: {e}");
+    if Path::new(PROFILE_SCRIPT).exists() {
+        match fs::remove_file(PROFILE_SCRIPT) {
+            Ok(()) => println!("Removed {PROFILE_SCRIPT}"),
+            Err(e) => eprintln!("warning: failed to remove {PROFILE_SCRIPT}: {e}"),
+        }
+    }
+}
+
+// Drops lines mentioning `.vellum`, or returns `None` when there are none so
+// untouched files aren't rewritten.
+fn without_vellum_lines(content: &str) -> Option<String> {
+    let kept: Vec<&str> = content.lines().filter(|line| !line.contains(".vellum")).collect();
+    if kept.len() == content.lines().count() {
+        return None;
+    }
+    let mut cleaned = kept.join("\n");
+    if content.ends_with('\n') && !cleaned.is_empty() {
+        cleaned.push('\n');
+    }
+    Some(cleaned)
+}
+
+// Packages not recorded as manually installed were pulled in as dependencies;
+// call them out so the user isn't surprised to see them go.
+fn warn_auto_installed(state: &State, installed: &[String]) {
+    if state.list_manual().map(|m| m.is_empty()).unwrap_or(true) {
+        return;
+    }
+
+    let auto: Vec<&str> = installed
+        .iter()
+        .filter(|p| *p != "vellum" && !VIRTUAL_PKGS.contains(&p.as_str()))
+        .filter(|p| !state.is_manual(p).unwrap_or(false))
+        .map(|p| p.as_str())
+        .collect();
+
+    if !auto.is_empty() {
+        eprintln!(
+            "warning: also removing automatically installed dependencies: {}",
+            auto.join(", ")
+        );
+    }
+}
+
+// Leaves a script behind that reinstalls vellum and the packages that were
+// installed, in case the uninstall turns out to be a mistake.
+fn write_undo_scripts(apk: &Apk, vellum_root: &str) {
+    let world = fs::read_to_string(format!("{vellum_root}/etc/apk/world")).unwrap_or_default();
+    let mut specs = undo_package_specs(&State::new(vellum_root), &world);
+    if specs.is_empty() {
+        // Without a world file, reinstall everything that is installed.
+        match apk.list_installed() {
+            Ok(list) => {
+                specs = list
+                    .into_iter()
+                    .filter(|p| p != "vellum" && !VIRTUAL_PKGS.contains(&p.as_str()))
+                    .collect();
+            }
+            Err(e) => {
+                eprintln!("warning: could not list installed packages for undo script: {e}")
+            }
+        }
+    }
+    let script = undo_script(vellum_root, &specs);
+
+    let tmp_path = format!("/tmp/vellum-undo-{}.sh", now_unix());
+    match write_script(&tmp_path, &script) {
+        Ok(()) => println!("Undo script written to {tmp_path}"),
+        Err(e) => eprintln!("warning: failed to write undo script {tmp_path}: {e}"),
+    }
+
+    if let Ok(home) = env::var("HOME") {
+        let home_path = format!("{home}/vellum-undo.sh");
+        // Replaces the script from an earlier uninstall; removing a symlink
+        // here doesn't touch what it points at.
+        let _ = fs::remove_file(&home_path);
+        if write_script(&home_path, &script).is_ok() {
+            println!("Undo script saved to {home_path}");
+        }
+    }
+}
+
+// Reinstalling only what was explicitly added lets apk pull dependencies back
+// in on its own. The world file lists every explicit install, including ones
+// from before the install log existed; the log only fills in repository tags
+// the world entry lacks. Version constraints are dropped.
+fn undo_package_specs(state: &State, world: &str) -> Vec<String> {
+    let mut specs: Vec<(String, Option<String>)> = world
+        .split_whitespace()
+        .filter_map(|entry| entry.parse::<Dependency>().ok())
+        .filter(|dep| dep.name != "vellum" && !VIRTUAL_PKGS.contains(&dep.name.as_str()))
+        .map(|dep| (dep.name, dep.tag))
+        .collect();
+
+    for event in state.get_install_log().unwrap_or_default() {
+        if event.op != "add" {
+            continue;
+        }
+        for spec in event.packages {
+            let Ok(dep) = spec.parse::<Dependency>() else {
+                continue;
+            };
+            if let Some((_, tag)) = specs.iter_mut().find(|(name, _)| *name == dep.name) {
+                if tag.is_none() {
+                    *tag = dep.tag;
+                }
+            }
+        }
+    }
+
+    let mut specs: Vec<String> = specs
+        .into_iter()
+        .map(|(name, tag)| match tag {
+            Some(tag) => format!("{name}@{tag}"),
+            None => name,
+        })
+        .collect();
+    specs.sort();
+    specs.dedup();
+    specs
+}
+
+fn undo_script(vellum_root: &str, specs: &[String]) -> String {
+    let mut script = String::from("#!/bin/sh\n");
+    script.push_str(&format!(
+        "# Generated by 'vellum self uninstall' at {}\n",
+        format_rfc3339(now_unix())
+    ));
+    script.push_str("# Reinstalls vellum and the packages that were installed.\n");
+    script.push_str("set -e\n\n");
+    script.push_str("bootstrap=$(mktemp)\n");
+    script.push_str("trap 'rm -f \"$bootstrap\"' EXIT\n");
+    script.push_str(&format!(
+        "wget --no-check-certificate -O \"$bootstrap\" {}\n",
+        bootstrap_url(VELLUM_VERSION)
+    ));
+    script.push_str(&format!("echo \"{BOOTSTRAP_SHA256}  $bootstrap\" | sha256sum -c\n"));
+    script.push_str("bash \"$bootstrap\"\n");
+    if !specs.is_empty() {
+        script.push_str(&format!("\n{vellum_root}/bin/vellum add {}\n", specs.join(" ")));
+    }
+    script
+}
+
+// The bootstrap of the release that is being uninstalled, so it matches the
+// checksum built into this binary; `latest` changes with every release.
+fn bootstrap_url(version: &str) -> String {
+    let tag = if version.starts_with('v') { version.to_string() } else { format!("v{version}") };
+    format!("{RELEASES_URL}/{tag}/bootstrap.sh")
+}
+
+// vellum runs as root, so the script is only ever created fresh: an existing
+// file or symlink at `path` makes this fail rather than be written through.
+fn write_script(path: &str, script: &str) -> Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o755).open(path)?;
+    file.write_all(script.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn undo_script_verifies_bootstrap() {
+        let script = undo_script("/home/root/.vellum", &["foo".to_string()]);
+        assert!(script.contains(&bootstrap_url(VELLUM_VERSION)));
+        assert!(script.contains("bootstrap=$(mktemp)"));
+        let check = format!("echo \"{BOOTSTRAP_SHA256}  $bootstrap\" | sha256sum -c");
+        assert!(script.contains(&check));
+        assert!(script.ends_with("/home/root/.vellum/bin/vellum add foo\n"));
+    }
+
+    #[test]
+    fn bootstrap_url_uses_release_tag() {
+        let expected = format!("{RELEASES_URL}/v0.4.0/bootstrap.sh");
+        assert_eq!(bootstrap_url("0.4.0"), expected);
+        assert_eq!(bootstrap_url("v0.4.0"), expected);
+        assert_eq!(BOOTSTRAP_SHA256.len(), 64);
+    }
+
+    #[test]
+    fn undo_package_specs_reads_world_and_log_tags() {
+        let (state, _tmp) = State::new_ephemeral();
+        state.append_install_event("add", &["bar@testing>=1.0".to_string()], "").unwrap();
+        state.append_install_event("add", &["gone".to_string()], "").unwrap();
+
+        let world = "vellum\nremarkable-os\nrm2\nfoo=1.2-r0\nbar\nbaz@testing\n";
+        assert_eq!(undo_package_specs(&state, world), vec!["bar@testing", "baz@testing", "foo"]);
+        assert!(undo_package_specs(&state, "").is_empty());
+    }
+
+    #[test]
+    fn write_script_refuses_existing_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("undo.sh");
+        let path = path.to_str().unwrap();
+
+        write_script(path, "#!/bin/sh\n").unwrap();
+        assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o755);
+        assert!(write_script(path, "#!/bin/sh\n").is_err());
+    }
+
+    #[test]
+    fn without_vellum_lines_strips_path_setup() {
+        let content = "alias ll='ls -l'\nexport PATH=\"/home/root/.vellum/bin:$PATH\"\n";
+        assert_eq!(without_vellum_lines(content).as_deref(), Some("alias ll='ls -l'\n"));
+        assert_eq!(without_vellum_lines("alias ll='ls -l'\n"), None);
+        assert_eq!(without_vellum_lines(". /home/root/.vellum/env\n").as_deref(), Some(""));
     }
-    println!("Vellum has been removed.");
 }
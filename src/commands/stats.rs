@@ -0,0 +1,162 @@
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use super::index::get_cached_index;
+use crate::apk::Apk;
+use crate::constants::VIRTUAL_PKGS;
+use crate::device::{get_apk_arch, get_device_type, get_os_version};
+use crate::state::State;
+use crate::util::{dir_size, format_mb, json_string};
+
+#[derive(Debug, Default)]
+struct Stats {
+    installed: usize,
+    user_installed: usize,
+    local_repo: usize,
+    remote_index: Option<usize>,
+    os_version: Option<String>,
+    device: Option<String>,
+    last_upgrade: Option<String>,
+    root_bytes: u64,
+    local_repo_bytes: u64,
+}
+
+impl Stats {
+    fn rows(&self) -> Vec<(&'static str, String)> {
+        let or_unknown = |v: &Option<String>| v.clone().unwrap_or_else(|| "unknown".to_string());
+        vec![
+            ("Installed packages", self.installed.to_string()),
+            ("User-installed packages", self.user_installed.to_string()),
+            ("Local repo packages", self.local_repo.to_string()),
+            (
+                "Remote index packages",
+                self.remote_index.map_or("not cached".to_string(), |n| n.to_string()),
+            ),
+            ("OS version", or_unknown(&self.os_version)),
+            ("Device", or_unknown(&self.device)),
+            (
+                "Last upgrade",
+                self.last_upgrade.clone().unwrap_or_else(|| "never".to_string()),
+            ),
+            ("Disk usage", format_mb(self.root_bytes)),
+            ("Local repo disk usage", format_mb(self.local_repo_bytes)),
+        ]
+    }
+
+    fn to_json(&self) -> String {
+        let optional = |v: &Option<String>| v.as_deref().map_or("null".to_string(), json_string);
+        format!(
+            "{{\"installed\":{},\"user_installed\":{},\"local_repo\":{},\"remote_index\":{},\
+             \"os_version\":{},\"device\":{},\"last_upgrade\":{},\"disk_usage_bytes\":{},\
+             \"local_repo_disk_usage_bytes\":{}}}",
+            self.installed,
+            self.user_installed,
+            self.local_repo,
+            self.remote_index.map_or("null".to_string(), |n| n.to_string()),
+            optional(&self.os_version),
+            optional(&self.device),
+            optional(&self.last_upgrade),
+            self.root_bytes,
+            self.local_repo_bytes
+        )
+    }
+}
+
+pub fn handle_stats(apk: &Apk, vellum_root: &str, args: &[String]) {
+    let mut json = false;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            a => {
+                eprintln!("Unknown stats option: {a}");
+                eprintln!("Usage: vellum stats [--json]");
+                process::exit(1);
+            }
+        }
+    }
+
+    let installed = match apk.list_installed() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Could not list installed packages: {e}");
+            process::exit(1);
+        }
+    };
+
+    let state = State::new(vellum_root);
+    let last_upgrade = match state.get_last_upgrade_report() {
+        Ok(report) => report.map(|r| r.timestamp),
+        Err(e) => {
+            eprintln!("warning: could not read last upgrade report: {e}");
+            None
+        }
+    };
+    let remote_index = match get_cached_index() {
+        Some(Ok(index)) => Some(index.len()),
+        Some(Err(e)) => {
+            eprintln!("warning: could not read cached package index: {e}");
+            None
+        }
+        None => None,
+    };
+
+    let local_repo = Path::new(vellum_root).join("local-repo");
+    let stats = Stats {
+        installed: installed.len(),
+        user_installed: installed
+            .iter()
+            .filter(|p| !VIRTUAL_PKGS.contains(&p.as_str()))
+            .count(),
+        local_repo: count_apks(&local_repo.join(get_apk_arch())),
+        remote_index,
+        os_version: get_os_version().ok(),
+        device: get_device_type().map(|d| d.id),
+        last_upgrade,
+        root_bytes: dir_size(Path::new(vellum_root)),
+        local_repo_bytes: dir_size(&local_repo),
+    };
+
+    if json {
+        println!("{}", stats.to_json());
+        return;
+    }
+
+    let rows = stats.rows();
+    let key_width = rows.iter().map(|(k, _)| k.len()).max().unwrap_or(0) + 1;
+    for (key, value) in rows {
+        println!("{:<key_width$} {value}", format!("{key}:"));
+    }
+}
+
+fn count_apks(dir: &Path) -> usize {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|e| e.file_name().to_string_lossy().ends_with(".apk"))
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_uses_null_for_unknown_values() {
+        let stats = Stats {
+            installed: 3,
+            user_installed: 1,
+            os_version: Some("3.20.0.92".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            stats.to_json(),
+            "{\"installed\":3,\"user_installed\":1,\"local_repo\":0,\"remote_index\":null,\
+             \"os_version\":\"3.20.0.92\",\"device\":null,\"last_upgrade\":null,\
+             \"disk_usage_bytes\":0,\"local_repo_disk_usage_bytes\":0}"
+        );
+    }
+}
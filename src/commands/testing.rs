@@ -1,42 +1,93 @@
+use std::collections::HashMap;
 use std::fs;
+use std::io::{self, BufRead, Write};
 use std::process;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+
+use super::hold::set_world_pin;
+use super::index::{get_index, write_repositories};
+use crate::apk::{fetch_remote_index, version_gt, Apk, Package, Version};
+use crate::config::Config;
+use crate::device::get_apk_arch;
+use crate::util::http_agent;
 
 const TESTING_REPO_URL: &str = "https://packages.vellum.delivery/testing";
 const TESTING_TAG: &str = "@testing";
+const CONNECTIVITY_TIMEOUT: Duration = Duration::from_secs(10);
+const USAGE: &str =
+    "Usage: vellum testing <enable [--force]|disable [--downgrade [--yes]]|status|list [--installed]>";
 
+/// Manages one tagged repository line in the repositories file. `new` is the
+/// official testing repository; `new_custom` any other `@tag url` pair.
 pub struct TestingManager {
     repos_path: String,
+    tag: String,
+    url: String,
 }
 
 impl TestingManager {
     pub fn new(vellum_root: &str) -> Self {
+        Self::new_custom(vellum_root, TESTING_TAG, TESTING_REPO_URL)
+    }
+
+    pub fn new_custom(vellum_root: &str, tag: &str, url: &str) -> Self {
         Self {
             repos_path: format!("{vellum_root}/etc/apk/repositories"),
+            tag: tag.to_string(),
+            url: url.trim_end_matches('/').to_string(),
         }
     }
 
+    // Compares the whole tag, so `@test` doesn't claim an `@testing` line.
+    fn is_own_line(&self, line: &str) -> bool {
+        line.split_whitespace().next() == Some(self.tag.as_str())
+    }
+
     pub fn is_enabled(&self) -> bool {
         let content = match fs::read_to_string(&self.repos_path) {
             Ok(c) => c,
             Err(_) => return false,
         };
 
-        content.lines().any(|line| line.trim().starts_with(TESTING_TAG))
+        content.lines().any(|line| self.is_own_line(line))
+    }
+
+    pub fn check_connectivity(&self) -> Result<()> {
+        let url = format!("{}/{}/APKINDEX.tar.gz", self.url, get_apk_arch());
+
+        let resp = http_agent(&url)?
+            .head(&url)
+            .timeout(CONNECTIVITY_TIMEOUT)
+            .call()
+            .map_err(|e| anyhow!("could not reach {}: {e}", self.url))?;
+
+        if !(200..300).contains(&resp.status()) {
+            return Err(anyhow!("{} returned HTTP {}", self.url, resp.status()));
+        }
+        Ok(())
+    }
+
+    pub fn fetch_index(&self) -> Result<Vec<Package>> {
+        fetch_remote_index(&self.url, &get_apk_arch(), None)
     }
 
-    pub fn enable(&self) -> Result<()> {
+    pub fn enable(&self, force: bool) -> Result<()> {
         let content = fs::read_to_string(&self.repos_path)?;
         let lines: Vec<&str> = content.lines().collect();
 
         for line in &lines {
-            if line.trim().starts_with(TESTING_TAG) {
+            if self.is_own_line(line) {
                 return Ok(());
             }
         }
 
-        let testing_line = format!("{TESTING_TAG} {TESTING_REPO_URL}");
+        if !force {
+            self.check_connectivity()?;
+        }
+
+        let testing_line = format!("{} {}", self.tag, self.url);
         let mut new_lines = Vec::new();
         let mut inserted = false;
 
@@ -52,8 +103,7 @@ impl TestingManager {
             new_lines.insert(0, testing_line);
         }
 
-        fs::write(&self.repos_path, new_lines.join("\n"))?;
-        Ok(())
+        write_repositories(&self.repos_path, &new_lines)
     }
 
     pub fn disable(&self) -> Result<()> {
@@ -61,15 +111,14 @@ impl TestingManager {
 
         let new_lines: Vec<&str> = content
             .lines()
-            .filter(|line| !line.trim().starts_with(TESTING_TAG))
+            .filter(|line| !self.is_own_line(line))
             .collect();
 
-        fs::write(&self.repos_path, new_lines.join("\n"))?;
-        Ok(())
+        write_repositories(&self.repos_path, &new_lines)
     }
 }
 
-pub fn handle_testing(vellum_root: &str, args: &[String]) {
+pub fn handle_testing(apk: &Apk, config: &Config, vellum_root: &str, args: &[String]) {
     let mgr = TestingManager::new(vellum_root);
 
     if args.is_empty() {
@@ -79,7 +128,7 @@ pub fn handle_testing(vellum_root: &str, args: &[String]) {
             println!("Testing repository: disabled");
         }
         println!();
-        println!("Usage: vellum testing <enable|disable|status>");
+        println!("{USAGE}");
         return;
     }
 
@@ -89,8 +138,12 @@ pub fn handle_testing(vellum_root: &str, args: &[String]) {
                 println!("Testing repository is already enabled.");
                 return;
             }
-            if let Err(e) = mgr.enable() {
+            let force = args[1..].iter().any(|a| a == "--force");
+            if let Err(e) = mgr.enable(force) {
                 eprintln!("Error enabling testing repository: {e}");
+                if !force {
+                    eprintln!("Use 'vellum testing enable --force' to skip the connectivity check.");
+                }
                 process::exit(1);
             }
             println!("Testing repository enabled.");
@@ -103,12 +156,32 @@ pub fn handle_testing(vellum_root: &str, args: &[String]) {
                 println!("Testing repository is already disabled.");
                 return;
             }
+            let downgrade = args[1..].iter().any(|a| a == "--downgrade");
+            let assume_yes = args[1..].iter().any(|a| a == "-y" || a == "--yes");
+
+            // Work out what came from testing while the repo is still known.
+            let testing_index = if downgrade {
+                match mgr.fetch_index() {
+                    Ok(idx) => Some(idx),
+                    Err(e) => {
+                        eprintln!("Could not fetch testing repository index: {e}");
+                        process::exit(1);
+                    }
+                }
+            } else {
+                None
+            };
+
             if let Err(e) = mgr.disable() {
                 eprintln!("Error disabling testing repository: {e}");
                 process::exit(1);
             }
             println!("Testing repository disabled.");
-            println!("Run 'vellum update' to refresh the package index.");
+
+            match testing_index {
+                Some(idx) => downgrade_testing_packages(apk, config, &idx, assume_yes),
+                None => println!("Run 'vellum update' to refresh the package index."),
+            }
         }
         "status" => {
             if mgr.is_enabled() {
@@ -117,10 +190,256 @@ pub fn handle_testing(vellum_root: &str, args: &[String]) {
                 println!("Testing repository: disabled");
             }
         }
+        "list" => {
+            let installed_only = args[1..].iter().any(|a| a == "--installed");
+            list_testing_packages(&mgr, apk, config, installed_only);
+        }
         cmd => {
             eprintln!("Unknown testing command: {cmd}");
-            println!("Usage: vellum testing <enable|disable|status>");
+            println!("{USAGE}");
+            process::exit(1);
+        }
+    }
+}
+
+fn list_testing_packages(mgr: &TestingManager, apk: &Apk, config: &Config, installed_only: bool) {
+    let testing = match mgr.fetch_index() {
+        Ok(idx) => idx,
+        Err(e) => {
+            eprintln!("Could not fetch testing repository index: {e}");
+            process::exit(1);
+        }
+    };
+
+    // Without the stable index we can still list testing packages, just
+    // without the comparison column.
+    let stable_index = get_index(None, config, false).unwrap_or_else(|e| {
+        eprintln!("warning: could not get stable package index: {e}");
+        Vec::new()
+    });
+    let stable = newest_versions(&stable_index);
+
+    let installed: HashMap<String, String> = if installed_only {
+        match apk.list_installed_with_versions() {
+            Ok(pkgs) => pkgs.into_iter().collect(),
+            Err(e) => {
+                eprintln!("Could not list installed packages: {e}");
+                process::exit(1);
+            }
+        }
+    } else {
+        HashMap::new()
+    };
+
+    let mut rows: Vec<(&str, &str, String)> = Vec::new();
+    let mut testing_newest: Vec<(&str, &str)> = newest_versions(&testing).into_iter().collect();
+    testing_newest.sort();
+
+    for (name, version) in testing_newest {
+        if installed_only {
+            match installed.get(name) {
+                Some(ver) if version_gt(version, ver) => {}
+                _ => continue,
+            }
+        }
+
+        let note = match stable.get(name) {
+            Some(stable_ver) if version_gt(version, stable_ver) => {
+                format!("(stable: {stable_ver})")
+            }
+            Some(_) => String::new(),
+            None => "(testing only)".to_string(),
+        };
+        rows.push((name, version, note));
+    }
+
+    if rows.is_empty() {
+        if installed_only {
+            println!("No installed packages have a testing upgrade available.");
+        } else {
+            println!("No packages in the testing repository.");
+        }
+        return;
+    }
+
+    let name_width = rows.iter().map(|(n, _, _)| n.len()).max().unwrap_or(0);
+    let ver_width = rows.iter().map(|(_, v, _)| v.len()).max().unwrap_or(0);
+
+    for (name, version, note) in &rows {
+        let line = format!("{name:<name_width$}  {version:<ver_width$}  {note}");
+        println!("{}", line.trim_end());
+    }
+}
+
+// Moves packages installed from the testing repo back to the newest stable
+// version, for when testing has just been disabled.
+fn downgrade_testing_packages(apk: &Apk, config: &Config, testing: &[Package], assume_yes: bool) {
+    let installed: Vec<(String, String)> = match apk.list_installed_with_versions() {
+        Ok(pkgs) => pkgs,
+        Err(e) => {
+            eprintln!("Could not list installed packages: {e}");
+            process::exit(1);
+        }
+    };
+
+    let from_testing: Vec<&(String, String)> = installed
+        .iter()
+        .filter(|(name, ver)| {
+            testing
+                .iter()
+                .any(|p| p.name == *name && p.version.as_str() == ver.as_str())
+        })
+        .collect();
+    if from_testing.is_empty() {
+        println!("No packages from the testing repository are installed.");
+        return;
+    }
+
+    let stable_index = match get_index(None, config, true) {
+        Ok(idx) => idx,
+        Err(e) => {
+            eprintln!("Could not get stable package index: {e}");
+            process::exit(1);
+        }
+    };
+    let stable = newest_versions(&stable_index);
+
+    let mut downgrades = Vec::new();
+    for (name, installed_ver) in from_testing {
+        match stable.get(name.as_str()) {
+            Some(stable_ver) if version_gt(installed_ver, stable_ver) => {
+                downgrades.push((name.as_str(), installed_ver.as_str(), *stable_ver));
+            }
+            Some(_) => {}
+            None => eprintln!("warning: {name} has no stable version; leaving it at {installed_ver}"),
+        }
+    }
+
+    if downgrades.is_empty() {
+        println!("No packages need to be downgraded.");
+        return;
+    }
+
+    println!("The following {} package(s) will be downgraded:", downgrades.len());
+    for (name, from, to) in &downgrades {
+        println!("  - {name} ({from} -> {to})");
+    }
+
+    if !assume_yes {
+        print!("\nProceed with downgrade? [y/N] ");
+        let _ = io::stdout().flush();
+
+        let stdin = io::stdin();
+        let mut line = String::new();
+        let _ = stdin.lock().read_line(&mut line);
+        let confirm = line.trim().to_lowercase();
+
+        if confirm != "y" && confirm != "yes" {
+            println!("Downgrade aborted.");
             process::exit(1);
         }
     }
+
+    let specs: Vec<String> = downgrades
+        .iter()
+        .map(|(name, _, to)| format!("{name}={to}"))
+        .collect();
+    let mut cmd_args = vec!["add"];
+    cmd_args.extend(specs.iter().map(|s| s.as_str()));
+    if let Err(e) = apk.run(&cmd_args) {
+        eprintln!("Downgrade failed: {e}");
+        process::exit(1);
+    }
+
+    // `apk add pkg=ver` leaves an exact pin in world; drop it so the packages
+    // keep following the stable repo.
+    for (name, _, _) in &downgrades {
+        if let Err(e) = set_world_pin(name, None) {
+            eprintln!("warning: failed to unpin {name}: {e}");
+        }
+    }
+}
+
+// Packages from tagged repositories, testing itself included, are skipped.
+fn newest_versions(index: &[Package]) -> HashMap<&str, &str> {
+    let mut newest: HashMap<&str, &Version> = HashMap::new();
+    for pkg in index.iter().filter(|p| p.tag.is_none()) {
+        let entry = newest.entry(pkg.name.as_str()).or_insert(&pkg.version);
+        if pkg.version > **entry {
+            *entry = &pkg.version;
+        }
+    }
+    newest.into_iter().map(|(name, v)| (name, v.as_str())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn temp_root(repos: &str) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("etc/apk")).unwrap();
+        fs::write(dir.path().join("etc/apk/repositories"), repos).unwrap();
+        dir
+    }
+
+    #[test]
+    fn enable_and_disable_keep_one_trailing_newline() {
+        let tmp = temp_root("/home/root/.vellum/local-repo\nhttps://example.com/main");
+        let root = tmp.path();
+        let mgr = TestingManager::new(root.to_str().unwrap());
+        let repos = root.join("etc/apk/repositories");
+
+        mgr.enable(true).unwrap();
+        let enabled = fs::read_to_string(&repos).unwrap();
+        assert_eq!(
+            enabled,
+            format!(
+                "/home/root/.vellum/local-repo\n{TESTING_TAG} {TESTING_REPO_URL}\nhttps://example.com/main\n"
+            )
+        );
+
+        mgr.disable().unwrap();
+        mgr.disable().unwrap();
+        let disabled = fs::read_to_string(&repos).unwrap();
+        assert_eq!(disabled, "/home/root/.vellum/local-repo\nhttps://example.com/main\n");
+    }
+
+    #[test]
+    fn custom_tag_leaves_testing_line_alone() {
+        let content = format!("/home/root/.vellum/local-repo\n{TESTING_TAG} {TESTING_REPO_URL}\n");
+        let tmp = temp_root(&content);
+        let root = tmp.path();
+        let mgr = TestingManager::new_custom(root.to_str().unwrap(), "@test", "https://example.com/");
+        let repos = root.join("etc/apk/repositories");
+
+        assert!(!mgr.is_enabled());
+        mgr.enable(true).unwrap();
+        assert_eq!(
+            fs::read_to_string(&repos).unwrap(),
+            format!(
+                "/home/root/.vellum/local-repo\n@test https://example.com\n\
+                 {TESTING_TAG} {TESTING_REPO_URL}\n"
+            )
+        );
+
+        mgr.disable().unwrap();
+        assert_eq!(
+            fs::read_to_string(&repos).unwrap(),
+            format!("/home/root/.vellum/local-repo\n{TESTING_TAG} {TESTING_REPO_URL}\n")
+        );
+    }
+
+    #[test]
+    fn disable_drops_trailing_blank_lines() {
+        let tmp =
+            temp_root(&format!("https://example.com/main\n{TESTING_TAG} {TESTING_REPO_URL}\n\n\n"));
+        let root = tmp.path();
+        let mgr = TestingManager::new(root.to_str().unwrap());
+
+        mgr.disable().unwrap();
+        let content = fs::read_to_string(root.join("etc/apk/repositories")).unwrap();
+        assert_eq!(content, "https://example.com/main\n");
+    }
 }
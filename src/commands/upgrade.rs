@@ -1,54 +1,171 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::fs;
 use std::io::{self, BufRead, Write};
+use std::os::unix::fs::PermissionsExt;
 use std::process;
+use std::time::{Duration, Instant, SystemTime};
 
+use super::diff::save_snapshot;
+use super::hold::{installed_version, set_world_pin};
+use super::index::{get_index, get_remote_index};
+use super::reenable::{hook_timeout, packages_with_hooks, run_hooks, HookSummary};
 use crate::apk::{
-    check_os_compatibility, generate_remarkable_os_package, fetch_remote_index,
-    parse_index_tar_gz, version_lt, Apk, Package,
+    check_os_compatibility, generate_remarkable_os_package, version_eq,
+    version_lt, Apk, CompatResult, Package,
 };
+use crate::config::Config;
 use crate::constants::{VELLUM_ROOT, VIRTUAL_PKGS};
 use crate::device::get_apk_arch;
 use crate::repo::update_index;
-use crate::state::State;
-use crate::util::remove_glob;
+use crate::state::{State, UpgradeReport, UpgradedPackage};
+use crate::util::{
+    available_space, format_mb, format_rfc3339, line_ending, now_unix, remove_glob, unix_secs,
+};
 
 pub fn handle_upgrade(
     state: &State,
     apk: &Apk,
+    config: &Config,
     args: &[String],
     os_mismatch: bool,
     os_prev: &str,
     os_cur: &str,
 ) {
-    let mut upgrade_yes = false;
+    ensure_apk_binary(apk);
+
+    let started = Instant::now();
+    let timeout = hook_timeout(config);
+    let mut upgrade_yes = !config.confirm_upgrades;
+    let mut dry_run = false;
+    let mut force_refresh = false;
+    let mut force_remote = false;
+    let mut ignore_hold = false;
+    let mut ignore_disk_check = false;
+    let mut no_reenable = false;
+    let mut auto_reenable =
+        env::var("VELLUM_AUTO_REENABLE").map_or(config.auto_reenable, |v| v == "1");
+    let mut unpin_all = false;
     let mut remaining_args = Vec::new();
+    let mut package_names = Vec::new();
+    let mut skip = Vec::new();
 
-    for arg in args {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
         match arg.as_str() {
+            "--skip" => match iter.next() {
+                Some(pkg) => skip.push(pkg.clone()),
+                None => {
+                    eprintln!("Error: --skip requires a package name");
+                    process::exit(1);
+                }
+            },
             "-y" | "--yes" => upgrade_yes = true,
-            _ => remaining_args.push(arg.clone()),
+            "--dry-run" => dry_run = true,
+            "--force-refresh" | "--no-cache" => force_refresh = true,
+            "--force-remote" => force_remote = true,
+            "--ignore-hold" => ignore_hold = true,
+            "--ignore-disk-check" => ignore_disk_check = true,
+            "--no-reenable" => no_reenable = true,
+            "--auto-reenable" => auto_reenable = true,
+            "--unpin-all" => unpin_all = true,
+            a if a.starts_with('-') => remaining_args.push(arg.clone()),
+            _ => package_names.push(arg.clone()),
+        }
+    }
+
+    // Skips are remembered until an upgrade succeeds or finds nothing to do,
+    // so retrying after an aborted or failed run doesn't quietly pick the
+    // skipped packages back up.
+    let had_skip_args = !skip.is_empty();
+    skip.extend(state.list_skip_once().unwrap_or_default());
+    if had_skip_args && !dry_run {
+        if let Err(e) = state.set_skip_once(&skip) {
+            eprintln!("warning: failed to save skipped packages: {e}");
+        }
+    }
+
+    let held = state.list_held().unwrap_or_else(|e| {
+        eprintln!("warning: could not read held packages: {e}");
+        Vec::new()
+    });
+    let is_held = |pkg: &str| !ignore_hold && held.iter().any(|(name, _)| name == pkg);
+
+    let mut pinned = state.list_pinned().unwrap_or_else(|e| {
+        eprintln!("warning: could not read pinned packages: {e}");
+        Vec::new()
+    });
+    if unpin_all && !dry_run {
+        release_pins(state, &pinned);
+        pinned.clear();
+    }
+    let is_pinned = |pkg: &str| pinned.iter().any(|(name, _)| name == pkg);
+
+    if !package_names.is_empty() {
+        if let Ok(installed) = apk.list_installed() {
+            let missing: Vec<&String> = package_names
+                .iter()
+                .filter(|p| !installed.contains(p))
+                .collect();
+            if !missing.is_empty() {
+                for pkg in missing {
+                    eprintln!("Error: '{pkg}' is not installed.");
+                }
+                process::exit(1);
+            }
+        }
+
+        package_names.retain(|p| !is_held(p) && !is_pinned(p));
+        if package_names.is_empty() {
+            print_held_notes(&held);
+            print_pinned_notes(&pinned);
+            if !dry_run {
+                clear_skips(state);
+            }
+            println!("No packages to upgrade.");
+            return;
+        }
+
+        // A partial upgrade during an OS change must still move remarkable-os
+        // to the new version, otherwise the OS sync below can never succeed.
+        if os_mismatch && !package_names.iter().any(|p| p == "remarkable-os") {
+            package_names.push("remarkable-os".to_string());
         }
     }
 
     let is_downgrade = os_mismatch && version_lt(os_cur, os_prev);
+    // World contents from before the planned versions were pinned there for a
+    // full upgrade; put back once the upgrade is over.
+    let mut world_before_pins: Option<String> = None;
 
     if os_mismatch {
         let action = if is_downgrade { "downgraded" } else { "upgraded" };
         println!("OS {action} ({os_prev} -> {os_cur}). Checking package compatibility...");
         println!();
 
-        let incompatible = check_os_compatibility_internal(apk, os_cur);
-        if incompatible.is_none() {
+        let compat = check_os_compatibility_internal(
+            apk,
+            (!dry_run).then_some(state),
+            config,
+            os_cur,
+            force_refresh,
+            force_remote,
+        );
+        if compat.is_none() {
             eprintln!("Could not fetch package index to verify compatibility.");
             eprintln!("Check your network connection and try again.");
             process::exit(1);
         }
 
-        let incompatible = incompatible.unwrap();
-        if !incompatible.is_empty() {
+        let compat = compat.unwrap();
+        if !compat.incompatible.is_empty() {
             println!("These packages have no version compatible with OS {os_cur}:");
-            for pkg in &incompatible {
-                println!("  - {pkg}");
+            for pkg in &compat.incompatible {
+                match compat.incompatible_nearest.get(pkg) {
+                    Some(Some(os)) => println!("  - {pkg} (available for OS {os} and later)"),
+                    _ => println!("  - {pkg}"),
+                }
             }
             println!();
             println!("Either wait for them to be updated, or remove them with 'vellum del <package>'.");
@@ -56,32 +173,81 @@ pub fn handle_upgrade(
             process::exit(1);
         }
 
-        println!("All packages have compatible versions. Preparing upgrade...");
+        // Pin each package to the version the plan picked, so apk can't pull in
+        // a newer release that doesn't support the new OS. Naming them would
+        // turn a full upgrade into one of only these packages, so a full
+        // upgrade pins them in world for its duration instead.
+        let mut planned: Vec<(&String, &String)> = compat
+            .plan
+            .iter()
+            .filter(|(pkg, _)| package_names.is_empty() || package_names.contains(pkg))
+            .filter(|(pkg, _)| !is_held(pkg) && !is_pinned(pkg))
+            .collect();
+        planned.sort();
+        let mut world_pins = Vec::new();
+        if package_names.is_empty() {
+            world_pins = planned;
+        } else if !planned.is_empty() {
+            package_names.retain(|p| !compat.plan.contains_key(p));
+            if !package_names.iter().any(|p| p == "remarkable-os") {
+                package_names.push("remarkable-os".to_string());
+            }
+            package_names.extend(planned.iter().map(|(pkg, ver)| format!("{pkg}={ver}")));
+        }
 
-        let arch = get_apk_arch();
-        let repo_dir = format!("{VELLUM_ROOT}/local-repo/{arch}");
-        let key_path = format!("{VELLUM_ROOT}/etc/apk/keys/local.rsa");
+        if dry_run {
+            println!("All packages have compatible versions.");
+            println!("Dry run: the remarkable-os package would be updated to {os_cur}.");
+            println!();
+        } else {
+            println!("All packages have compatible versions. Preparing upgrade...");
+
+            rebuild_os_package(os_cur);
+
+            let keep: Vec<&str> = held
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .filter(|name| is_held(name))
+                .chain(pinned.iter().map(|(name, _)| name.as_str()))
+                .collect();
+            clean_world_file_pins(apk, &keep);
+
+            if is_downgrade {
+                let pkg_version = format!("remarkable-os={os_cur}-r0");
+                if let Err(e) = apk.run(&["add", &pkg_version]) {
+                    eprintln!("warning: failed to downgrade remarkable-os package: {e}");
+                }
+            }
 
-        if let Err(e) = fs::create_dir_all(&repo_dir) {
-            eprintln!("warning: failed to create repo directory: {e}");
-        }
-        remove_glob(&format!("{repo_dir}/remarkable-os-*.apk"));
-        if let Err(e) = generate_remarkable_os_package(os_cur, &repo_dir, &key_path) {
-            eprintln!("warning: failed to generate remarkable-os package: {e}");
-        }
-        if let Err(e) = update_index(&repo_dir, Some(&key_path)) {
-            eprintln!("warning: failed to update local repo index: {e}");
+            if !world_pins.is_empty() {
+                world_before_pins = pin_planned_versions(&world_pins);
+            }
         }
+    }
 
-        clean_world_file_pins(apk);
+    remaining_args.extend(package_names);
 
-        if is_downgrade {
-            let pkg_version = format!("remarkable-os={os_cur}-r0");
-            if let Err(e) = apk.run(&["add", &pkg_version]) {
-                eprintln!("warning: failed to downgrade remarkable-os package: {e}");
+    // Held packages are pinned in world, so apk won't move them until the pin
+    // is lifted. They are re-pinned at whatever version ends up installed.
+    let release_holds = ignore_hold && !dry_run && !held.is_empty();
+    if release_holds {
+        for (pkg, _) in &held {
+            if let Err(e) = set_world_pin(pkg, None) {
+                eprintln!("warning: failed to release hold on {pkg}: {e}");
             }
         }
     }
+    let reapply = || {
+        if let Some(content) = &world_before_pins {
+            let world_path = format!("{VELLUM_ROOT}/etc/apk/world");
+            if let Err(e) = fs::write(&world_path, content) {
+                eprintln!("warning: failed to remove temporary pins from {world_path}: {e}");
+            }
+        }
+        if release_holds {
+            reapply_holds(apk, state, &held);
+        }
+    };
 
     let mut simulate_args = vec!["upgrade", "--simulate"];
     if is_downgrade {
@@ -93,6 +259,7 @@ pub fn handle_upgrade(
         Ok(o) => o,
         Err(e) => {
             eprintln!("Failed to check for upgrades: {e}");
+            reapply();
             process::exit(1);
         }
     };
@@ -111,14 +278,44 @@ pub fn handle_upgrade(
         }
     }
 
+    if !ignore_hold && !held.is_empty() {
+        print_held_notes(&held);
+        packages.retain(|p| !is_held(p));
+    }
+    if !pinned.is_empty() {
+        print_pinned_notes(&pinned);
+        packages.retain(|p| !is_pinned(p));
+    }
+
+    let skipped: Vec<String> = packages.iter().filter(|p| skip.contains(p)).cloned().collect();
+    for pkg in &skipped {
+        println!("Skipping: {pkg}");
+    }
+    if os_mismatch && skipped.iter().any(|p| p == "remarkable-os") {
+        eprintln!("warning: skipping remarkable-os means the OS version sync will not complete");
+    }
+    packages.retain(|p| !skip.contains(p));
+
+    if dry_run {
+        if packages.is_empty() {
+            println!("No packages to upgrade.");
+        } else {
+            println!("The following {} package(s) would be upgraded:", packages.len());
+            for pkg in &packages {
+                println!("  - {pkg}");
+            }
+        }
+        return;
+    }
+
     if packages.is_empty() {
+        reapply();
+        clear_skips(state);
         if os_mismatch {
             match apk.get_package_version("remarkable-os") {
-                Ok(Some(installed_ver)) if installed_ver == os_cur => {
-                    if let Err(e) = state.set_os_version(os_cur) {
-                        eprintln!("warning: failed to save OS version: {e}");
-                    }
-                    println!("OS version synced to {os_cur}");
+                Ok(Some(installed_ver)) if version_eq(&installed_ver, os_cur) => {
+                    let hooks = sync_os_version(state, os_cur, no_reenable, timeout);
+                    save_upgrade_report(state, os_prev, os_cur, Vec::new(), hooks, started);
                 }
                 _ => {}
             }
@@ -127,11 +324,44 @@ pub fn handle_upgrade(
         return;
     }
 
+    let size = if ignore_disk_check && upgrade_yes {
+        None
+    } else {
+        match load_index((!dry_run).then_some(state), config, force_refresh, force_remote) {
+            Ok(index) => Some(estimate_download_size(&index, &packages, &remaining_args)),
+            Err(e) => {
+                eprintln!("warning: could not estimate upgrade size: {e}");
+                None
+            }
+        }
+    };
+
+    if let Some(size) = size.as_ref().filter(|_| !ignore_disk_check) {
+        if !check_disk_space(size.bytes) {
+            println!("Upgrade aborted.");
+            reapply();
+            process::exit(1);
+        }
+    }
+
+    // An OS sync runs every hook anyway, unless --no-reenable.
+    let hooked = packages_with_hooks(&packages);
+    let hooks_will_run = auto_reenable || (os_mismatch && !no_reenable);
+    if !hooks_will_run {
+        for pkg in &hooked {
+            println!("Note: {pkg} has a post-upgrade hook; run 'vellum reenable' after upgrading.");
+        }
+    }
+
     if !upgrade_yes {
         println!("The following {} package(s) will be upgraded:", packages.len());
         for pkg in &packages {
             println!("  - {pkg}");
         }
+        match &size {
+            Some(size) => println!("\nTotal download size: {size}"),
+            None => println!("\nTotal download size: unknown"),
+        }
         print!("\nProceed with upgrade? [y/N] ");
         let _ = io::stdout().flush();
 
@@ -142,28 +372,54 @@ pub fn handle_upgrade(
 
         if confirm != "y" && confirm != "yes" {
             println!("Upgrade aborted.");
+            reapply();
             process::exit(1);
         }
     }
 
+    // apk has no way to exclude a package from a full upgrade, so with skips
+    // the remaining packages are named explicitly (keeping any planned pins).
+    let targets: Vec<String> = if skipped.is_empty() {
+        remaining_args.clone()
+    } else {
+        let flags = remaining_args.iter().filter(|a| a.starts_with('-')).cloned();
+        let named = packages.iter().map(|p| {
+            let pinned = format!("{p}=");
+            remaining_args
+                .iter()
+                .find(|a| a.starts_with(&pinned))
+                .cloned()
+                .unwrap_or_else(|| p.clone())
+        });
+        flags.chain(named).collect()
+    };
+
     let mut upgrade_args = vec!["upgrade"];
     if is_downgrade {
         upgrade_args.push("--available");
     }
-    upgrade_args.extend(remaining_args.iter().map(|s| s.as_str()));
+    upgrade_args.extend(targets.iter().map(|s| s.as_str()));
+
+    let before: HashMap<String, String> = apk
+        .list_installed_with_versions()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
 
     if os_mismatch {
-        if let Err(e) = apk.run(&upgrade_args) {
+        if let Err(e) = apk.run_with_retry(&upgrade_args, 3) {
             eprintln!("upgrade error: {e}");
+            reapply();
             process::exit(1);
         }
+        let upgraded = record_upgrade(apk, state, &before, &packages, os_cur);
+        reapply();
+        clear_skips(state);
 
+        let mut hooks = HookSummary::default();
         match apk.get_package_version("remarkable-os") {
-            Ok(Some(installed_ver)) if installed_ver == os_cur => {
-                if let Err(e) = state.set_os_version(os_cur) {
-                    eprintln!("warning: failed to save OS version: {e}");
-                }
-                println!("OS version synced to {os_cur}");
+            Ok(Some(installed_ver)) if version_eq(&installed_ver, os_cur) => {
+                hooks = sync_os_version(state, os_cur, no_reenable, timeout);
             }
             Ok(Some(installed_ver)) => {
                 eprintln!("error: remarkable-os package is at {installed_ver}, expected {os_cur}");
@@ -178,93 +434,361 @@ pub fn handle_upgrade(
                 eprintln!("warning: could not verify remarkable-os version: {e}");
             }
         }
+        save_upgrade_report(state, os_prev, os_cur, upgraded, hooks, started);
     } else {
-        if let Err(e) = apk.exec(&upgrade_args) {
-            eprintln!("exec error: {e}");
+        if let Err(e) = apk.run_with_retry(&upgrade_args, 3) {
+            eprintln!("upgrade error: {e}");
+            reapply();
             process::exit(1);
         }
+        let upgraded = record_upgrade(apk, state, &before, &packages, os_cur);
+        reapply();
+        clear_skips(state);
+        let hooks = if auto_reenable && !hooked.is_empty() {
+            run_hooks(state, false, timeout)
+        } else {
+            HookSummary::default()
+        };
+        save_upgrade_report(state, os_prev, os_cur, upgraded, hooks, started);
     }
 }
 
-fn check_os_compatibility_internal(apk: &Apk, target_os: &str) -> Option<Vec<String>> {
-    let installed = match apk.list_installed() {
-        Ok(list) => list,
-        Err(_) => return None,
-    };
+/// Replaces the remarkable-os package in the local repo with one for `os`,
+/// so apk can install it.
+pub fn rebuild_os_package(os: &str) {
+    let arch = get_apk_arch();
+    let repo_dir = format!("{VELLUM_ROOT}/local-repo/{arch}");
+    let key_path = format!("{VELLUM_ROOT}/etc/apk/keys/local.rsa");
 
-    let filtered: Vec<String> = installed
-        .into_iter()
-        .filter(|p| !VIRTUAL_PKGS.contains(&p.as_str()))
-        .collect();
+    if let Err(e) = fs::create_dir_all(&repo_dir) {
+        eprintln!("warning: failed to create repo directory: {e}");
+    }
+    if let Err(e) = remove_glob(&format!("{repo_dir}/remarkable-os-*.apk")) {
+        eprintln!("warning: failed to remove old remarkable-os package: {e}");
+    }
+    if let Err(e) = generate_remarkable_os_package(os, &repo_dir, &key_path) {
+        eprintln!("warning: failed to generate remarkable-os package: {e}");
+    }
+    if let Err(e) = update_index(&repo_dir, Some(&key_path)) {
+        eprintln!("warning: failed to update local repo index: {e}");
+    }
+}
 
-    if filtered.is_empty() {
-        return Some(Vec::new());
+// Without apk.vellum the upgrade would fail part way through, after the OS
+// package or world file may already have been changed.
+fn ensure_apk_binary(apk: &Apk) {
+    let path = apk.bin_path();
+    let executable = fs::metadata(&path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0);
+    if !executable {
+        eprintln!("vellum internal binary missing: {}. Try reinstalling vellum.", path.display());
+        process::exit(1);
     }
+}
 
-    let index = match get_index() {
-        Ok(idx) => idx,
-        Err(_) => return None,
+// Hooks put back what the OS update wiped from the system partition, so they
+// run as soon as the new OS version is recorded.
+fn sync_os_version(
+    state: &State,
+    os_cur: &str,
+    no_reenable: bool,
+    timeout: Duration,
+) -> HookSummary {
+    if let Err(e) = state.set_os_version(os_cur) {
+        eprintln!("warning: failed to save OS version: {e}");
+        eprintln!("Run 'vellum reenable' to restore packages that modify the system partition.");
+        return HookSummary::default();
+    }
+    println!("OS version synced to {os_cur}");
+
+    if no_reenable {
+        println!("Run 'vellum reenable' to restore packages that modify the system partition.");
+        return HookSummary::default();
+    }
+    println!();
+    println!("Running post-upgrade hooks...");
+    let hooks = run_hooks(state, false, timeout);
+    println!("Post-upgrade complete.");
+    hooks
+}
+
+fn save_upgrade_report(
+    state: &State,
+    os_before: &str,
+    os_after: &str,
+    packages_upgraded: Vec<UpgradedPackage>,
+    hooks: HookSummary,
+    started: Instant,
+) {
+    let report = UpgradeReport {
+        op: "upgrade".to_string(),
+        timestamp: format_rfc3339(now_unix()),
+        os_before: os_before.to_string(),
+        os_after: os_after.to_string(),
+        packages_upgraded,
+        hooks_run: hooks.run,
+        hooks_failed: hooks.failed,
+        duration_secs: started.elapsed().as_secs(),
+    };
+    match state.set_last_upgrade_report(&report) {
+        Ok(()) => println!("Upgrade report saved to {VELLUM_ROOT}/state/last-upgrade.json"),
+        Err(e) => eprintln!("warning: failed to save upgrade report: {e}"),
+    }
+}
+
+// apk keeps the downloaded archive while unpacking it, so twice the
+// compressed size is a rough lower bound on what an upgrade needs.
+fn check_disk_space(download_bytes: u64) -> bool {
+    let available = match available_space(VELLUM_ROOT) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("warning: could not check free disk space: {e}");
+            return true;
+        }
     };
 
-    let mut installed_with_os_dep = Vec::new();
-    for pkg in &filtered {
-        if let Ok(deps) = apk.get_dependencies(pkg) {
-            if deps.iter().any(|d| d.contains("remarkable-os")) {
-                installed_with_os_dep.push(pkg.clone());
-            }
+    let needed = 2 * download_bytes;
+    if available >= needed {
+        return true;
+    }
+
+    eprintln!(
+        "warning: low disk space (available: {}, estimated needed: {})",
+        format_mb(available),
+        format_mb(needed)
+    );
+    print!("Continue anyway? (--ignore-disk-check skips this check) [y/N] ");
+    let _ = io::stdout().flush();
+
+    let mut line = String::new();
+    let _ = io::stdin().lock().read_line(&mut line);
+    let confirm = line.trim().to_lowercase();
+    confirm == "y" || confirm == "yes"
+}
+
+/// Compressed size of what an upgrade downloads. `unknown` counts packages
+/// whose index entry has no size, which are left out of `bytes`.
+#[derive(Debug, PartialEq)]
+struct DownloadSize {
+    bytes: u64,
+    unknown: usize,
+}
+
+impl fmt::Display for DownloadSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.unknown {
+            0 => write!(f, "{}", format_mb(self.bytes)),
+            n if self.bytes == 0 => write!(f, "size unknown for {n} package(s)"),
+            n => write!(f, "{} (size unknown for {n} package(s))", format_mb(self.bytes)),
         }
     }
+}
 
-    if installed_with_os_dep.is_empty() {
-        return Some(Vec::new());
+/// Sums the compressed size of the versions an upgrade would fetch: the
+/// pinned version from `targets` (`pkg=ver`), otherwise the newest one.
+fn estimate_download_size(
+    index: &[Package],
+    packages: &[String],
+    targets: &[String],
+) -> DownloadSize {
+    let mut size = DownloadSize { bytes: 0, unknown: 0 };
+    for name in packages {
+        let prefix = format!("{name}=");
+        let pinned = targets.iter().find_map(|t| t.strip_prefix(&prefix));
+        let pkg = index
+            .iter()
+            .filter(|p| &p.name == name)
+            .filter(|p| pinned.is_none_or(|v| p.version.as_str() == v))
+            .max_by(|a, b| a.version.cmp(&b.version));
+        match pkg.and_then(|p| p.size) {
+            Some(bytes) => size.bytes += bytes,
+            None => size.unknown += 1,
+        }
     }
+    size
+}
 
-    let result = check_os_compatibility(target_os, &installed_with_os_dep, &index);
-    Some(result.incompatible)
+fn clear_skips(state: &State) {
+    if let Err(e) = state.clear_skip_once() {
+        eprintln!("warning: failed to clear skipped packages: {e}");
+    }
 }
 
-fn get_index() -> anyhow::Result<Vec<Package>> {
-    let cache_dir = format!("{VELLUM_ROOT}/etc/apk/cache");
+fn print_held_notes(held: &[(String, String)]) {
+    for (pkg, version) in held {
+        println!("Held: {pkg} (pinned to {version})");
+    }
+}
 
-    if let Ok(entries) = fs::read_dir(&cache_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.starts_with("APKINDEX.") && name.ends_with(".tar.gz") {
-                    if let Some(path_str) = path.to_str() {
-                        return parse_index_tar_gz(path_str);
-                    }
-                }
-            }
+fn print_pinned_notes(pinned: &[(String, String)]) {
+    for (pkg, version) in pinned {
+        println!("Pinned: {pkg} (at {version}; 'vellum unpin {pkg}' or --unpin-all to upgrade)");
+    }
+}
+
+fn release_pins(state: &State, pinned: &[(String, String)]) {
+    for (pkg, _) in pinned {
+        let result = set_world_pin(pkg, None).and_then(|()| state.remove_pinned(pkg));
+        if let Err(e) = result {
+            eprintln!("warning: failed to unpin {pkg}: {e}");
+            continue;
         }
+        println!("Unpinned: {pkg}");
     }
+}
 
-    let repo_url = match get_repo_url() {
-        Some(url) => url,
-        None => return Err(anyhow::anyhow!("no cached index and could not determine repository URL")),
+// Pins each package in world at its planned version and returns the world
+// file as it was before, or `None` if it couldn't be read.
+fn pin_planned_versions(planned: &[(&String, &String)]) -> Option<String> {
+    let world_path = format!("{VELLUM_ROOT}/etc/apk/world");
+    let content = match fs::read_to_string(&world_path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("warning: could not read {world_path}, planned versions not pinned: {e}");
+            return None;
+        }
     };
+    for (pkg, version) in planned {
+        if let Err(e) = set_world_pin(pkg, Some(version)) {
+            eprintln!("warning: failed to pin {pkg} to {version}: {e}");
+        }
+    }
+    Some(content)
+}
 
-    let arch = get_apk_arch();
-    fetch_remote_index(&repo_url, &arch)
+fn reapply_holds(apk: &Apk, state: &State, held: &[(String, String)]) {
+    for (pkg, old_version) in held {
+        let version = installed_version(apk, pkg).unwrap_or_else(|| old_version.clone());
+        if let Err(e) = set_world_pin(pkg, Some(&version)) {
+            eprintln!("warning: failed to re-pin {pkg}: {e}");
+        }
+        if let Err(e) = state.set_held(pkg, &version) {
+            eprintln!("warning: failed to save hold for {pkg}: {e}");
+        }
+    }
 }
 
-fn get_repo_url() -> Option<String> {
-    let repos_file = format!("{VELLUM_ROOT}/etc/apk/repositories");
-    let content = fs::read_to_string(repos_file).ok()?;
+// Logs the upgrade and snapshots the result, returning the version change of
+// each upgraded package.
+fn record_upgrade(
+    apk: &Apk,
+    state: &State,
+    before: &HashMap<String, String>,
+    packages: &[String],
+    os: &str,
+) -> Vec<UpgradedPackage> {
+    if let Err(e) = state.append_install_event("upgrade", packages, os) {
+        eprintln!("warning: failed to record install log: {e}");
+    }
+    save_snapshot(apk, state);
 
-    for line in content.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
+    let after: HashMap<String, String> = apk
+        .list_installed_with_versions()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let mut changes = version_changes(before, &after, packages);
+
+    // The report keeps how long the old version was installed; the new one
+    // starts its own clock.
+    let now = SystemTime::now();
+    for change in &mut changes {
+        let installed = state.get_install_time(&change.name).ok().flatten();
+        change.installed_at = installed.map(|t| format_rfc3339(unix_secs(t)));
+        if installed.is_some() {
+            if let Err(e) = state.set_install_time(&change.name, now) {
+                eprintln!("warning: failed to record install time for {}: {e}", change.name);
+            }
         }
-        if !line.contains("local-repo") {
-            return Some(line.to_string());
+    }
+    changes
+}
+
+
+fn version_changes(
+    before: &HashMap<String, String>,
+    after: &HashMap<String, String>,
+    packages: &[String],
+) -> Vec<UpgradedPackage> {
+    packages
+        .iter()
+        .map(|name| UpgradedPackage {
+            name: name.clone(),
+            from_version: before.get(name).cloned().unwrap_or_default(),
+            to_version: after.get(name).cloned().unwrap_or_default(),
+            installed_at: None,
+        })
+        .collect()
+}
+
+fn load_index(
+    state: Option<&State>,
+    config: &Config,
+    force_refresh: bool,
+    force_remote: bool,
+) -> anyhow::Result<Vec<Package>> {
+    if force_remote {
+        get_remote_index(config)
+    } else {
+        get_index(state, config, force_refresh)
+    }
+}
+
+fn check_os_compatibility_internal(
+    apk: &Apk,
+    state: Option<&State>,
+    config: &Config,
+    target_os: &str,
+    force_refresh: bool,
+    force_remote: bool,
+) -> Option<CompatResult> {
+    let out = match apk.run_capture(&["info", "-q"]) {
+        Ok(out) => out,
+        Err(_) => return None,
+    };
+    for line in out.stderr.lines() {
+        eprintln!("warning: apk: {line}");
+    }
+    if out.code != 0 {
+        return None;
+    }
+
+    let filtered: Vec<String> = out
+        .stdout
+        .lines()
+        .filter(|p| !VIRTUAL_PKGS.contains(p))
+        .map(|p| p.to_string())
+        .collect();
+
+    if filtered.is_empty() {
+        return Some(CompatResult::default());
+    }
+
+    let index = match load_index(state, config, force_refresh, force_remote) {
+        Ok(idx) => idx,
+        Err(_) => return None,
+    };
+
+    let mut installed_with_os_dep = Vec::new();
+    for pkg in &filtered {
+        match apk.get_dependencies(pkg) {
+            Ok(deps) => {
+                if deps.iter().any(|d| d.name == "remarkable-os" && !d.conflict) {
+                    installed_with_os_dep.push(pkg.clone());
+                }
+            }
+            Err(e) => eprintln!("warning: could not read dependencies of {pkg}: {e}"),
         }
     }
-    None
+
+    if installed_with_os_dep.is_empty() {
+        return Some(CompatResult::default());
+    }
+
+    let arch = get_apk_arch();
+    Some(check_os_compatibility(target_os, &installed_with_os_dep, &index, Some(&arch)))
 }
 
-fn clean_world_file_pins(apk: &Apk) {
+fn clean_world_file_pins(apk: &Apk, keep: &[&str]) {
     let installed = match apk.list_installed() {
         Ok(list) => list,
         Err(_) => return,
@@ -273,9 +797,10 @@ fn clean_world_file_pins(apk: &Apk) {
     let mut packages_to_unpin: Vec<String> = installed
         .into_iter()
         .filter(|p| !VIRTUAL_PKGS.contains(&p.as_str()))
+        .filter(|p| !keep.contains(&p.as_str()))
         .filter(|p| {
             if let Ok(deps) = apk.get_dependencies(p) {
-                deps.iter().any(|d| d.contains("remarkable-os"))
+                deps.iter().any(|d| d.name == "remarkable-os" && !d.conflict)
             } else {
                 false
             }
@@ -290,6 +815,7 @@ fn clean_world_file_pins(apk: &Apk) {
         Err(_) => return,
     };
 
+    let eol = line_ending(&content);
     let new_content: String = content
         .lines()
         .map(|line| {
@@ -301,8 +827,78 @@ fn clean_world_file_pins(apk: &Apk) {
             line.to_string()
         })
         .collect::<Vec<_>>()
-        .join("\n");
+        .join(eol);
 
-    let _ = fs::write(&world_path, new_content + "\n");
+    let _ = fs::write(&world_path, new_content + eol);
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pkg(name: &str, version: &str, size: u64) -> Package {
+        Package {
+            name: name.to_string(),
+            version: version.into(),
+            size: Some(size),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn download_size_uses_newest_or_pinned_version() {
+        let index = vec![
+            pkg("foo", "1.0-r0", 100),
+            pkg("foo", "2.0-r0", 300),
+            pkg("bar", "1.0-r0", 50),
+            pkg("bar", "1.1-r0", 70),
+        ];
+        let packages = vec!["foo".to_string(), "bar".to_string()];
+        let targets = vec!["--no-cache".to_string(), "bar=1.0-r0".to_string()];
+
+        assert_eq!(estimate_download_size(&index, &packages, &targets).bytes, 350);
+        assert_eq!(estimate_download_size(&index, &packages, &[]).bytes, 370);
+    }
+
+    #[test]
+    fn download_size_counts_unknown_packages() {
+        let mut no_size = pkg("bar", "1.0-r0", 0);
+        no_size.size = None;
+        let index = vec![pkg("foo", "1.0-r0", 100), no_size];
+        let packages = vec!["foo".to_string(), "bar".to_string(), "missing".to_string()];
+
+        let size = estimate_download_size(&index, &packages, &[]);
+        assert_eq!(size, DownloadSize { bytes: 100, unknown: 2 });
+    }
+
+    #[test]
+    fn version_changes_pairs_before_and_after() {
+        let map = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+        };
+        let before = map(&[("foo", "1.0-r0"), ("bar", "2.0-r0")]);
+        let after = map(&[("foo", "1.1-r0"), ("bar", "2.0-r0"), ("new", "0.1-r0")]);
+
+        let changes = version_changes(&before, &after, &["foo".to_string(), "new".to_string()]);
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].from_version, "1.0-r0");
+        assert_eq!(changes[0].to_version, "1.1-r0");
+        assert_eq!(changes[1].from_version, "");
+        assert_eq!(changes[1].to_version, "0.1-r0");
+    }
+
+    #[test]
+    fn download_size_display() {
+        let mb = 1024 * 1024;
+        assert_eq!(DownloadSize { bytes: 3 * mb / 2, unknown: 0 }.to_string(), "1.5 MB");
+        assert_eq!(
+            DownloadSize { bytes: mb, unknown: 1 }.to_string(),
+            "1.0 MB (size unknown for 1 package(s))"
+        );
+        assert_eq!(
+            DownloadSize { bytes: 0, unknown: 2 }.to_string(),
+            "size unknown for 2 package(s)"
+        );
+    }
+}
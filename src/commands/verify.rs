@@ -0,0 +1,76 @@
+use std::process;
+
+use crate::apk::Apk;
+use crate::constants::VIRTUAL_PKGS;
+
+pub fn handle_verify(apk: &Apk, args: &[String]) {
+    let mut fix = false;
+    let mut packages = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "--fix" => fix = true,
+            a if a.starts_with('-') => {
+                eprintln!("Unknown verify option: {a}");
+                eprintln!("Usage: vellum verify [--fix] [package...]");
+                process::exit(1);
+            }
+            _ => packages.push(arg.clone()),
+        }
+    }
+
+    if packages.is_empty() {
+        packages = match apk.list_installed() {
+            Ok(list) => list,
+            Err(e) => {
+                eprintln!("Could not list installed packages: {e}");
+                process::exit(1);
+            }
+        };
+    }
+    packages.retain(|p| !VIRTUAL_PKGS.contains(&p.as_str()));
+
+    let mut failures = Vec::new();
+    for pkg in &packages {
+        match apk.run_capture(&["verify", pkg]) {
+            Ok(out) if out.code == 0 => {}
+            Ok(out) => {
+                let reason = out.stderr.lines().chain(out.stdout.lines()).next().unwrap_or("");
+                if reason.is_empty() {
+                    println!("[FAIL] {pkg}");
+                } else {
+                    println!("[FAIL] {pkg}: {reason}");
+                }
+                failures.push(pkg.as_str());
+            }
+            Err(e) => {
+                println!("[FAIL] {pkg}: {e}");
+                failures.push(pkg.as_str());
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        println!("All {} package(s) verified.", packages.len());
+        return;
+    }
+
+    println!();
+    if !fix {
+        println!("{} package(s) failed verification. Reinstall them with:", failures.len());
+        for pkg in &failures {
+            println!("  vellum fix --reinstall {pkg}");
+        }
+        println!("or run 'vellum verify --fix'.");
+        process::exit(1);
+    }
+
+    println!("Reinstalling {} package(s)...", failures.len());
+    let mut cmd_args = vec!["fix", "--reinstall"];
+    cmd_args.extend(failures.iter().copied());
+    if let Err(e) = apk.run(&cmd_args) {
+        eprintln!("Failed to reinstall packages: {e}");
+        process::exit(1);
+    }
+    println!("Reinstalled: {}", failures.join(", "));
+}
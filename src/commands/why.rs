@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::process;
+
+use crate::apk::Apk;
+use crate::state::State;
+
+const USAGE: &str = "Usage: vellum why [--depth <n>] <package>...";
+
+/// A package and the installed packages that require it.
+#[derive(Debug, PartialEq)]
+struct Node {
+    name: String,
+    manual: bool,
+    circular: bool,
+    /// Set when `--depth` stopped the search before this package's dependents.
+    truncated: bool,
+    required_by: Vec<Node>,
+}
+
+pub fn handle_why(apk: &Apk, state: &State, args: &[String]) {
+    let mut depth = usize::MAX;
+    let mut packages = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--depth" => match iter.next().and_then(|d| d.parse().ok()) {
+                Some(d) => depth = d,
+                None => {
+                    eprintln!("Error: --depth requires a number");
+                    process::exit(1);
+                }
+            },
+            a if a.starts_with('-') => {
+                eprintln!("Unknown why option: {a}");
+                eprintln!("{USAGE}");
+                process::exit(1);
+            }
+            _ => packages.push(arg.as_str()),
+        }
+    }
+    if packages.is_empty() {
+        eprintln!("{USAGE}");
+        process::exit(1);
+    }
+
+    let manual = state.list_manual().unwrap_or_else(|e| {
+        eprintln!("warning: could not read manually installed packages: {e}");
+        Vec::new()
+    });
+
+    let mut cache: HashMap<String, Vec<String>> = HashMap::new();
+    let mut required_by = |pkg: &str| {
+        cache
+            .entry(pkg.to_string())
+            .or_insert_with(|| match apk.get_reverse_dependencies(pkg) {
+                Ok(rdeps) => rdeps,
+                Err(e) => {
+                    eprintln!("warning: could not read dependents of {pkg}: {e}");
+                    Vec::new()
+                }
+            })
+            .clone()
+    };
+
+    let mut failed = false;
+    for (i, pkg) in packages.iter().enumerate() {
+        if !matches!(apk.get_package_version(pkg), Ok(Some(_))) {
+            eprintln!("{pkg} is not installed.");
+            failed = true;
+            continue;
+        }
+        if i > 0 {
+            println!();
+        }
+
+        let tree = build_tree(pkg, &manual, depth, &mut Vec::new(), &mut required_by);
+        if !tree.manual && tree.required_by.is_empty() && !tree.truncated {
+            println!("{pkg} is not required by any installed package.");
+            continue;
+        }
+        for line in render(&tree, 0) {
+            println!("{line}");
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+// Follows dependents up from `name` until each path reaches a manually
+// installed package, loops back on itself or hits `depth`.
+fn build_tree(
+    name: &str,
+    manual: &[String],
+    depth: usize,
+    path: &mut Vec<String>,
+    required_by: &mut dyn FnMut(&str) -> Vec<String>,
+) -> Node {
+    let mut node = Node {
+        name: name.to_string(),
+        manual: manual.iter().any(|m| m == name),
+        circular: path.iter().any(|p| p == name),
+        truncated: false,
+        required_by: Vec::new(),
+    };
+    if node.manual || node.circular {
+        return node;
+    }
+
+    let parents = required_by(name);
+    if path.len() >= depth {
+        node.truncated = !parents.is_empty();
+        return node;
+    }
+
+    path.push(name.to_string());
+    node.required_by = parents
+        .iter()
+        .map(|parent| build_tree(parent, manual, depth, path, required_by))
+        .collect();
+    path.pop();
+    node
+}
+
+fn render(node: &Node, level: usize) -> Vec<String> {
+    let mut label = node.name.clone();
+    if node.manual {
+        label.push_str(" [manual]");
+    } else if node.circular {
+        label.push_str(" (circular)");
+    } else if node.truncated {
+        label.push_str(" ...");
+    }
+
+    let mut lines = vec![if level == 0 {
+        label
+    } else {
+        format!("{}required by: {label}", "  ".repeat(level))
+    }];
+    for parent in &node.required_by {
+        lines.extend(render(parent, level + 1));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(pkg: &str, manual: &[&str], depth: usize, edges: &[(&str, &[&str])]) -> Vec<String> {
+        let manual: Vec<String> = manual.iter().map(|s| s.to_string()).collect();
+        let mut required_by = |pkg: &str| {
+            edges
+                .iter()
+                .find(|(name, _)| *name == pkg)
+                .map(|(_, parents)| parents.iter().map(|s| s.to_string()).collect())
+                .unwrap_or_default()
+        };
+        render(&build_tree(pkg, &manual, depth, &mut Vec::new(), &mut required_by), 0)
+    }
+
+    #[test]
+    fn stops_at_manual_packages() {
+        let edges: &[(&str, &[&str])] = &[("libfoo", &["foo", "bar"]), ("bar", &["app"])];
+        assert_eq!(
+            tree("libfoo", &["foo", "app"], usize::MAX, edges),
+            vec![
+                "libfoo",
+                "  required by: foo [manual]",
+                "  required by: bar",
+                "    required by: app [manual]",
+            ]
+        );
+    }
+
+    #[test]
+    fn marks_cycles_and_depth_limit() {
+        let edges: &[(&str, &[&str])] = &[("a", &["b"]), ("b", &["a"])];
+        assert_eq!(
+            tree("a", &[], usize::MAX, edges),
+            vec!["a", "  required by: b", "    required by: a (circular)"]
+        );
+        assert_eq!(tree("a", &[], 1, edges), vec!["a", "  required by: b ..."]);
+    }
+}
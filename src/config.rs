@@ -0,0 +1,246 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+pub const KEYS: &[&str] =
+    &["auto_reenable", "confirm_upgrades", "hook_timeout_secs", "preferred_arch", "repo_url"];
+const ARCHES: &[&str] = &["aarch64", "armv7"];
+
+/// Settings from `etc/vellum.toml` under the vellum root. Keys missing from
+/// the file keep their defaults, and environment variables such as
+/// `VELLUM_HOOK_TIMEOUT` or `VELLUM_ARCH` still take precedence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Run post-upgrade hooks after upgrading a package that has one, as
+    /// with `vellum upgrade --auto-reenable`.
+    pub auto_reenable: bool,
+    /// Ask before upgrading; `false` behaves like `vellum upgrade --yes`.
+    pub confirm_upgrades: bool,
+    pub hook_timeout_secs: u64,
+    /// Used instead of the detected architecture.
+    pub preferred_arch: Option<String>,
+    /// Repository to use when the repositories file lists none.
+    pub repo_url: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            auto_reenable: false,
+            confirm_upgrades: true,
+            hook_timeout_secs: 60,
+            preferred_arch: None,
+            repo_url: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn path(vellum_root: &str) -> PathBuf {
+        Path::new(vellum_root).join("etc").join("vellum.toml")
+    }
+
+    /// Reads the config file, or returns the defaults when there isn't one.
+    pub fn load(vellum_root: &str) -> Result<Config> {
+        match fs::read_to_string(Self::path(vellum_root)) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Only the subset of TOML the config needs: `key = value` lines with
+    // booleans, integers or double-quoted strings, and `#` comments.
+    fn parse(content: &str) -> Result<Config> {
+        let mut config = Config::default();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .and_then(|(key, value)| Some((key.trim(), parse_value(value.trim())?)))
+                .ok_or_else(|| anyhow!("line {}: expected key = value", i + 1))?;
+            config.set(key, &value).map_err(|e| anyhow!("line {}: {e}", i + 1))?;
+        }
+        Ok(config)
+    }
+
+    /// The value of `key`, or `None` when it has no value set.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(match key {
+            "auto_reenable" => Some(self.auto_reenable.to_string()),
+            "confirm_upgrades" => Some(self.confirm_upgrades.to_string()),
+            "hook_timeout_secs" => Some(self.hook_timeout_secs.to_string()),
+            "preferred_arch" => self.preferred_arch.clone(),
+            "repo_url" => self.repo_url.clone(),
+            _ => return Err(unknown_key(key)),
+        })
+    }
+
+    /// What `key` is when the config file doesn't set it.
+    pub fn default_value(key: &str) -> Result<String> {
+        Ok(match key {
+            "preferred_arch" => "detected".to_string(),
+            "repo_url" => "none".to_string(),
+            _ => Config::default().get(key)?.unwrap_or_default(),
+        })
+    }
+
+    /// Sets `key` from its text form, checking that the value fits the key.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "auto_reenable" => self.auto_reenable = parse_bool(key, value)?,
+            "confirm_upgrades" => self.confirm_upgrades = parse_bool(key, value)?,
+            "hook_timeout_secs" => {
+                self.hook_timeout_secs = value
+                    .parse()
+                    .ok()
+                    .filter(|secs| *secs > 0)
+                    .ok_or_else(|| anyhow!("{key} must be a number of seconds above 0"))?;
+            }
+            "preferred_arch" => {
+                if !ARCHES.contains(&value) {
+                    return Err(anyhow!("{key} must be one of: {}", ARCHES.join(", ")));
+                }
+                self.preferred_arch = Some(value.to_string());
+            }
+            "repo_url" => {
+                if !value.starts_with("https://") {
+                    return Err(anyhow!("{key} must start with https://"));
+                }
+                self.repo_url = Some(value.trim_end_matches('/').to_string());
+            }
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+
+    /// Writes `key`'s current value to the config file, replacing any line
+    /// already setting it and leaving the rest of the file as it was.
+    pub fn save_key(&self, vellum_root: &str, key: &str) -> Result<()> {
+        let path = Self::path(vellum_root);
+        let content = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let line = match self.get(key)? {
+            Some(value) if matches!(key, "preferred_arch" | "repo_url") => {
+                format!("{key} = \"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+            Some(value) => format!("{key} = {value}"),
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, replace_key_line(&content, key, &line))?;
+        Ok(())
+    }
+}
+
+fn unknown_key(key: &str) -> anyhow::Error {
+    anyhow!("unknown config key '{key}' (expected one of: {})", KEYS.join(", "))
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(anyhow!("{key} must be true or false")),
+    }
+}
+
+// A double-quoted string (with `\"` and `\\` escapes) or a bare value, either
+// optionally followed by a comment.
+fn parse_value(raw: &str) -> Option<String> {
+    let Some(rest) = raw.strip_prefix('"') else {
+        let value = raw.split('#').next().unwrap_or(raw).trim();
+        return (!value.is_empty()).then(|| value.to_string());
+    };
+
+    let mut value = String::new();
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?),
+            '"' => {
+                let tail = chars.as_str().trim();
+                return (tail.is_empty() || tail.starts_with('#')).then_some(value);
+            }
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+fn replace_key_line(content: &str, key: &str, line: &str) -> String {
+    let mut replaced = false;
+    let mut lines: Vec<&str> = content
+        .lines()
+        .map(|l| match l.split_once('=') {
+            Some((k, _)) if k.trim() == key && !l.trim_start().starts_with('#') => {
+                replaced = true;
+                line
+            }
+            _ => l,
+        })
+        .collect();
+    if !replaced {
+        lines.push(line);
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_every_key() {
+        let content = "# vellum settings\n\
+                       auto_reenable = true\n\
+                       confirm_upgrades = false # always --yes\n\
+                       hook_timeout_secs = 120\n\
+                       preferred_arch = \"armv7\"\n\
+                       repo_url = \"https://example.com/repo/\"\n";
+        let config = Config::parse(content).unwrap();
+
+        assert!(config.auto_reenable);
+        assert!(!config.confirm_upgrades);
+        assert_eq!(config.hook_timeout_secs, 120);
+        assert_eq!(config.preferred_arch.as_deref(), Some("armv7"));
+        assert_eq!(config.repo_url.as_deref(), Some("https://example.com/repo"));
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn parse_rejects_bad_values() {
+        let err = Config::parse("auto_reenable = yes\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: auto_reenable must be true or false");
+        assert!(Config::parse("\nhook_timeout_secs = 0\n").is_err());
+        assert!(Config::parse("preferred_arch = \"x86_64\"\n").is_err());
+        assert!(Config::parse("repo_url = \"http://example.com\"\n").is_err());
+        assert!(Config::parse("colour = true\n").is_err());
+        assert!(Config::parse("repo_url = \"https://example.com\n").is_err());
+        assert!(Config::parse("auto_reenable\n").is_err());
+    }
+
+    #[test]
+    fn replace_key_line_keeps_other_lines() {
+        let content = "# settings\nconfirm_upgrades = true\nhook_timeout_secs = 60\n";
+        assert_eq!(
+            replace_key_line(content, "confirm_upgrades", "confirm_upgrades = false"),
+            "# settings\nconfirm_upgrades = false\nhook_timeout_secs = 60\n"
+        );
+        assert_eq!(
+            replace_key_line("", "auto_reenable", "auto_reenable = true"),
+            "auto_reenable = true\n"
+        );
+    }
+}
@@ -1,2 +1,4 @@
 pub const VELLUM_ROOT: &str = "/home/root/.vellum";
+pub const VELLUM_VERSION: &str = env!("VELLUM_VERSION");
+pub const DEVICE_TYPES: &[&str] = &["rm1", "rm2", "rmpp", "rmppm"];
 pub const VIRTUAL_PKGS: &[&str] = &["remarkable-os", "rm1", "rm2", "rmpp", "rmppm"];
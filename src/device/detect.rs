@@ -1,9 +1,14 @@
+use std::env;
 use std::fs;
 use std::process::Command;
 
 use anyhow::{anyhow, Result};
 
 pub fn get_os_version() -> Result<String> {
+    if let Some(ver) = env_override("VELLUM_OS_VERSION") {
+        return Ok(ver);
+    }
+
     if let Ok(data) = fs::read_to_string("/usr/share/remarkable/update.conf") {
         for line in data.lines() {
             if let Some(ver) = line.strip_prefix("RELEASE_VERSION=") {
@@ -30,6 +35,10 @@ pub fn get_os_version() -> Result<String> {
 }
 
 pub fn get_apk_arch() -> String {
+    if let Some(arch) = env_override("VELLUM_ARCH") {
+        return arch;
+    }
+
     if cfg!(target_arch = "aarch64") {
         return "aarch64".to_string();
     }
@@ -45,6 +54,18 @@ pub fn get_apk_arch() -> String {
     "noarch".to_string()
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    /// Short code, also the name of the device's virtual package (`rmpp`).
+    pub id: String,
+    /// Human-readable name (`reMarkable Paper Pro`).
+    pub name: String,
+    /// Raw contents of `/sys/devices/soc0/machine`, trimmed.
+    pub machine_string: String,
+    /// APK architecture of this device.
+    pub arch: String,
+}
+
 const DEVICE_PATTERNS: &[(&str, &str)] = &[
     ("Ferrari", "rmpp"),
     ("Chiappa", "rmppm"),
@@ -52,11 +73,86 @@ const DEVICE_PATTERNS: &[(&str, &str)] = &[
     ("reMarkable 2.0", "rm2"),
 ];
 
-pub fn get_device_type() -> Option<String> {
-    let data = fs::read_to_string("/sys/devices/soc0/machine").ok()?;
-    let machine = data.trim();
+const DEVICE_NAMES: &[(&str, &str)] = &[
+    ("rm1", "reMarkable 1"),
+    ("rm2", "reMarkable 2"),
+    ("rmpp", "reMarkable Paper Pro"),
+    ("rmppm", "reMarkable Paper Pro Move"),
+];
+
+const DEVICE_OVERRIDES: &[&str] = &["rm1", "rm2", "rmpp", "rmppm", "unknown"];
+
+pub fn get_device_type() -> Option<DeviceInfo> {
+    let machine = match env_override("VELLUM_MACHINE_STRING") {
+        Some(machine) => Some(machine),
+        None => fs::read_to_string("/sys/devices/soc0/machine").ok(),
+    };
+    let machine = machine.map(|m| m.trim().to_string());
+
+    if let Some(device) = env_override("VELLUM_DEVICE") {
+        if DEVICE_OVERRIDES.contains(&device.as_str()) {
+            return Some(device_info(&device, &machine.unwrap_or_default()));
+        }
+        eprintln!(
+            "warning: ignoring VELLUM_DEVICE={device} (expected one of {})",
+            DEVICE_OVERRIDES.join(", ")
+        );
+    }
+
+    let machine = machine?;
+    let id = match_device(&machine)?;
+    Some(device_info(&id, &machine))
+}
+
+fn device_info(id: &str, machine: &str) -> DeviceInfo {
+    DeviceInfo {
+        id: id.to_string(),
+        name: device_name(id).to_string(),
+        machine_string: machine.to_string(),
+        arch: get_apk_arch(),
+    }
+}
+
+fn device_name(id: &str) -> &'static str {
+    DEVICE_NAMES
+        .iter()
+        .find(|(code, _)| *code == id)
+        .map_or("reMarkable Device", |(_, name)| name)
+}
+
+fn match_device(machine: &str) -> Option<String> {
     DEVICE_PATTERNS
         .iter()
         .find(|(pattern, _)| machine.contains(pattern))
         .map(|(_, device)| device.to_string())
 }
+
+fn env_override(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_device_known_machines() {
+        assert_eq!(match_device("reMarkable Ferrari"), Some("rmpp".to_string()));
+        assert_eq!(match_device("reMarkable Chiappa"), Some("rmppm".to_string()));
+        assert_eq!(match_device("reMarkable 1.0"), Some("rm1".to_string()));
+        assert_eq!(match_device("reMarkable 2.0"), Some("rm2".to_string()));
+    }
+
+    #[test]
+    fn device_name_known_and_unknown() {
+        assert_eq!(device_name("rmpp"), "reMarkable Paper Pro");
+        assert_eq!(device_name("rm1"), "reMarkable 1");
+        assert_eq!(device_name("unknown"), "reMarkable Device");
+    }
+
+    #[test]
+    fn match_device_unknown_machine() {
+        assert_eq!(match_device("Raspberry Pi 4"), None);
+        assert_eq!(match_device(""), None);
+    }
+}
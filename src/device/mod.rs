@@ -1,3 +1,3 @@
 mod detect;
 
-pub use detect::{get_apk_arch, get_device_type, get_os_version};
+pub use detect::{get_apk_arch, get_device_type, get_os_version, DeviceInfo};
@@ -1,5 +1,6 @@
 mod apk;
 mod commands;
+mod config;
 mod constants;
 mod device;
 mod repo;
@@ -10,20 +11,26 @@ use std::env;
 use std::fs;
 use std::path::Path;
 use std::process;
+use std::time::Duration;
 
-use apk::{generate_device_package, generate_remarkable_os_package, version_lt, Apk};
+use apk::{
+    generate_device_package, generate_remarkable_os_package, version_eq, version_lt, Apk,
+};
 use commands::{
-    handle_add, handle_check_os, handle_del, handle_purge, handle_reenable,
-    handle_self_uninstall, handle_testing, handle_upgrade,
+    handle_add, handle_audit, handle_check_os, handle_completions, handle_config, handle_del,
+    handle_diff, handle_doctor, handle_env, handle_fetch, handle_freeze, handle_graph, handle_hold,
+    handle_info, handle_key_generate, handle_list, handle_log, handle_outdated, handle_pin,
+    handle_purge, handle_reenable, handle_repo, handle_restore, handle_rollback, handle_search,
+    handle_self_uninstall, handle_stats, handle_testing, handle_unhold, handle_unpin,
+    handle_upgrade, handle_verify, handle_why,
 };
-use constants::VELLUM_ROOT;
+use config::Config;
+use constants::{DEVICE_TYPES, VELLUM_ROOT, VELLUM_VERSION};
 use device::{get_apk_arch, get_device_type, get_os_version};
-use repo::update_index;
+use repo::{gc_repo, prune_old_packages, update_index};
 use state::State;
 use util::remove_glob;
 
-const VERSION: &str = env!("VELLUM_VERSION");
-
 struct AppState {
     os_mismatch: bool,
     os_cur: String,
@@ -32,10 +39,49 @@ struct AppState {
 
 fn main() {
     let state = State::new(VELLUM_ROOT);
-    let apk = Apk::new(VELLUM_ROOT);
+    let _lock = match state.lock() {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    if let Err(errors) = state.validate() {
+        for e in &errors {
+            eprintln!("warning: {e}");
+        }
+        if let Err(e) = state.repair() {
+            eprintln!("warning: failed to repair state: {e}");
+        }
+    }
+    let config = Config::load(VELLUM_ROOT).unwrap_or_else(|e| {
+        eprintln!("warning: ignoring {}: {e}", Config::path(VELLUM_ROOT).display());
+        Config::default()
+    });
+    // get_apk_arch reads VELLUM_ARCH, which still wins over the config file.
+    if let Some(arch) = &config.preferred_arch {
+        if env::var_os("VELLUM_ARCH").is_none() {
+            env::set_var("VELLUM_ARCH", arch);
+        }
+    }
+    let apk = new_apk();
 
-    let app_state = ensure_remarkable_os(&state, &apk);
-    ensure_device_package(&state, &apk);
+    let (app_state, new_os_version) = ensure_remarkable_os(&state, &apk);
+    let new_device = ensure_device_package(&state, &apk);
+    if new_os_version.is_some() || new_device.is_some() {
+        let saved = state.transaction(|txn| {
+            if let Some(version) = &new_os_version {
+                txn.set_os_version(version);
+            }
+            if let Some(device) = &new_device {
+                txn.set_device(device);
+            }
+            Ok(())
+        });
+        if let Err(e) = saved {
+            eprintln!("warning: failed to save OS version and device type: {e}");
+        }
+    }
 
     let args: Vec<String> = env::args().collect();
 
@@ -59,30 +105,41 @@ fn main() {
 
     match cmd.as_str() {
         "--help" | "-h" => show_help(&apk),
-        "--version" | "-V" | "version" => println!("vellum {VERSION}"),
-        "install" => handle_add(&apk, &args[2..]),
-        "remove" => handle_del(&apk, &args[2..]),
-        "purge" => handle_purge(&apk, &args[2..]),
+        "--version" | "-V" | "version" => println!("vellum {VELLUM_VERSION}"),
+        "install" => handle_add(&state, &apk, &config, &args[2..]),
+        "remove" => handle_del(&state, &apk, &args[2..]),
+        "purge" => handle_purge(&state, &apk, &args[2..]),
         "show" => handle_show(&apk, &args[2..]),
-        "add" => handle_add(&apk, &args[2..]),
-        "del" => handle_del(&apk, &args[2..]),
+        "add" => handle_add(&state, &apk, &config, &args[2..]),
+        "del" => handle_del(&state, &apk, &args[2..]),
+        "list" => handle_list(&state, &apk, &args[2..]),
+        "hold" => handle_hold(&apk, &state, &args[2..]),
+        "unhold" => handle_unhold(&apk, &state, &args[2..]),
+        "pin" => handle_pin(&apk, &state, &args[2..]),
+        "unpin" => handle_unpin(&apk, &state, &args[2..]),
+        "log" => handle_log(&state, &args[2..]),
+        "diff" => handle_diff(&apk, &state, &args[2..]),
+        "why" => handle_why(&apk, &state, &args[2..]),
+        "graph" => handle_graph(&apk, &args[2..]),
+        "search" => handle_search(&apk, &config, &args[2..]),
+        "info" => handle_info(&apk, &config, &args[2..]),
+        "outdated" => handle_outdated(&apk, &config, &args[2..]),
+        "fetch" => handle_fetch(&apk, &config, &args[2..]),
+        "freeze" => handle_freeze(&apk, &args[2..]),
+        "restore" => handle_restore(&state, &apk, &config, &args[2..]),
         "upgrade" => handle_upgrade(
             &state,
             &apk,
+            &config,
             &args[2..],
             app_state.os_mismatch,
             &app_state.os_prev,
             &app_state.os_cur,
         ),
-        "reenable" => handle_reenable(),
-        "check-os" => {
-            if args.len() < 3 {
-                eprintln!("Usage: vellum check-os <version>");
-                eprintln!("Check if installed packages are compatible with a given OS version.");
-                process::exit(1);
-            }
-            handle_check_os(&apk, &args[2]);
-        }
+        "rollback" => handle_rollback(&apk, &state, &args[2..]),
+        "reenable" => handle_reenable(&state, &config, &args[2..]),
+        "config" => handle_config(VELLUM_ROOT, &args[2..]),
+        "check-os" => handle_check_os(&apk, &config, &args[2..]),
         "self" => {
             if args.len() > 2 && args[2] == "uninstall" {
                 handle_self_uninstall(&apk, VELLUM_ROOT, &args[3..]);
@@ -92,7 +149,29 @@ fn main() {
                 process::exit(1);
             }
         }
-        "testing" => handle_testing(VELLUM_ROOT, &args[2..]),
+        "key" => {
+            if args.len() > 2 && args[2] == "generate" {
+                handle_key_generate(VELLUM_ROOT, &args[3..]);
+            } else {
+                eprintln!("Unknown key command");
+                eprintln!("Usage: vellum key generate [--bits 2048|4096] [--force]");
+                process::exit(1);
+            }
+        }
+        "repo" => handle_repo(VELLUM_ROOT, &args[2..]),
+        "stats" => handle_stats(&apk, VELLUM_ROOT, &args[2..]),
+        "completions" => match args.get(2) {
+            Some(shell) => handle_completions(shell),
+            None => {
+                eprintln!("Usage: vellum completions <bash|zsh|fish>");
+                process::exit(1);
+            }
+        },
+        "testing" => handle_testing(&apk, &config, VELLUM_ROOT, &args[2..]),
+        "doctor" => handle_doctor(&apk, VELLUM_ROOT),
+        "env" => handle_env(&state, VELLUM_ROOT),
+        "verify" => handle_verify(&apk, &args[2..]),
+        "audit" => handle_audit(&apk, &args[2..]),
         _ => {
             let pass_args: Vec<&str> = args[1..].iter().map(|s| s.as_str()).collect();
             if let Err(e) = apk.exec(&pass_args) {
@@ -103,19 +182,30 @@ fn main() {
     }
 }
 
+fn new_apk() -> Apk {
+    let apk = Apk::new(VELLUM_ROOT);
+    match env::var("VELLUM_APK_TIMEOUT").ok().and_then(|v| v.parse::<u64>().ok()) {
+        Some(secs) if secs > 0 => apk.with_timeout(Duration::from_secs(secs)),
+        _ => apk,
+    }
+}
+
 fn is_allowed_during_mismatch(cmd: &str) -> bool {
-    !matches!(cmd, "add" | "install")
+    !matches!(cmd, "add" | "install" | "restore" | "rollback")
 }
 
-fn ensure_remarkable_os(state: &State, apk: &Apk) -> AppState {
+/// Also returns the OS version to record once the remarkable-os package has
+/// been registered; `main` saves it together with the device type.
+fn ensure_remarkable_os(state: &State, apk: &Apk) -> (AppState, Option<String>) {
     let os_cur = match get_os_version() {
         Ok(v) => v,
         Err(_) => {
-            return AppState {
+            let app_state = AppState {
                 os_mismatch: false,
                 os_cur: String::new(),
                 os_prev: String::new(),
-            }
+            };
+            return (app_state, None);
         }
     };
 
@@ -128,12 +218,20 @@ fn ensure_remarkable_os(state: &State, apk: &Apk) -> AppState {
         if let Err(e) = fs::create_dir_all(&repo_dir) {
             eprintln!("warning: failed to create repo directory: {e}");
         }
-        remove_glob(&format!("{repo_dir}/remarkable-os-*.apk"));
+        if let Err(e) = remove_glob(&format!("{repo_dir}/remarkable-os-*.apk")) {
+            eprintln!("warning: failed to remove old remarkable-os package: {e}");
+        }
         if let Err(e) = generate_remarkable_os_package(&os_cur, &repo_dir, &key_path) {
             eprintln!("warning: failed to generate remarkable-os package: {e}");
         }
-        if let Err(e) = update_index(&repo_dir, Some(&key_path)) {
-            eprintln!("warning: failed to update local repo index: {e}");
+        match prune_old_packages(&repo_dir, &["remarkable-os"]) {
+            Ok(0) => {}
+            Ok(n) => eprintln!("warning: removed {n} stale package(s) from local repo"),
+            Err(e) => eprintln!("warning: failed to prune local repo: {e}"),
+        }
+        match update_index(&repo_dir, Some(&key_path)) {
+            Ok(()) => gc_local_repo(&repo_dir),
+            Err(e) => eprintln!("warning: failed to update local repo index: {e}"),
         }
 
         let pkg_version = format!("remarkable-os={os_cur}-r0");
@@ -141,11 +239,10 @@ fn ensure_remarkable_os(state: &State, apk: &Apk) -> AppState {
             eprintln!("warning: failed to register remarkable-os package: {e}");
         }
 
+        let mut new_os_version = None;
         match apk.get_package_version("remarkable-os") {
-            Ok(Some(installed_ver)) if installed_ver == os_cur => {
-                if let Err(e) = state.set_os_version(&os_cur) {
-                    eprintln!("warning: failed to save OS version: {e}");
-                }
+            Ok(Some(installed_ver)) if version_eq(&installed_ver, &os_cur) => {
+                new_os_version = Some(os_cur.clone());
             }
             Ok(Some(installed_ver)) => {
                 eprintln!("warning: remarkable-os package is at {installed_ver}, expected {os_cur}");
@@ -158,33 +255,35 @@ fn ensure_remarkable_os(state: &State, apk: &Apk) -> AppState {
             }
         }
 
-        AppState {
+        let app_state = AppState {
             os_mismatch: false,
             os_cur,
             os_prev: String::new(),
-        }
+        };
+        (app_state, new_os_version)
     } else if os_cur != os_prev {
-        AppState {
+        let app_state = AppState {
             os_mismatch: true,
             os_cur,
             os_prev,
-        }
+        };
+        (app_state, None)
     } else {
-        AppState {
+        let app_state = AppState {
             os_mismatch: false,
             os_cur,
             os_prev,
-        }
+        };
+        (app_state, None)
     }
 }
 
-fn ensure_device_package(state: &State, apk: &Apk) {
-    let Some(device_type) = get_device_type() else {
-        return;
-    };
+/// Returns the device type to record when its package had to be rebuilt.
+fn ensure_device_package(state: &State, apk: &Apk) -> Option<String> {
+    let device = get_device_type()?;
+    let device_type = device.id.as_str();
     let prev_device = state.get_device().unwrap_or_default();
-    let arch = get_apk_arch();
-    let repo_dir = format!("{VELLUM_ROOT}/local-repo/{arch}");
+    let repo_dir = format!("{VELLUM_ROOT}/local-repo/{}", device.arch);
     let key_path = format!("{VELLUM_ROOT}/etc/apk/keys/local.rsa");
 
     let pkg_path = format!("{repo_dir}/{device_type}-1.0.0-r0.apk");
@@ -193,42 +292,108 @@ fn ensure_device_package(state: &State, apk: &Apk) {
         if let Err(e) = fs::create_dir_all(&repo_dir) {
             eprintln!("warning: failed to create repo directory: {e}");
         }
-        for d in &["rm1", "rm2", "rmpp", "rmppm"] {
-            remove_glob(&format!("{repo_dir}/{d}-*.apk"));
+        for d in DEVICE_TYPES {
+            if let Err(e) = remove_glob(&format!("{repo_dir}/{d}-*.apk")) {
+                eprintln!("warning: failed to remove old device package: {e}");
+            }
         }
-        if let Err(e) = generate_device_package(&device_type, &repo_dir, &key_path) {
+        if let Err(e) = generate_device_package(&device, &repo_dir, &key_path) {
             eprintln!("warning: failed to generate device package: {e}");
         }
-        if let Err(e) = update_index(&repo_dir, Some(&key_path)) {
-            eprintln!("warning: failed to update local repo index: {e}");
+        match update_index(&repo_dir, Some(&key_path)) {
+            Ok(()) => gc_local_repo(&repo_dir),
+            Err(e) => eprintln!("warning: failed to update local repo index: {e}"),
         }
-        if let Err(e) = state.set_device(&device_type) {
-            eprintln!("warning: failed to save device type: {e}");
-        }
-        if let Err(e) = apk.run_silent(&["add", &device_type]) {
+        if let Err(e) = apk.run_silent(&["add", device_type]) {
             eprintln!("warning: failed to register device package: {e}");
         }
+        return Some(device_type.to_string());
+    }
+    None
+}
+
+// Only safe right after a successful `update_index`: anything the fresh index
+// doesn't list is left over from an earlier failed rebuild.
+fn gc_local_repo(repo_dir: &str) {
+    match gc_repo(repo_dir) {
+        Ok(0) => {}
+        Ok(n) => eprintln!("warning: removed {n} unindexed package(s) from local repo"),
+        Err(e) => eprintln!("warning: failed to clean local repo: {e}"),
     }
 }
 
 fn show_help(apk: &Apk) {
     println!(
-        r#"vellum {VERSION} - package manager for reMarkable
+        r#"vellum {VELLUM_VERSION} - package manager for reMarkable
 
 Usage: vellum <command> [options]
 
 Vellum commands:
-  upgrade             Upgrade packages (handles OS version changes)
-  check-os <version>  Check package compatibility with an OS version
-  reenable            Restore system files after OS upgrade
-  testing             Manage testing repository (enable, disable, status)
+  add <pkg>           Install the newest version compatible with the current OS
+                      (--no-cache refetches the package index first; slower on slow connections)
+                      (--verbose prints each package's description before installing)
+                      (--yes skips the confirmation)
+  upgrade             Upgrade packages (handles OS version changes, --skip <pkg>, --ignore-hold,
+                      --ignore-disk-check, --no-cache, --force-remote, --no-reenable,
+                      --auto-reenable, --unpin-all)
+  rollback            Revert the packages changed by the last upgrade (--yes)
+  fetch <pkg...>      Download packages into the cache to install later (--os <version>)
+  outdated            List installed packages with newer versions available (--all, --json)
+  list                List packages (--installed, --available, --upgradeable, --sort-by-age)
+  hold <pkg>          Keep a package at its installed version during upgrades
+  unhold <pkg>        Release a held package
+  pin <pkg>[=<ver>]   Pin a package to a version; upgrades skip it
+  unpin <pkg>         Remove a package's version pin
+  log                 Show install history (--since <date>, --pkg <name>)
+  diff                Show package changes since the last upgrade snapshot (--since <date>)
+  why <pkg...>        Show which installed packages require a package (--depth <n>)
+  graph [pkg...]      Print the dependency graph in Graphviz DOT format (--depth <n>,
+                      --installed-only, --output <file.dot>)
+  search <query>      Search the package index (--description, --exact, --json)
+  info <pkg>          Show index details and versions of a package (--json)
+  check-os <version>  Check package compatibility with an OS version (--json, --no-cache,
+                      --force-remote)
+  reenable            Restore system files after OS upgrade (--quiet, --dry-run,
+                      --timeout <secs>)
+  repo add <url>      Add a package repository (--tag <@tag> to install from it by tag)
+  repo add-tagged <@tag> <url>
+                      Add a package repository whose packages are installed as <pkg>@tag
+  repo remove <url>   Remove a package repository
+  repo list           List configured repositories
+  repo add-local <file.apk>
+                      Add a package built elsewhere to the local repository
+  key generate        Create the local repository signing key (--bits 2048|4096, --force)
+  testing             Manage testing repository (enable, disable, status, list)
+  doctor              Check the vellum installation for problems
+  env                 Show vellum's paths, version and detected device
+  stats               Show package counts, disk usage and the last upgrade (--json)
+  verify [pkg...]     Check installed packages for damaged files (--fix to reinstall)
+  audit               Check installed packages against known issues (--local <file>)
+  freeze [file]       Save installed packages to a list (default: packages.txt)
+  restore [file]      Install packages from a saved list (--dry-run, --yes)
   self uninstall      Remove vellum itself (--all to include packages)
+  completions <shell> Print a completion script for bash, zsh or fish
+  config <get <key>|set <key> <value>|list>
+                      Show or change settings in /home/root/.vellum/etc/vellum.toml
 
 Aliases:
   install <pkg>       Alias for 'add'
   remove <pkg>        Alias for 'del'
   purge <pkg>         Alias for 'del --purge'
   show <pkg>          Alias for 'info -a'
+
+Environment variables:
+  VELLUM_OS_VERSION   Override the detected reMarkable OS version
+  VELLUM_DEVICE       Override the detected device type (rm1, rm2, rmpp, rmppm, unknown)
+  VELLUM_MACHINE_STRING
+                      Use this instead of /sys/devices/soc0/machine for device detection
+  VELLUM_ARCH         Override the detected package architecture
+  VELLUM_APK_TIMEOUT  Kill apk subprocesses that run longer than this many seconds
+  VELLUM_AUTO_REENABLE
+                      1 makes 'upgrade' run post-upgrade hooks, like --auto-reenable;
+                      any other value overrides the auto_reenable setting to off
+  https_proxy, http_proxy, no_proxy
+                      Proxy used when fetching package indexes
 "#
     );
     let _ = apk.run(&["--help"]);
@@ -1,6 +1,7 @@
+use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use anyhow::{anyhow, Result};
@@ -13,6 +14,10 @@ use rsa::RsaPrivateKey;
 use sha1::{Digest, Sha1};
 use tar::{Builder, Header};
 
+use crate::apk::{
+    compare_package_versions, parse_index_tar_gz, read_package_identity, resign_package,
+};
+
 pub fn update_index(repo_dir: &str, key_path: Option<&str>) -> Result<()> {
     let apks: Vec<_> = fs::read_dir(repo_dir)?
         .filter_map(|e| e.ok())
@@ -69,6 +74,95 @@ pub fn update_index(repo_dir: &str, key_path: Option<&str>) -> Result<()> {
     Ok(())
 }
 
+/// Copies a package built outside vellum into the local repo and rebuilds the
+/// index, returning the package's name and version. With a `key_path`, the
+/// package is re-signed with that key and the index signed with it too, so
+/// apk will trust it.
+///
+/// The copy is named `<pkgname>-<pkgver>.apk` after its `.PKGINFO`, which is
+/// what `gc_repo` expects of indexed packages.
+pub fn add_external_apk(
+    apk_path: &str,
+    repo_dir: &str,
+    key_path: Option<&str>,
+) -> Result<(String, String)> {
+    if Path::new(apk_path).extension().is_none_or(|ext| ext != "apk") {
+        return Err(anyhow!("{apk_path} does not have a .apk extension"));
+    }
+    let data = fs::read(apk_path)?;
+    if !data.starts_with(&[0x1f, 0x8b]) {
+        return Err(anyhow!("{apk_path} is not a gzip archive"));
+    }
+    let (name, version) = read_package_identity(&data)?;
+
+    let data = match key_path {
+        Some(key_path) => resign_package(&data, key_path)?,
+        None => data,
+    };
+    fs::create_dir_all(repo_dir)?;
+    fs::write(Path::new(repo_dir).join(format!("{name}-{version}.apk")), data)?;
+
+    update_index(repo_dir, key_path)?;
+    Ok((name, version))
+}
+
+/// Removes all but the highest-versioned `<name>-<version>-rN.apk` for each of
+/// `keep_names`, returning how many files were deleted.
+pub fn prune_old_packages(repo_dir: &str, keep_names: &[&str]) -> Result<usize> {
+    let mut by_name: HashMap<&str, Vec<(String, PathBuf)>> = HashMap::new();
+
+    for entry in fs::read_dir(repo_dir)?.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let Some(stem) = file_name.strip_suffix(".apk") else {
+            continue;
+        };
+        for name in keep_names {
+            let Some(version) = stem.strip_prefix(&format!("{name}-")) else {
+                continue;
+            };
+            // `foo-bar-1.0-r0.apk` also starts with `foo-`; only a version
+            // may follow the name.
+            if version.starts_with(|c: char| c.is_ascii_digit()) {
+                by_name.entry(name).or_default().push((version.to_string(), path.clone()));
+            }
+        }
+    }
+
+    let mut removed = 0;
+    for (_, mut files) in by_name {
+        files.sort_by(|a, b| compare_package_versions(&b.0, &a.0));
+        for (_, path) in files.iter().skip(1) {
+            fs::remove_file(path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Deletes `.apk` files in `repo_dir` that the current `APKINDEX.tar.gz`
+/// doesn't list, returning how many were removed.
+pub fn gc_repo(repo_dir: &str) -> Result<usize> {
+    let index_path = Path::new(repo_dir).join("APKINDEX.tar.gz");
+    let index_path = index_path
+        .to_str()
+        .ok_or_else(|| anyhow!("invalid repo path {repo_dir}"))?;
+    let indexed: Vec<String> = parse_index_tar_gz(index_path)?
+        .iter()
+        .map(|p| format!("{}-{}.apk", p.name, p.version))
+        .collect();
+
+    let mut removed = 0;
+    for entry in fs::read_dir(repo_dir)?.filter_map(|e| e.ok()) {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name.ends_with(".apk") && !indexed.contains(&file_name) {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
 fn write_signed_index(output_path: &Path, unsigned_data: &[u8], key_pem: &str) -> Result<()> {
     let key = RsaPrivateKey::from_pkcs1_pem(key_pem)
         .or_else(|_| RsaPrivateKey::from_pkcs8_pem(key_pem))
@@ -119,3 +213,124 @@ fn write_signed_index(output_path: &Path, unsigned_data: &[u8], key_pem: &str) -
     fs::write(output_path, &final_buf)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn temp_repo(files: &[&str]) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for f in files {
+            fs::write(dir.path().join(f), "").unwrap();
+        }
+        dir
+    }
+
+    fn remaining(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn write_index(dir: &Path, apkindex: &str) {
+        let mut tar_buf = Vec::new();
+        {
+            let mut tar = Builder::new(&mut tar_buf);
+            let mut header = Header::new_gnu();
+            header.set_path("APKINDEX").unwrap();
+            header.set_mode(0o644);
+            header.set_size(apkindex.len() as u64);
+            header.set_cksum();
+            tar.append(&header, apkindex.as_bytes()).unwrap();
+            tar.finish().unwrap();
+        }
+        let file = fs::File::create(dir.join("APKINDEX.tar.gz")).unwrap();
+        let mut gz = GzEncoder::new(file, Compression::default());
+        gz.write_all(&tar_buf).unwrap();
+        gz.finish().unwrap();
+    }
+
+    #[test]
+    fn add_external_apk_rejects_bad_files() {
+        let tmp = temp_repo(&["notes.txt", "broken.apk"]);
+        let dir = tmp.path();
+        let repo = dir.join("repo");
+        let repo = repo.to_str().unwrap();
+
+        let err = add_external_apk(dir.join("notes.txt").to_str().unwrap(), repo, None);
+        assert!(err.unwrap_err().to_string().contains(".apk extension"));
+        let err = add_external_apk(dir.join("broken.apk").to_str().unwrap(), repo, None);
+        assert!(err.unwrap_err().to_string().contains("not a gzip archive"));
+        assert!(!dir.join("repo").exists());
+    }
+
+    #[test]
+    fn gc_removes_unindexed_packages() {
+        let tmp = temp_repo(&[
+            "remarkable-os-3.10.0.0-r0.apk",
+            "remarkable-os-3.9.0.0-r0.apk",
+            "rm2-1.0.0-r0.apk",
+        ]);
+        let dir = tmp.path();
+        write_index(dir, "P:remarkable-os\nV:3.10.0.0-r0\n\nP:rm2\nV:1.0.0-r0\n");
+
+        let removed = gc_repo(dir.to_str().unwrap()).unwrap();
+
+        assert_eq!(removed, 1);
+        assert_eq!(
+            remaining(dir),
+            vec!["APKINDEX.tar.gz", "remarkable-os-3.10.0.0-r0.apk", "rm2-1.0.0-r0.apk"]
+        );
+    }
+
+    #[test]
+    fn gc_without_index_fails_and_keeps_files() {
+        let tmp = temp_repo(&["foo-1.0-r0.apk"]);
+        let dir = tmp.path();
+
+        assert!(gc_repo(dir.to_str().unwrap()).is_err());
+        assert_eq!(remaining(dir), vec!["foo-1.0-r0.apk"]);
+    }
+
+    #[test]
+    fn prune_keeps_highest_version() {
+        let tmp = temp_repo(&[
+            "remarkable-os-3.10.0.0-r0.apk",
+            "remarkable-os-3.9.2.0-r0.apk",
+            "remarkable-os-3.11.1.0-r0.apk",
+            "rm2-1.0.0-r0.apk",
+        ]);
+        let dir = tmp.path();
+
+        let removed = prune_old_packages(dir.to_str().unwrap(), &["remarkable-os"]).unwrap();
+
+        assert_eq!(removed, 2);
+        assert_eq!(remaining(dir), vec!["remarkable-os-3.11.1.0-r0.apk", "rm2-1.0.0-r0.apk"]);
+    }
+
+    #[test]
+    fn prune_compares_release_numbers() {
+        let tmp = temp_repo(&["foo-1.0-r0.apk", "foo-1.0-r2.apk", "foo-1.0-r1.apk"]);
+        let dir = tmp.path();
+
+        let removed = prune_old_packages(dir.to_str().unwrap(), &["foo"]).unwrap();
+
+        assert_eq!(removed, 2);
+        assert_eq!(remaining(dir), vec!["foo-1.0-r2.apk"]);
+    }
+
+    #[test]
+    fn prune_ignores_names_sharing_a_prefix() {
+        let tmp = temp_repo(&["foo-1.0-r0.apk", "foo-bar-2.0-r0.apk"]);
+        let dir = tmp.path();
+
+        let removed = prune_old_packages(dir.to_str().unwrap(), &["foo"]).unwrap();
+
+        assert_eq!(removed, 0);
+        assert_eq!(remaining(dir), vec!["foo-1.0-r0.apk", "foo-bar-2.0-r0.apk"]);
+    }
+}
@@ -1,3 +1,3 @@
 mod local;
 
-pub use local::update_index;
+pub use local::{add_external_apk, gc_repo, prune_old_packages, update_index};
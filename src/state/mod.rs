@@ -1,7 +1,230 @@
-use std::fs;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use thiserror::Error;
+
+use crate::constants::{DEVICE_TYPES, VELLUM_VERSION};
+use crate::util::{
+    format_rfc3339, json_string, json_string_array, now_unix, parse_json_object, JsonField,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstallEvent {
+    pub ts: String,
+    pub op: String,
+    pub packages: Vec<String>,
+    pub os: String,
+}
+
+impl InstallEvent {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"ts\":{},\"op\":{},\"packages\":{},\"os\":{}}}",
+            json_string(&self.ts),
+            json_string(&self.op),
+            json_string_array(&self.packages),
+            json_string(&self.os)
+        )
+    }
+
+    fn from_json(line: &str) -> Option<Self> {
+        let mut fields = parse_json_object(line)?;
+        let mut take_str = |key: &str| match fields.remove(key) {
+            Some(JsonField::Str(s)) => Some(s),
+            _ => None,
+        };
+        let ts = take_str("ts")?;
+        let op = take_str("op")?;
+        let os = take_str("os").unwrap_or_default();
+        let packages = match fields.remove("packages") {
+            Some(JsonField::StrArray(p)) => p,
+            _ => Vec::new(),
+        };
+        Some(Self { ts, op, packages, os })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpgradedPackage {
+    pub name: String,
+    pub from_version: String,
+    pub to_version: String,
+    /// When `from_version` was installed, if vellum recorded it.
+    pub installed_at: Option<String>,
+}
+
+/// Summary of the last successful `vellum upgrade` or `vellum rollback`, kept
+/// in `last-upgrade.json` for monitoring and debugging.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpgradeReport {
+    /// `upgrade` or `rollback`.
+    pub op: String,
+    pub timestamp: String,
+    pub os_before: String,
+    pub os_after: String,
+    pub packages_upgraded: Vec<UpgradedPackage>,
+    pub hooks_run: Vec<String>,
+    /// Failed hooks with their exit code.
+    pub hooks_failed: Vec<(String, i32)>,
+    pub duration_secs: u64,
+}
+
+impl UpgradeReport {
+    fn to_json(&self) -> String {
+        let packages: Vec<String> = self
+            .packages_upgraded
+            .iter()
+            .map(|p| {
+                let installed_at = p
+                    .installed_at
+                    .as_deref()
+                    .map(|t| format!(",\"installed_at\":{}", json_string(t)))
+                    .unwrap_or_default();
+                format!(
+                    "{{\"name\":{},\"from_version\":{},\"to_version\":{}{installed_at}}}",
+                    json_string(&p.name),
+                    json_string(&p.from_version),
+                    json_string(&p.to_version)
+                )
+            })
+            .collect();
+        let failed: Vec<String> = self
+            .hooks_failed
+            .iter()
+            .map(|(name, code)| format!("{{\"name\":{},\"exit_code\":{code}}}", json_string(name)))
+            .collect();
+        format!(
+            "{{\"op\":{},\"timestamp\":{},\"os_before\":{},\"os_after\":{},\
+             \"packages_upgraded\":[{}],\"hooks_run\":{},\"hooks_failed\":[{}],\
+             \"duration_secs\":{}}}\n",
+            json_string(&self.op),
+            json_string(&self.timestamp),
+            json_string(&self.os_before),
+            json_string(&self.os_after),
+            packages.join(","),
+            json_string_array(&self.hooks_run),
+            failed.join(","),
+            self.duration_secs
+        )
+    }
+
+    fn from_json(data: &str) -> Option<Self> {
+        let mut fields = parse_json_object(data)?;
+        let objects = |field: Option<JsonField>| match field {
+            Some(JsonField::ObjArray(objects)) => objects,
+            _ => Vec::new(),
+        };
+        let str_of = |obj: &mut HashMap<String, JsonField>, key: &str| match obj.remove(key) {
+            Some(JsonField::Str(s)) => Some(s),
+            _ => None,
+        };
+
+        let packages_upgraded = objects(fields.remove("packages_upgraded"))
+            .into_iter()
+            .map(|mut p| {
+                Some(UpgradedPackage {
+                    name: str_of(&mut p, "name")?,
+                    from_version: str_of(&mut p, "from_version").unwrap_or_default(),
+                    to_version: str_of(&mut p, "to_version").unwrap_or_default(),
+                    installed_at: str_of(&mut p, "installed_at"),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let hooks_failed = objects(fields.remove("hooks_failed"))
+            .into_iter()
+            .map(|mut h| {
+                let code = match h.remove("exit_code") {
+                    Some(JsonField::Num(n)) => i32::try_from(n).ok()?,
+                    _ => -1,
+                };
+                Some((str_of(&mut h, "name")?, code))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let hooks_run = match fields.remove("hooks_run") {
+            Some(JsonField::StrArray(hooks)) => hooks,
+            _ => Vec::new(),
+        };
+        let duration_secs = match fields.remove("duration_secs") {
+            Some(JsonField::Num(n)) => u64::try_from(n).ok()?,
+            _ => 0,
+        };
+
+        Some(Self {
+            // Reports written before rollback existed were all upgrades.
+            op: str_of(&mut fields, "op").unwrap_or_else(|| "upgrade".to_string()),
+            timestamp: str_of(&mut fields, "timestamp")?,
+            os_before: str_of(&mut fields, "os_before").unwrap_or_default(),
+            os_after: str_of(&mut fields, "os_after").unwrap_or_default(),
+            packages_upgraded,
+            hooks_run,
+            hooks_failed,
+            duration_secs,
+        })
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum StateError {
+    #[error("state file osver is malformed: {0:?}")]
+    InvalidOsVersion(String),
+    #[error("state file device is not a known device: {0:?}")]
+    InvalidDevice(String),
+    #[error("state file {0} is unreadable: {1}")]
+    Unreadable(&'static str, String),
+}
+
+impl StateError {
+    fn file_name(&self) -> &'static str {
+        match self {
+            StateError::InvalidOsVersion(_) => "osver",
+            StateError::InvalidDevice(_) => "device",
+            StateError::Unreadable(name, _) => name,
+        }
+    }
+}
+
+const MAX_SNAPSHOTS: usize = 20;
+
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// State writes collected by `State::transaction`, applied together once the
+/// closure returns successfully.
+#[derive(Debug, Default)]
+pub struct StateTransaction {
+    writes: Vec<(&'static str, String)>,
+}
+
+impl StateTransaction {
+    pub fn set_os_version(&mut self, version: &str) {
+        self.set("osver", version);
+    }
+
+    pub fn set_device(&mut self, device: &str) {
+        self.set("device", device);
+    }
+
+    fn set(&mut self, name: &'static str, data: &str) {
+        self.writes.retain(|(n, _)| *n != name);
+        self.writes.push((name, data.to_string()));
+    }
+}
+
+/// Holds the vellum state lock until dropped.
+pub struct StateLock {
+    file: File,
+}
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
 
 pub struct State {
     root: PathBuf,
@@ -18,15 +241,167 @@ impl State {
         self.root.join("state")
     }
 
+    /// Takes an exclusive lock on `state/lock` so two vellum processes can't
+    /// run apk against the same root at once.
+    pub fn lock(&self) -> Result<StateLock> {
+        self.lock_with_timeout(LOCK_TIMEOUT)
+    }
+
+    fn lock_with_timeout(&self, timeout: Duration) -> Result<StateLock> {
+        fs::create_dir_all(self.dir())?;
+        let file = File::create(self.dir().join("lock"))?;
+
+        let start = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => {
+                    let lock = StateLock { file };
+                    self.recover_transaction()?;
+                    return Ok(lock);
+                }
+                Err(TryLockError::WouldBlock) if start.elapsed() < timeout => {
+                    thread::sleep(LOCK_POLL_INTERVAL);
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(anyhow!("Another vellum operation is in progress"));
+                }
+                Err(TryLockError::Error(e)) => return Err(e.into()),
+            }
+        }
+    }
+
+    // Writes to a sibling `.tmp` file and renames it into place so a killed
+    // process never leaves a truncated state file behind.
+    fn write_atomic(path: &Path, data: &str) -> Result<()> {
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let tmp_path = path.with_file_name(format!("{file_name}.tmp"));
+
+        fs::write(&tmp_path, data)?;
+
+        if fs::rename(&tmp_path, path).is_err() {
+            fs::copy(&tmp_path, path)?;
+            fs::remove_file(&tmp_path)?;
+        }
+        Ok(())
+    }
+
+    fn txn_dir(&self) -> PathBuf {
+        self.dir().join(".txn")
+    }
+
+    /// Runs `f` and applies every write it made, or none of them if it fails.
+    ///
+    /// The new files are staged in `state/.txn/` and a `COMMIT` marker is
+    /// written once they are all in place. Only then are they renamed over
+    /// the live files; if the process dies during the renames, the next
+    /// `lock` or transaction finishes them. A staging directory without the
+    /// marker is discarded.
+    pub fn transaction<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut StateTransaction) -> Result<()>,
+    {
+        self.recover_transaction()?;
+
+        let mut txn = StateTransaction::default();
+        f(&mut txn)?;
+        if txn.writes.is_empty() {
+            return Ok(());
+        }
+
+        let txn_dir = self.txn_dir();
+        fs::create_dir_all(&txn_dir)?;
+        for (name, data) in &txn.writes {
+            fs::write(txn_dir.join(name), data)?;
+            fs::write(txn_dir.join(format!("{name}.version")), VELLUM_VERSION)?;
+        }
+        fs::write(txn_dir.join("COMMIT"), "")?;
+
+        self.recover_transaction()
+    }
+
+    // Rolls a committed transaction forward, or drops an uncommitted one.
+    fn recover_transaction(&self) -> Result<()> {
+        let txn_dir = self.txn_dir();
+        if !txn_dir.exists() {
+            return Ok(());
+        }
+
+        if txn_dir.join("COMMIT").exists() {
+            for entry in fs::read_dir(&txn_dir)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                if name == "COMMIT" {
+                    continue;
+                }
+                fs::rename(entry.path(), self.dir().join(name))?;
+            }
+        }
+        fs::remove_dir_all(&txn_dir)?;
+        Ok(())
+    }
+
+    /// Checks that the state files which exist are well-formed.
+    pub fn validate(&self) -> Result<(), Vec<StateError>> {
+        let mut errors = Vec::new();
+
+        match self.read_if_exists("osver") {
+            Ok(Some(data)) if !is_os_version(data.trim()) => {
+                errors.push(StateError::InvalidOsVersion(data));
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(StateError::Unreadable("osver", e.to_string())),
+        }
+
+        match self.read_if_exists("device") {
+            Ok(Some(data)) if !is_device(data.trim()) => {
+                errors.push(StateError::InvalidDevice(data));
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(StateError::Unreadable("device", e.to_string())),
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Moves each state file that fails validation aside to `<name>.corrupt`,
+    /// so it is regenerated as if vellum were running for the first time.
+    pub fn repair(&self) -> Result<()> {
+        let Err(errors) = self.validate() else {
+            return Ok(());
+        };
+        for error in errors {
+            let path = self.dir().join(error.file_name());
+            let backup = self.dir().join(format!("{}.corrupt", error.file_name()));
+            if fs::rename(&path, &backup).is_err() {
+                fs::copy(&path, &backup)?;
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_if_exists(&self, name: &str) -> std::io::Result<Option<String>> {
+        let path = self.dir().join(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        fs::read_to_string(path).map(Some)
+    }
+
     pub fn get_os_version(&self) -> Result<String> {
         let data = fs::read_to_string(self.dir().join("osver"))?;
         Ok(data.trim().to_string())
     }
 
     pub fn set_os_version(&self, version: &str) -> Result<()> {
-        fs::create_dir_all(self.dir())?;
-        fs::write(self.dir().join("osver"), version)?;
-        Ok(())
+        self.write_with_version("osver", version)
     }
 
     pub fn get_device(&self) -> Result<String> {
@@ -34,9 +409,723 @@ impl State {
         Ok(data.trim().to_string())
     }
 
-    pub fn set_device(&self, device: &str) -> Result<()> {
+    /// The last remote repository an index was fetched from, kept so vellum
+    /// still knows where packages come from if the repositories file is lost.
+    pub fn get_repo_url(&self) -> Result<String> {
+        let data = fs::read_to_string(self.dir().join("repo-url"))?;
+        Ok(data.trim().to_string())
+    }
+
+    pub fn set_repo_url(&self, url: &str) -> Result<()> {
+        self.write_with_version("repo-url", url)
+    }
+
+    /// Writes state file `key` along with a `<key>.version` sibling naming
+    /// the vellum version that wrote it.
+    pub fn write_with_version(&self, key: &str, value: &str) -> Result<()> {
+        fs::create_dir_all(self.dir())?;
+        Self::write_atomic(&self.dir().join(key), value)?;
+        Self::write_atomic(&self.dir().join(format!("{key}.version")), VELLUM_VERSION)
+    }
+
+    /// The value of state file `key` and the vellum version that wrote it,
+    /// which is `None` for files written before versions were recorded.
+    pub fn read_with_version(&self, key: &str) -> Result<(String, Option<String>)> {
+        let data = fs::read_to_string(self.dir().join(key))?;
+        let version = self
+            .read_if_exists(&format!("{key}.version"))?
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+        Ok((data.trim().to_string(), version))
+    }
+
+    pub fn list_manual(&self) -> Result<Vec<String>> {
+        let path = self.dir().join("manual.txt");
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let data = fs::read_to_string(path)?;
+        Ok(data
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    pub fn is_manual(&self, pkg: &str) -> Result<bool> {
+        Ok(self.list_manual()?.iter().any(|p| p == pkg))
+    }
+
+    pub fn set_manual(&self, pkg: &str) -> Result<()> {
+        let mut manual = self.list_manual()?;
+        if manual.iter().any(|p| p == pkg) {
+            return Ok(());
+        }
+        manual.push(pkg.to_string());
+        manual.sort();
+        self.write_manual(&manual)
+    }
+
+    /// Drops `pkg` from `manual.txt`, marking it as automatically installed.
+    /// Returns whether it was listed.
+    pub fn remove_manual(&self, pkg: &str) -> Result<bool> {
+        let mut manual = self.list_manual()?;
+        let before = manual.len();
+        manual.retain(|p| p != pkg);
+        if manual.len() == before {
+            return Ok(false);
+        }
+        self.write_manual(&manual)?;
+        Ok(true)
+    }
+
+    fn write_manual(&self, manual: &[String]) -> Result<()> {
         fs::create_dir_all(self.dir())?;
-        fs::write(self.dir().join("device"), device)?;
+        let content = if manual.is_empty() { String::new() } else { manual.join("\n") + "\n" };
+        Self::write_atomic(&self.dir().join("manual.txt"), &content)
+    }
+
+    /// Held packages and the version each is pinned to, from `held.txt`
+    /// (`name=version` per line).
+    pub fn list_held(&self) -> Result<Vec<(String, String)>> {
+        self.list_entries("held.txt")
+    }
+
+    pub fn set_held(&self, pkg: &str, version: &str) -> Result<()> {
+        self.set_entry("held.txt", pkg, version)
+    }
+
+    /// Returns whether `pkg` was held.
+    pub fn remove_held(&self, pkg: &str) -> Result<bool> {
+        self.remove_entry("held.txt", pkg)
+    }
+
+    /// Pinned packages and their versions, from `pinned.txt`. Unlike a hold,
+    /// a pin is set by the user to any version and only lifted by `unpin`.
+    pub fn list_pinned(&self) -> Result<Vec<(String, String)>> {
+        self.list_entries("pinned.txt")
+    }
+
+    pub fn set_pinned(&self, pkg: &str, version: &str) -> Result<()> {
+        self.set_entry("pinned.txt", pkg, version)
+    }
+
+    /// Returns whether `pkg` was pinned.
+    pub fn remove_pinned(&self, pkg: &str) -> Result<bool> {
+        self.remove_entry("pinned.txt", pkg)
+    }
+
+    /// When each package was installed with `vellum add`, from
+    /// `install-times.txt` (`name=<unix time>` per line).
+    pub fn list_install_times(&self) -> Result<Vec<(String, SystemTime)>> {
+        Ok(self
+            .list_entries("install-times.txt")?
+            .into_iter()
+            .filter_map(|(name, secs)| {
+                let secs = secs.parse().ok()?;
+                Some((name, UNIX_EPOCH + Duration::from_secs(secs)))
+            })
+            .collect())
+    }
+
+    pub fn get_install_time(&self, pkg: &str) -> Result<Option<SystemTime>> {
+        let times = self.list_install_times()?;
+        Ok(times.into_iter().find(|(name, _)| name == pkg).map(|(_, t)| t))
+    }
+
+    pub fn set_install_time(&self, pkg: &str, time: SystemTime) -> Result<()> {
+        let secs = time.duration_since(UNIX_EPOCH)?.as_secs();
+        self.set_entry("install-times.txt", pkg, &secs.to_string())
+    }
+
+    /// Returns whether `pkg` had an install time.
+    pub fn remove_install_time(&self, pkg: &str) -> Result<bool> {
+        self.remove_entry("install-times.txt", pkg)
+    }
+
+    // Files of `name=value` lines, kept sorted by name.
+    fn list_entries(&self, file: &str) -> Result<Vec<(String, String)>> {
+        let Some(data) = self.read_if_exists(file)? else {
+            return Ok(Vec::new());
+        };
+        Ok(data
+            .lines()
+            .filter_map(|l| l.trim().split_once('='))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect())
+    }
+
+    fn set_entry(&self, file: &str, pkg: &str, value: &str) -> Result<()> {
+        let mut entries = self.list_entries(file)?;
+        entries.retain(|(name, _)| name != pkg);
+        entries.push((pkg.to_string(), value.to_string()));
+        self.write_entries(file, entries)
+    }
+
+    fn remove_entry(&self, file: &str, pkg: &str) -> Result<bool> {
+        let mut entries = self.list_entries(file)?;
+        let before = entries.len();
+        entries.retain(|(name, _)| name != pkg);
+        if entries.len() == before {
+            return Ok(false);
+        }
+        self.write_entries(file, entries)?;
+        Ok(true)
+    }
+
+    fn write_entries(&self, file: &str, mut entries: Vec<(String, String)>) -> Result<()> {
+        entries.sort();
+        let data: String = entries.iter().map(|(name, val)| format!("{name}={val}\n")).collect();
+
+        fs::create_dir_all(self.dir())?;
+        Self::write_atomic(&self.dir().join(file), &data)
+    }
+
+    /// Packages `vellum upgrade --skip` should leave alone until an upgrade
+    /// goes through.
+    pub fn list_skip_once(&self) -> Result<Vec<String>> {
+        let path = self.dir().join("skip-once.txt");
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let data = fs::read_to_string(path)?;
+        Ok(data
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    pub fn set_skip_once(&self, packages: &[String]) -> Result<()> {
+        let mut packages = packages.to_vec();
+        packages.sort();
+        packages.dedup();
+
+        fs::create_dir_all(self.dir())?;
+        Self::write_atomic(&self.dir().join("skip-once.txt"), &(packages.join("\n") + "\n"))
+    }
+
+    pub fn clear_skip_once(&self) -> Result<()> {
+        match fs::remove_file(self.dir().join("skip-once.txt")) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces `reenable-last-run.log` with the output of the latest
+    /// `vellum reenable`.
+    pub fn write_reenable_log(&self, log: &str) -> Result<()> {
+        fs::create_dir_all(self.dir())?;
+        Self::write_atomic(&self.dir().join("reenable-last-run.log"), log)
+    }
+
+    pub fn append_install_event(&self, op: &str, packages: &[String], os: &str) -> Result<()> {
+        fs::create_dir_all(self.dir())?;
+        let event = InstallEvent {
+            ts: format_rfc3339(now_unix()),
+            op: op.to_string(),
+            packages: packages.to_vec(),
+            os: os.to_string(),
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir().join("install-log.jsonl"))?;
+        writeln!(file, "{}", event.to_json())?;
         Ok(())
     }
+
+    pub fn get_install_log(&self) -> Result<Vec<InstallEvent>> {
+        let path = self.dir().join("install-log.jsonl");
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let data = fs::read_to_string(path)?;
+        Ok(data.lines().filter_map(InstallEvent::from_json).collect())
+    }
+
+    pub fn set_last_upgrade_report(&self, report: &UpgradeReport) -> Result<()> {
+        fs::create_dir_all(self.dir())?;
+        Self::write_atomic(&self.dir().join("last-upgrade.json"), &report.to_json())
+    }
+
+    /// The report of the last successful upgrade, or `None` if there hasn't
+    /// been one yet.
+    pub fn get_last_upgrade_report(&self) -> Result<Option<UpgradeReport>> {
+        let Some(data) = self.read_if_exists("last-upgrade.json")? else {
+            return Ok(None);
+        };
+        UpgradeReport::from_json(&data)
+            .map(Some)
+            .ok_or_else(|| anyhow!("last-upgrade.json is malformed"))
+    }
+
+    /// Records the installed packages as `snapshot-<unix time>.txt`, one
+    /// `name=version` per line. Only the newest snapshots are kept.
+    pub fn write_snapshot(&self, packages: &[(String, String)]) -> Result<()> {
+        fs::create_dir_all(self.dir())?;
+        let content: String = packages
+            .iter()
+            .map(|(name, version)| format!("{name}={version}\n"))
+            .collect();
+        let path = self.dir().join(format!("snapshot-{}.txt", now_unix()));
+        Self::write_atomic(&path, &content)?;
+
+        let snapshots = self.list_snapshots()?;
+        if snapshots.len() > MAX_SNAPSHOTS {
+            for ts in &snapshots[..snapshots.len() - MAX_SNAPSHOTS] {
+                let _ = fs::remove_file(self.dir().join(format!("snapshot-{ts}.txt")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Timestamps of the stored snapshots, oldest first.
+    pub fn list_snapshots(&self) -> Result<Vec<u64>> {
+        let entries = match fs::read_dir(self.dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut snapshots: Vec<u64> = entries
+            .flatten()
+            .filter_map(|e| {
+                let name = e.file_name().into_string().ok()?;
+                name.strip_prefix("snapshot-")?.strip_suffix(".txt")?.parse().ok()
+            })
+            .collect();
+        snapshots.sort_unstable();
+        Ok(snapshots)
+    }
+
+    pub fn read_snapshot(&self, ts: u64) -> Result<Vec<(String, String)>> {
+        let data = fs::read_to_string(self.dir().join(format!("snapshot-{ts}.txt")))?;
+        Ok(data
+            .lines()
+            .filter_map(|l| l.trim().split_once('='))
+            .map(|(name, version)| (name.to_string(), version.to_string()))
+            .collect())
+    }
+}
+
+// reMarkable OS versions are always four dot-separated numbers.
+fn is_os_version(s: &str) -> bool {
+    let parts: Vec<&str> = s.split('.').collect();
+    parts.len() == 4
+        && parts
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn is_device(s: &str) -> bool {
+    s == "unknown" || DEVICE_TYPES.contains(&s)
+}
+
+#[cfg(test)]
+impl State {
+    /// A `State` rooted at `tmp_dir`, with its `state` directory created.
+    pub fn new_for_testing(tmp_dir: &Path) -> State {
+        fs::create_dir_all(tmp_dir.join("state")).unwrap();
+        State::new(tmp_dir.to_str().unwrap())
+    }
+
+    /// A `State` in a fresh temporary directory, which is removed when the
+    /// returned `TempDir` is dropped.
+    pub fn new_ephemeral() -> (State, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        (State::new_for_testing(dir.path()), dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_device(state: &State, device: &str) {
+        state
+            .transaction(|txn| {
+                txn.set_device(device);
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn write_atomic_replaces_existing_file_completely() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let path = root.join("osver");
+        fs::write(&path, "3.10.0.0-with-a-long-trailing-value").unwrap();
+
+        State::write_atomic(&path, "3.11.0.0").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "3.11.0.0");
+    }
+
+    #[test]
+    fn write_atomic_leaves_no_temp_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let path = root.join("device");
+
+        State::write_atomic(&path, "rmpp").unwrap();
+
+        assert!(!root.join("device.tmp").exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "rmpp");
+    }
+
+    #[test]
+    fn write_atomic_ignores_stale_temp_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let path = root.join("osver");
+        fs::write(root.join("osver.tmp"), "partial garbage from a killed write").unwrap();
+
+        State::write_atomic(&path, "3.12.0.0").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "3.12.0.0");
+    }
+
+    #[test]
+    fn transaction_applies_all_writes() {
+        let (state, tmp) = State::new_ephemeral();
+        let root = tmp.path();
+
+        state
+            .transaction(|txn| {
+                txn.set_os_version("3.10.0.0");
+                txn.set_device("rmpp");
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(state.get_os_version().unwrap(), "3.10.0.0");
+        assert_eq!(state.get_device().unwrap(), "rmpp");
+        assert!(!root.join("state/.txn").exists());
+    }
+
+    #[test]
+    fn transaction_abort_leaves_state_untouched() {
+        let (state, tmp) = State::new_ephemeral();
+        let root = tmp.path();
+        state.set_os_version("3.9.0.0").unwrap();
+
+        let result = state.transaction(|txn| {
+            txn.set_os_version("3.10.0.0");
+            Err(anyhow!("interrupted"))?;
+            txn.set_device("rmpp");
+            Ok(())
+        });
+
+        assert!(result.is_err());
+        assert_eq!(state.get_os_version().unwrap(), "3.9.0.0");
+        assert!(state.get_device().is_err());
+        assert!(!root.join("state/.txn").exists());
+    }
+
+    #[test]
+    fn uncommitted_staging_is_discarded() {
+        let (state, tmp) = State::new_ephemeral();
+        let root = tmp.path();
+        let txn_dir = root.join("state/.txn");
+        fs::create_dir_all(&txn_dir).unwrap();
+        fs::write(txn_dir.join("osver"), "3.10.0.0").unwrap();
+
+        let _lock = state.lock().unwrap();
+
+        assert!(state.get_os_version().is_err());
+        assert!(!txn_dir.exists());
+    }
+
+    #[test]
+    fn committed_staging_is_rolled_forward() {
+        let (state, tmp) = State::new_ephemeral();
+        let root = tmp.path();
+        let txn_dir = root.join("state/.txn");
+        fs::create_dir_all(&txn_dir).unwrap();
+        fs::write(txn_dir.join("osver"), "3.10.0.0").unwrap();
+        fs::write(txn_dir.join("device"), "rm2").unwrap();
+        fs::write(txn_dir.join("COMMIT"), "").unwrap();
+
+        let _lock = state.lock().unwrap();
+
+        assert_eq!(state.get_os_version().unwrap(), "3.10.0.0");
+        assert_eq!(state.get_device().unwrap(), "rm2");
+        assert!(!txn_dir.exists());
+    }
+
+    #[test]
+    fn set_and_get_round_trip() {
+        let (state, _tmp) = State::new_ephemeral();
+
+        state.set_os_version("3.10.0.0").unwrap();
+        write_device(&state, "rm2");
+        state.set_repo_url("https://packages.vellum.delivery").unwrap();
+
+        assert_eq!(state.get_os_version().unwrap(), "3.10.0.0");
+        assert_eq!(state.get_device().unwrap(), "rm2");
+        assert_eq!(state.get_repo_url().unwrap(), "https://packages.vellum.delivery");
+    }
+
+    #[test]
+    fn writer_version_recorded() {
+        let (state, tmp) = State::new_ephemeral();
+        let root = tmp.path();
+
+        state.write_with_version("repo-url", "https://example.com").unwrap();
+        write_device(&state, "rm2");
+        fs::write(root.join("state/osver"), "3.10.0.0").unwrap();
+
+        let (url, version) = state.read_with_version("repo-url").unwrap();
+        assert_eq!(url, "https://example.com");
+        assert_eq!(version.as_deref(), Some(VELLUM_VERSION));
+        let (_, version) = state.read_with_version("device").unwrap();
+        assert_eq!(version.as_deref(), Some(VELLUM_VERSION));
+        assert_eq!(state.read_with_version("osver").unwrap().1, None);
+    }
+
+    #[test]
+    fn install_log_round_trip() {
+        let (state, _tmp) = State::new_ephemeral();
+
+        state
+            .append_install_event("add", &["foo".to_string(), "bar".to_string()], "3.10.0.0")
+            .unwrap();
+        state.append_install_event("del", &["foo".to_string()], "3.10.0.0").unwrap();
+
+        let log = state.get_install_log().unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].op, "add");
+        assert_eq!(log[0].packages, vec!["foo", "bar"]);
+        assert_eq!(log[0].os, "3.10.0.0");
+        assert!(log[0].ts.ends_with('Z'));
+        assert_eq!(log[1].op, "del");
+    }
+
+    #[test]
+    fn upgrade_report_round_trip() {
+        let (state, _tmp) = State::new_ephemeral();
+        assert_eq!(state.get_last_upgrade_report().unwrap(), None);
+
+        let report = UpgradeReport {
+            op: "upgrade".to_string(),
+            timestamp: "2024-01-31T00:00:00Z".to_string(),
+            os_before: "3.9.0.0".to_string(),
+            os_after: "3.10.0.0".to_string(),
+            packages_upgraded: vec![
+                UpgradedPackage {
+                    name: "foo".to_string(),
+                    from_version: "1.0-r0".to_string(),
+                    to_version: "1.1-r0".to_string(),
+                    installed_at: Some("2023-12-01T08:00:00Z".to_string()),
+                },
+                UpgradedPackage {
+                    name: "bar".to_string(),
+                    from_version: "2.0-r0".to_string(),
+                    to_version: "2.0-r1".to_string(),
+                    installed_at: None,
+                },
+            ],
+            hooks_run: vec!["10-foo".to_string(), "20-bar".to_string()],
+            hooks_failed: vec![("20-bar".to_string(), 2)],
+            duration_secs: 42,
+        };
+        state.set_last_upgrade_report(&report).unwrap();
+
+        assert_eq!(state.get_last_upgrade_report().unwrap(), Some(report));
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let (state, _tmp) = State::new_ephemeral();
+        assert!(state.list_snapshots().unwrap().is_empty());
+
+        let packages = vec![
+            ("foo".to_string(), "1.0-r0".to_string()),
+            ("bar".to_string(), "2.1-r3".to_string()),
+        ];
+        state.write_snapshot(&packages).unwrap();
+
+        let snapshots = state.list_snapshots().unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(state.read_snapshot(snapshots[0]).unwrap(), packages);
+    }
+
+    #[test]
+    fn snapshots_are_pruned_oldest_first() {
+        let (state, tmp) = State::new_ephemeral();
+        let root = tmp.path();
+        let dir = root.join("state");
+        fs::create_dir_all(&dir).unwrap();
+        for ts in 1..=MAX_SNAPSHOTS as u64 {
+            fs::write(dir.join(format!("snapshot-{ts}.txt")), "").unwrap();
+        }
+
+        state.write_snapshot(&[]).unwrap();
+
+        let snapshots = state.list_snapshots().unwrap();
+        assert_eq!(snapshots.len(), MAX_SNAPSHOTS);
+        assert_eq!(snapshots[0], 2);
+    }
+
+    #[test]
+    fn install_log_missing_file_is_empty() {
+        let (state, _tmp) = State::new_ephemeral();
+
+        assert!(state.get_install_log().unwrap().is_empty());
+    }
+
+    #[test]
+    fn install_log_skips_corrupt_lines() {
+        let (state, tmp) = State::new_ephemeral();
+        let root = tmp.path();
+        state.append_install_event("add", &["foo".to_string()], "3.10.0.0").unwrap();
+        let path = root.join("state").join("install-log.jsonl");
+        let mut data = fs::read_to_string(&path).unwrap();
+        data.push_str("{\"ts\":\"2024-01-01T00:00:00Z\",\"op\":\"ad");
+        fs::write(&path, data).unwrap();
+
+        let log = state.get_install_log().unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].packages, vec!["foo"]);
+    }
+
+    #[test]
+    fn manual_packages_tracked() {
+        let (state, _tmp) = State::new_ephemeral();
+
+        assert!(state.list_manual().unwrap().is_empty());
+        state.set_manual("foo").unwrap();
+        state.set_manual("bar").unwrap();
+        state.set_manual("foo").unwrap();
+
+        assert_eq!(state.list_manual().unwrap(), vec!["bar", "foo"]);
+        assert!(state.is_manual("foo").unwrap());
+        assert!(!state.is_manual("baz").unwrap());
+
+        assert!(state.remove_manual("foo").unwrap());
+        assert!(!state.remove_manual("foo").unwrap());
+        assert_eq!(state.list_manual().unwrap(), vec!["bar"]);
+    }
+
+    #[test]
+    fn lock_is_exclusive_until_dropped() {
+        let (state, _tmp) = State::new_ephemeral();
+
+        let lock = state.lock_with_timeout(Duration::ZERO).unwrap();
+        let err = state.lock_with_timeout(Duration::from_millis(200)).err().unwrap();
+        assert!(err.to_string().contains("in progress"));
+
+        drop(lock);
+        assert!(state.lock_with_timeout(Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn validate_accepts_good_and_missing_files() {
+        let (state, _tmp) = State::new_ephemeral();
+        assert!(state.validate().is_ok());
+
+        state.set_os_version("3.10.2.3").unwrap();
+        write_device(&state, "rmpp");
+        assert!(state.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_reports_corrupt_files() {
+        let (state, _tmp) = State::new_ephemeral();
+        state.set_os_version("   ").unwrap();
+        write_device(&state, "rm3");
+
+        let errors = state.validate().unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                StateError::InvalidOsVersion("   ".to_string()),
+                StateError::InvalidDevice("rm3".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn repair_backs_up_corrupt_files() {
+        let (state, tmp) = State::new_ephemeral();
+        let root = tmp.path();
+        state.set_os_version("3.10\u{0}\u{1}").unwrap();
+        write_device(&state, "rm2");
+
+        state.repair().unwrap();
+
+        let dir = root.join("state");
+        assert!(!dir.join("osver").exists());
+        assert_eq!(fs::read_to_string(dir.join("osver.corrupt")).unwrap(), "3.10\u{0}\u{1}");
+        assert_eq!(state.get_device().unwrap(), "rm2");
+        assert!(state.validate().is_ok());
+    }
+
+    #[test]
+    fn skip_once_round_trip() {
+        let (state, _tmp) = State::new_ephemeral();
+
+        assert!(state.list_skip_once().unwrap().is_empty());
+        state
+            .set_skip_once(&["foo".to_string(), "bar".to_string(), "foo".to_string()])
+            .unwrap();
+        assert_eq!(state.list_skip_once().unwrap(), vec!["bar", "foo"]);
+
+        state.clear_skip_once().unwrap();
+        assert!(state.list_skip_once().unwrap().is_empty());
+        state.clear_skip_once().unwrap();
+    }
+
+    #[test]
+    fn held_packages_tracked() {
+        let (state, _tmp) = State::new_ephemeral();
+
+        assert!(state.list_held().unwrap().is_empty());
+        state.set_held("foo", "1.0-r0").unwrap();
+        state.set_held("bar", "2.0-r1").unwrap();
+        state.set_held("foo", "1.1-r0").unwrap();
+
+        assert_eq!(
+            state.list_held().unwrap(),
+            vec![
+                ("bar".to_string(), "2.0-r1".to_string()),
+                ("foo".to_string(), "1.1-r0".to_string())
+            ]
+        );
+
+        assert!(state.remove_held("foo").unwrap());
+        assert!(!state.remove_held("foo").unwrap());
+        assert_eq!(state.list_held().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn pinned_packages_tracked_apart_from_holds() {
+        let (state, _tmp) = State::new_ephemeral();
+
+        state.set_held("foo", "1.0-r0").unwrap();
+        state.set_pinned("foo", "0.9-r0").unwrap();
+        state.set_pinned("foo", "0.9-r1").unwrap();
+
+        assert_eq!(state.list_pinned().unwrap(), vec![("foo".to_string(), "0.9-r1".to_string())]);
+        assert_eq!(state.list_held().unwrap(), vec![("foo".to_string(), "1.0-r0".to_string())]);
+        assert!(state.remove_pinned("foo").unwrap());
+        assert!(state.list_pinned().unwrap().is_empty());
+    }
+
+    #[test]
+    fn install_times_round_trip() {
+        let (state, _tmp) = State::new_ephemeral();
+        let time = UNIX_EPOCH + Duration::from_secs(1706659200);
+
+        assert_eq!(state.get_install_time("foo").unwrap(), None);
+        state.set_install_time("foo", time).unwrap();
+        assert_eq!(state.get_install_time("foo").unwrap(), Some(time));
+        assert!(state.remove_install_time("foo").unwrap());
+        assert!(!state.remove_install_time("foo").unwrap());
+        assert_eq!(state.get_install_time("foo").unwrap(), None);
+    }
 }
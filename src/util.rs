@@ -1,29 +1,452 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CString;
 use std::fs;
+use std::io;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn remove_glob(pattern: &str) {
+use anyhow::{anyhow, Result};
+
+/// Removes the files matching `pattern` and returns how many were removed.
+/// Every match is attempted; if any can't be removed, the error lists each
+/// failure. A missing directory matches nothing.
+pub fn remove_glob(pattern: &str) -> Result<usize> {
     let dir = Path::new(pattern).parent().unwrap_or(Path::new("."));
     let file_pattern = Path::new(pattern)
         .file_name()
         .and_then(|s| s.to_str())
         .unwrap_or("");
 
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            if let Some(name) = entry.file_name().to_str() {
-                if matches_glob(name, file_pattern) {
-                    let _ = fs::remove_file(entry.path());
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(0);
+    };
+
+    let mut removed = 0;
+    let mut failures = Vec::new();
+    for entry in entries.flatten() {
+        if let Some(name) = entry.file_name().to_str() {
+            if matches_glob(name, file_pattern) {
+                match fs::remove_file(entry.path()) {
+                    Ok(()) => removed += 1,
+                    Err(e) => failures.push(format!("{}: {e}", entry.path().display())),
                 }
             }
         }
     }
+
+    if failures.is_empty() {
+        Ok(removed)
+    } else {
+        Err(anyhow!("could not remove {}", failures.join(", ")))
+    }
 }
 
 pub fn matches_glob(name: &str, pattern: &str) -> bool {
-    if let Some(prefix) = pattern.strip_suffix("*.apk") {
-        name.starts_with(prefix) && name.ends_with(".apk")
+    if !pattern.contains(['?', '[']) && pattern.matches('*').count() == 1 {
+        if let Some((prefix, suffix)) = pattern.split_once('*') {
+            return name.len() >= prefix.len() + suffix.len()
+                && name.starts_with(prefix)
+                && name.ends_with(suffix);
+        }
+    }
+
+    let name: Vec<char> = name.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    glob_match(&name, &pattern)
+}
+
+// Iterative wildcard matcher: remembers the last `*` and backtracks to it,
+// letting it absorb one more character, whenever the rest fails to match.
+fn glob_match(name: &[char], pattern: &[char]) -> bool {
+    let mut n = 0;
+    let mut p = 0;
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() {
+            if pattern[p] == '*' {
+                star = Some((p, n));
+                p += 1;
+                continue;
+            }
+            if let Some(len) = match_one(&pattern[p..], name[n]) {
+                p += len;
+                n += 1;
+                continue;
+            }
+        }
+
+        match star {
+            Some((star_p, star_n)) => {
+                p = star_p + 1;
+                n = star_n + 1;
+                star = Some((star_p, star_n + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Matches a single pattern token (`?`, `[...]` or a literal) against `ch`,
+// returning the token length on success.
+fn match_one(pattern: &[char], ch: char) -> Option<usize> {
+    match pattern[0] {
+        '?' => Some(1),
+        '[' => match match_bracket(pattern, ch) {
+            Some((true, len)) => Some(len),
+            Some((false, _)) => None,
+            // An unterminated bracket is a literal `[`.
+            None => (ch == '[').then_some(1),
+        },
+        c => (c == ch).then_some(1),
+    }
+}
+
+// Parses a bracket expression starting at `pattern[0] == '['`. A `]` right
+// after the opening bracket (or negation) is literal, as is `^` anywhere but
+// the start. Returns whether `ch` is in the set and the expression length, or
+// `None` if the bracket is never closed.
+fn match_bracket(pattern: &[char], ch: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        let c = *pattern.get(i)?;
+        if c == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some('-'), Some(&end)) if end != ']' => {
+                matched |= c <= ch && ch <= end;
+                i += 3;
+            }
+            _ => {
+                matched |= c == ch;
+                i += 1;
+            }
+        }
+        first = false;
+    }
+}
+
+/// Builds an HTTP agent for `url`, going through `https_proxy` (or
+/// `http_proxy`) unless the host is listed in `no_proxy`. Setting
+/// `VELLUM_NO_PROXY_CHECK=1` skips the `no_proxy` check.
+pub fn http_agent(url: &str) -> Result<ureq::Agent> {
+    let proxy = ["https_proxy", "HTTPS_PROXY", "http_proxy", "HTTP_PROXY"]
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|v| !v.is_empty()));
+
+    let Some(proxy) = proxy else {
+        return Ok(ureq::Agent::new());
+    };
+
+    if env::var("VELLUM_NO_PROXY_CHECK").as_deref() != Ok("1") {
+        let no_proxy = env::var("no_proxy")
+            .or_else(|_| env::var("NO_PROXY"))
+            .unwrap_or_default();
+        if no_proxy_matches(url_host(url), &no_proxy) {
+            return Ok(ureq::Agent::new());
+        }
+    }
+
+    let proxy = ureq::Proxy::new(&proxy).map_err(|e| anyhow!("invalid proxy {proxy}: {e}"))?;
+    Ok(ureq::AgentBuilder::new().proxy(proxy).build())
+}
+
+// Extracts the host from `scheme://user@host:port/path`.
+fn url_host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, r)| r);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    match host_port.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or(""),
+        None => host_port.split(':').next().unwrap_or(""),
+    }
+}
+
+// `no_proxy` is a comma-separated list of hosts or domain suffixes (with or
+// without a leading dot), optionally with a port; `*` disables the proxy.
+fn no_proxy_matches(host: &str, no_proxy: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().to_ascii_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            if entry == "*" {
+                return true;
+            }
+            let entry = entry.split(':').next().unwrap_or("");
+            let domain = entry.trim_start_matches('.');
+            host == domain || host.ends_with(&format!(".{domain}"))
+        })
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+pub fn available_space(path: &str) -> io::Result<u64> {
+    let c_path = CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Total size of the regular files under `path`. Symlinks are not followed
+/// and unreadable entries are skipped.
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return if meta.is_file() { meta.len() } else { 0 };
+    }
+    fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|e| dir_size(&e.path())).sum())
+        .unwrap_or(0)
+}
+
+/// Width of the terminal on stdout, or `None` when stdout isn't a terminal.
+pub fn terminal_columns() -> Option<u16> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 {
+        return None;
+    }
+    (size.ws_col > 0).then_some(size.ws_col)
+}
+
+/// The line ending `content` uses, judged by its first line, so a file can be
+/// written back the way it was read.
+pub fn line_ending(content: &str) -> &'static str {
+    match content.split_once('\n') {
+        Some((first, _)) if first.ends_with('\r') => "\r\n",
+        _ => "\n",
+    }
+}
+
+pub fn format_mb(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+pub fn now_unix() -> u64 {
+    unix_secs(SystemTime::now())
+}
+
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// Formats a Unix timestamp as an RFC3339 UTC string (`2024-01-31T12:00:00Z`).
+pub fn format_rfc3339(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub fn json_string_array(items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|s| json_string(s)).collect();
+    format!("[{}]", items.join(","))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonField {
+    Str(String),
+    Num(i64),
+    StrArray(Vec<String>),
+    ObjArray(Vec<HashMap<String, JsonField>>),
+}
+
+// Parses a JSON object whose values are strings, integers, or arrays of
+// strings or of such objects, which is all vellum's own state files ever
+// contain. An empty array is a `StrArray`.
+pub fn parse_json_object(input: &str) -> Option<HashMap<String, JsonField>> {
+    let mut chars = input.trim().chars().peekable();
+    let fields = parse_json_object_from(&mut chars)?;
+
+    skip_ws(&mut chars);
+    if chars.next().is_some() {
+        return None;
+    }
+    Some(fields)
+}
+
+/// Parses a JSON array of objects in the same restricted form as
+/// `parse_json_object`.
+pub fn parse_json_object_array(input: &str) -> Option<Vec<HashMap<String, JsonField>>> {
+    let mut chars = input.trim().chars().peekable();
+    let mut objects = Vec::new();
+
+    if chars.next()? != '[' {
+        return None;
+    }
+    loop {
+        skip_ws(&mut chars);
+        match chars.peek()? {
+            ']' => {
+                chars.next();
+                break;
+            }
+            ',' => {
+                chars.next();
+            }
+            _ => objects.push(parse_json_object_from(&mut chars)?),
+        }
+    }
+
+    skip_ws(&mut chars);
+    if chars.next().is_some() {
+        return None;
+    }
+    Some(objects)
+}
+
+fn parse_json_object_from(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+) -> Option<HashMap<String, JsonField>> {
+    let mut fields = HashMap::new();
+
+    if chars.next()? != '{' {
+        return None;
+    }
+
+    loop {
+        skip_ws(chars);
+        match chars.peek()? {
+            '}' => {
+                chars.next();
+                break;
+            }
+            ',' => {
+                chars.next();
+                continue;
+            }
+            _ => {}
+        }
+
+        let key = parse_json_str(chars)?;
+        skip_ws(chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_ws(chars);
+
+        let value = match *chars.peek()? {
+            '[' => parse_json_array(chars)?,
+            '"' => JsonField::Str(parse_json_str(chars)?),
+            _ => {
+                let mut num = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '-') {
+                    num.push(c);
+                }
+                JsonField::Num(num.parse().ok()?)
+            }
+        };
+
+        fields.insert(key, value);
+    }
+    Some(fields)
+}
+
+fn parse_json_array(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<JsonField> {
+    chars.next();
+    let mut strings = Vec::new();
+    let mut objects = Vec::new();
+    loop {
+        skip_ws(chars);
+        match chars.peek()? {
+            ']' => {
+                chars.next();
+                break;
+            }
+            ',' => {
+                chars.next();
+            }
+            '{' if strings.is_empty() => objects.push(parse_json_object_from(chars)?),
+            _ if objects.is_empty() => strings.push(parse_json_str(chars)?),
+            _ => return None,
+        }
+    }
+    if objects.is_empty() {
+        Some(JsonField::StrArray(strings))
     } else {
-        name == pattern
+        Some(JsonField::ObjArray(objects))
+    }
+}
+
+fn skip_ws(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn parse_json_str(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    if chars.next()? != '"' {
+        return None;
+    }
+
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                'r' => out.push('\r'),
+                't' => out.push('\t'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    out.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                c => out.push(c),
+            },
+            c => out.push(c),
+        }
     }
 }
 
@@ -31,6 +454,28 @@ pub fn matches_glob(name: &str, pattern: &str) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn remove_glob_counts_removed_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for name in ["remarkable-os-1.apk", "remarkable-os-2.apk", "other-1.apk"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let pattern = format!("{}/remarkable-os-*.apk", dir.display());
+        assert_eq!(remove_glob(&pattern).unwrap(), 2);
+        assert_eq!(remove_glob(&pattern).unwrap(), 0);
+        assert!(dir.join("other-1.apk").exists());
+    }
+
+    #[test]
+    fn line_ending_follows_first_line() {
+        assert_eq!(line_ending("foo\r\nbar\r\n"), "\r\n");
+        assert_eq!(line_ending("foo\nbar\r\n"), "\n");
+        assert_eq!(line_ending("foo"), "\n");
+        assert_eq!(line_ending(""), "\n");
+    }
+
     #[test]
     fn matches_glob_exact_match() {
         assert!(matches_glob("foo.apk", "foo.apk"));
@@ -62,4 +507,200 @@ mod tests {
         assert!(matches_glob("anything.apk", "*.apk"));
         assert!(matches_glob(".apk", "*.apk"));
     }
+
+    #[test]
+    fn matches_glob_question_mark() {
+        assert!(matches_glob("rm1-1.0.apk", "rm?-1.0.apk"));
+        assert!(matches_glob("rm2-1.0.apk", "rm?-1.0.apk"));
+        assert!(!matches_glob("rmpp-1.0.apk", "rm?-1.0.apk"));
+        assert!(!matches_glob("rm-1.0.apk", "rm?-1.0.apk"));
+    }
+
+    #[test]
+    fn matches_glob_bracket_set_and_range() {
+        assert!(matches_glob("rm1.apk", "rm[12].apk"));
+        assert!(matches_glob("rm2.apk", "rm[12].apk"));
+        assert!(!matches_glob("rm3.apk", "rm[12].apk"));
+        assert!(matches_glob("pkg-b.apk", "pkg-[a-z].apk"));
+        assert!(!matches_glob("pkg-B.apk", "pkg-[a-z].apk"));
+        assert!(matches_glob("v7", "v[0-9]"));
+    }
+
+    #[test]
+    fn matches_glob_bracket_negation() {
+        assert!(matches_glob("rm3.apk", "rm[!12].apk"));
+        assert!(!matches_glob("rm1.apk", "rm[!12].apk"));
+        assert!(matches_glob("rm3.apk", "rm[^12].apk"));
+        assert!(!matches_glob("rm2.apk", "rm[^12].apk"));
+    }
+
+    #[test]
+    fn matches_glob_empty_brackets_are_literal() {
+        assert!(matches_glob("a[]", "a[]"));
+        assert!(!matches_glob("a", "a[]"));
+        assert!(matches_glob("[", "["));
+    }
+
+    #[test]
+    fn matches_glob_close_bracket_first_is_literal() {
+        assert!(matches_glob("]", "[]]"));
+        assert!(matches_glob("a", "[]a]"));
+        assert!(matches_glob("]", "[]a]"));
+        assert!(!matches_glob("b", "[]a]"));
+        assert!(matches_glob("b", "[!]a]"));
+        assert!(!matches_glob("]", "[!]a]"));
+    }
+
+    #[test]
+    fn matches_glob_caret_not_at_start_is_literal() {
+        assert!(matches_glob("^", "[a^]"));
+        assert!(matches_glob("a", "[a^]"));
+        assert!(!matches_glob("b", "[a^]"));
+    }
+
+    #[test]
+    fn matches_glob_dash_at_edges_is_literal() {
+        assert!(matches_glob("-", "[a-]"));
+        assert!(matches_glob("-", "[-a]"));
+        assert!(!matches_glob("b", "[a-]"));
+    }
+
+    #[test]
+    fn matches_glob_adjacent_wildcards() {
+        assert!(matches_glob("foo.apk", "**.apk"));
+        assert!(matches_glob("foo.apk", "f*?*.apk"));
+        assert!(!matches_glob("f.apk", "f*?*x.apk"));
+        assert!(matches_glob("", "**"));
+    }
+
+    #[test]
+    fn matches_glob_multiple_stars_backtrack() {
+        assert!(matches_glob("remarkable-os-3.10.0.0-r0.apk", "remarkable-*-*-r0.apk"));
+        assert!(matches_glob("abcabcabd", "*abd"));
+        assert!(!matches_glob("abcabcabc", "*abd*x"));
+    }
+
+    #[test]
+    fn matches_glob_pattern_longer_than_input() {
+        assert!(!matches_glob("a", "a?"));
+        assert!(!matches_glob("a", "a[bc]"));
+        assert!(!matches_glob("ab", "ab*cd"));
+        assert!(!matches_glob("", "?"));
+    }
+
+    #[test]
+    fn format_rfc3339_epoch() {
+        assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn format_rfc3339_known_dates() {
+        assert_eq!(format_rfc3339(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_rfc3339(1700000000), "2023-11-14T22:13:20Z");
+        assert_eq!(format_rfc3339(1735689599), "2024-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn json_string_escapes() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("line\nbreak"), "\"line\\nbreak\"");
+        assert_eq!(json_string("\u{1}"), "\"\\u0001\"");
+    }
+
+    #[test]
+    fn json_string_array_formats() {
+        assert_eq!(json_string_array(&[]), "[]");
+        assert_eq!(
+            json_string_array(&["a".to_string(), "b".to_string()]),
+            "[\"a\",\"b\"]"
+        );
+    }
+
+    #[test]
+    fn parse_json_object_round_trip() {
+        let line = format!(
+            "{{\"op\":{},\"packages\":{}}}",
+            json_string("add \"quoted\"\n"),
+            json_string_array(&["foo".to_string(), "bar".to_string()])
+        );
+        let fields = parse_json_object(&line).unwrap();
+
+        assert_eq!(fields["op"], JsonField::Str("add \"quoted\"\n".to_string()));
+        assert_eq!(
+            fields["packages"],
+            JsonField::StrArray(vec!["foo".to_string(), "bar".to_string()])
+        );
+    }
+
+    #[test]
+    fn parse_json_object_whitespace_and_empty_array() {
+        let fields = parse_json_object(" { \"a\" : \"1\" , \"b\" : [ ] } ").unwrap();
+
+        assert_eq!(fields["a"], JsonField::Str("1".to_string()));
+        assert_eq!(fields["b"], JsonField::StrArray(vec![]));
+    }
+
+    #[test]
+    fn parse_json_object_numbers_and_nested_objects() {
+        let fields =
+            parse_json_object(r#"{"n":-42,"list":[{"name":"a","code":1},{"name":"b"}]}"#)
+                .unwrap();
+
+        assert_eq!(fields["n"], JsonField::Num(-42));
+        let JsonField::ObjArray(list) = &fields["list"] else {
+            panic!("expected an array of objects");
+        };
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0]["code"], JsonField::Num(1));
+        assert_eq!(list[1]["name"], JsonField::Str("b".to_string()));
+    }
+
+    #[test]
+    fn parse_json_object_rejects_malformed() {
+        assert!(parse_json_object("{\"a\":[\"x\",{}]}").is_none());
+        assert!(parse_json_object("{\"a\":12x}").is_none());
+        assert!(parse_json_object("").is_none());
+        assert!(parse_json_object("{\"a\":\"1\"").is_none());
+        assert!(parse_json_object("{\"a\" \"1\"}").is_none());
+        assert!(parse_json_object("{\"a\":\"1\"} trailing").is_none());
+        assert!(parse_json_object("[1]").is_none());
+    }
+
+    #[test]
+    fn parse_json_object_array_parses_each_object() {
+        let objects =
+            parse_json_object_array(" [ {\"a\":\"1\"}, {\"a\":\"2\",\"b\":[\"x\"]} ] ").unwrap();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0]["a"], JsonField::Str("1".to_string()));
+        assert_eq!(objects[1]["b"], JsonField::StrArray(vec!["x".to_string()]));
+
+        assert_eq!(parse_json_object_array("[]").unwrap().len(), 0);
+        assert!(parse_json_object_array("{\"a\":\"1\"}").is_none());
+        assert!(parse_json_object_array("[{\"a\":\"1\"}").is_none());
+    }
+
+    #[test]
+    fn url_host_extracts_host() {
+        assert_eq!(
+            url_host("https://packages.vellum.delivery/testing"),
+            "packages.vellum.delivery"
+        );
+        assert_eq!(url_host("http://user:pw@example.com:8080/x"), "example.com");
+        assert_eq!(url_host("http://[::1]:3128/"), "::1");
+        assert_eq!(url_host("example.com"), "example.com");
+    }
+
+    #[test]
+    fn no_proxy_matching() {
+        let list = "localhost, .corp.example.com,internal.net:8080";
+        assert!(no_proxy_matches("localhost", list));
+        assert!(no_proxy_matches("repo.corp.example.com", list));
+        assert!(no_proxy_matches("corp.example.com", list));
+        assert!(no_proxy_matches("INTERNAL.net", list));
+        assert!(!no_proxy_matches("example.com", list));
+        assert!(!no_proxy_matches("notinternal.net", list));
+        assert!(no_proxy_matches("anything", "*"));
+        assert!(!no_proxy_matches("anything", ""));
+    }
 }